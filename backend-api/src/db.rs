@@ -3,9 +3,11 @@
 //! Handles MongoDB connection initialization and configuration.
 //! Requires the MONGODB_URI environment variable to be set.
 
-use mongodb::{Client, Database};
+use mongodb::{bson::doc, options::IndexOptions, Client, Database, IndexModel};
 use std::env;
 
+use crate::models::Category;
+
 /// Establishes a connection to MongoDB
 /// 
 /// # Environment Variables Required
@@ -44,3 +46,37 @@ pub async fn connect() -> Result<Database, mongodb::error::Error> {
     
     Ok(database)
 }
+
+/// Creates the indexes the application relies on, if they do not exist yet
+///
+/// Category names are unique regardless of case, and slugs are unique.
+/// Runs at startup after the migrations, which resolve duplicates left
+/// from before these indexes existed.
+///
+/// # Arguments
+///
+/// * `db` - MongoDB database connection
+pub async fn create_indexes(db: &Database) -> mongodb::error::Result<()> {
+    let categories = db.collection::<Category>("category");
+    categories.create_index(
+        IndexModel::builder()
+            .keys(doc! { "name": 1 })
+            .options(IndexOptions::builder()
+                .name("category_name_unique".to_string())
+                .unique(true)
+                .collation(Category::name_collation())
+                .build())
+            .build(),
+        None,
+    ).await?;
+    categories.create_index(
+        IndexModel::builder()
+            .keys(doc! { "slug": 1 })
+            .options(IndexOptions::builder().name("category_slug_unique".to_string()).unique(true).build())
+            .build(),
+        None,
+    ).await?;
+
+    println!("🗂️ Database indexes ready");
+    Ok(())
+}
@@ -1,41 +1,172 @@
 //! Category handling module
 //! 
 //! Provides functionality for:
-//! - Category creation
-//! - Category listing
+//! - Category creation, with unique names and slugs
+//! - Category listing and lookup by ID or slug
+//! - Category renaming and deletion
+//! - Per-category watermark opt-in
 
-use axum::{extract::State, Json};
+use axum::{extract::{Path, Query, State}, Json};
 use axum::http::StatusCode;
-use mongodb::Database;
+use mongodb::{
+    Collection, Database,
+    bson::{doc, oid::ObjectId, Document},
+    error::{ErrorKind, WriteFailure},
+    options::FindOneOptions,
+};
+use serde::{Serialize, Deserialize};
+use serde_json::json;
 use std::sync::Arc;
 use futures_util::TryStreamExt;
-use crate::models::Category;
+use crate::models::{Category, Model, Photo, Video};
+use crate::handlers::{
+    common::json_error,
+    models::remove_stored_model,
+    photos::remove_photo_files,
+    videos::remove_video_files,
+    watermark::rerender_photos,
+};
+
+/// Request body for toggling the watermark of a category
+#[derive(Debug, Deserialize)]
+pub struct CategoryWatermark {
+    /// Whether photos in the category should be watermarked
+    pub enabled: bool,
+}
+
+/// Request body for renaming a category
+#[derive(Debug, Deserialize)]
+pub struct CategoryRename {
+    pub name: String,
+}
+
+/// What happens to the media of a deleted category
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteStrategy {
+    /// Delete the category only if no media is in it
+    #[default]
+    Refuse,
+    /// Move the media to the `target` category
+    Reassign,
+    /// Delete the media along with the category
+    Cascade,
+}
+
+/// Query parameters of a category deletion
+#[derive(Debug, Deserialize)]
+pub struct DeleteCategoryQuery {
+    #[serde(default)]
+    pub strategy: DeleteStrategy,
+    /// ID of the category receiving the media when reassigning
+    pub target: Option<String>,
+}
+
+/// Query parameters of the photo, model and video listings
+#[derive(Debug, Deserialize)]
+pub struct CategoryQuery {
+    /// ID or slug of the category to list, e.g. `landscapes`; all media when absent
+    pub category: Option<String>,
+}
+
+impl CategoryQuery {
+    /// Builds the filter selecting the media of the requested category
+    ///
+    /// # Returns
+    /// `None` when no category was requested, or `NOT_FOUND` for an unknown one
+    pub(crate) async fn media_filter(&self, db: &Database) -> Result<Option<Document>, StatusCode> {
+        match &self.category {
+            Some(id) => {
+                let category = find_category(db, id).await?;
+                Ok(Some(doc! { "category_id": category.id }))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Media affected by a category deletion
+#[derive(Debug, Serialize)]
+pub struct CategoryDeletion {
+    pub strategy: DeleteStrategy,
+    pub photos: u64,
+    pub models: u64,
+    pub videos: u64,
+}
+
+/// MongoDB error code of writes violating a unique index
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Logs a database error and turns it into an `INTERNAL_SERVER_ERROR`
+fn database_error(e: mongodb::error::Error) -> (StatusCode, Json<serde_json::Value>) {
+    eprintln!("Failed to update categories: {}", e);
+    json_error(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+}
 
 /// Creates a new category
+///
+/// The name is trimmed and must be unique regardless of case. The slug is
+/// generated from the name; any slug in the request is ignored.
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
 /// * `category` - Category data to create
 /// 
 /// # Returns
-/// Returns the created category with its ID
+/// Returns the created category with its ID and slug, `BAD_REQUEST` for an
+/// invalid name or `CONFLICT` when the name is taken
 pub async fn create_category(
     State(db): State<Arc<Database>>,
-    Json(category): Json<Category>,
-) -> Result<Json<Category>, StatusCode> {
+    Json(mut category): Json<Category>,
+) -> Result<Json<Category>, (StatusCode, Json<serde_json::Value>)> {
     let collection = db.collection::<Category>("category");
-    
+    category.id = None;
+    category.name = Category::normalize_name(&category.name)
+        .map_err(|e| json_error(StatusCode::BAD_REQUEST, e))?;
+    ensure_name_available(&collection, &category.name, None).await?;
+    category.slug = unique_slug(&collection, &category.name, None).await.map_err(database_error)?;
+
     let result = collection
         .insert_one(category.clone(), None)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|e| conflict_or_database_error(e, &category.name))?;
+    category.id = result.inserted_id.as_object_id();
 
-    let created_category = Category {
-        id: Some(result.inserted_id.as_object_id().unwrap()),
-        name: category.name,
-    };
+    Ok(Json(category))
+}
 
-    Ok(Json(created_category))
+/// Looks up a category by its ID or its slug
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID or slug of the category, e.g. `landscapes`
+///
+/// # Returns
+/// Returns the category, or `NOT_FOUND` if neither matches
+pub async fn get_category(
+    State(db): State<Arc<Database>>,
+    Path(id): Path<String>,
+) -> Result<Json<Category>, StatusCode> {
+    find_category(&db, &id).await.map(Json)
+}
+
+/// Looks up a category by its ID or its slug
+///
+/// # Returns
+/// The category, or `NOT_FOUND` if neither matches
+pub(crate) async fn find_category(db: &Database, id: &str) -> Result<Category, StatusCode> {
+    let filter = match ObjectId::parse_str(id) {
+        Ok(object_id) => doc! { "$or": [{ "_id": object_id }, { "slug": id }] },
+        Err(_) => doc! { "slug": id },
+    };
+    db.collection::<Category>("category")
+        .find_one(filter, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to query category: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
 }
 
 /// Lists all available categories
@@ -60,3 +191,253 @@ pub async fn list_categories(
 
     Ok(Json(categories))
 }
+
+/// Opts a category in or out of watermarking
+///
+/// The public copies of the category's photos are re-rendered in the background.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the category
+/// * `body` - Whether the watermark is enabled for the category
+///
+/// # Returns
+/// Returns the updated category, or `NOT_FOUND` if it does not exist
+pub async fn set_category_watermark(
+    State(db): State<Arc<Database>>,
+    Path(id): Path<String>,
+    Json(body): Json<CategoryWatermark>,
+) -> Result<Json<Category>, StatusCode> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let collection = db.collection::<Category>("category");
+
+    let result = collection
+        .update_one(doc! { "_id": object_id }, doc! { "$set": { "watermark": body.enabled } }, None)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if result.matched_count == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let category = collection
+        .find_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    if result.modified_count > 0 {
+        tokio::spawn(rerender_photos(db.clone(), Some(doc! { "category_id": object_id })));
+    }
+
+    Ok(Json(category))
+}
+
+/// Renames a category
+///
+/// Media refer to their category by ID, so they follow the new name. The
+/// slug is generated again from the new name, so public URLs change with it.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the category
+/// * `body` - New name of the category
+///
+/// # Returns
+/// Returns the updated category, or an error status with a JSON `error` message
+pub async fn update_category(
+    State(db): State<Arc<Database>>,
+    Path(id): Path<String>,
+    Json(body): Json<CategoryRename>,
+) -> Result<Json<Category>, (StatusCode, Json<serde_json::Value>)> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| json_error(StatusCode::BAD_REQUEST, "invalid category id"))?;
+    let name = Category::normalize_name(&body.name)
+        .map_err(|e| json_error(StatusCode::BAD_REQUEST, e))?;
+
+    let collection = db.collection::<Category>("category");
+    ensure_name_available(&collection, &name, Some(object_id)).await?;
+    let slug = unique_slug(&collection, &name, Some(object_id)).await.map_err(database_error)?;
+    let result = collection
+        .update_one(doc! { "_id": object_id }, doc! { "$set": { "name": &name, "slug": slug } }, None)
+        .await
+        .map_err(|e| conflict_or_database_error(e, &name))?;
+    if result.matched_count == 0 {
+        return Err(json_error(StatusCode::NOT_FOUND, "category not found"));
+    }
+
+    collection
+        .find_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(database_error)?
+        .map(Json)
+        .ok_or_else(|| json_error(StatusCode::NOT_FOUND, "category not found"))
+}
+
+/// Deletes a category, handling its media with the given strategy
+///
+/// With `strategy=refuse`, the default, a category that still holds media
+/// is kept and `409 Conflict` reports what is in it. With
+/// `strategy=reassign&target=<id>` the media move to the target category,
+/// whose watermark setting then applies to the photos. With
+/// `strategy=cascade` the media are deleted too.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the category
+/// * `query` - Deletion strategy and reassignment target
+///
+/// # Returns
+/// The strategy and the number of photos, models and videos it affected,
+/// or an error status with a JSON `error` message
+pub async fn delete_category(
+    State(db): State<Arc<Database>>,
+    Path(id): Path<String>,
+    Query(query): Query<DeleteCategoryQuery>,
+) -> Result<Json<CategoryDeletion>, (StatusCode, Json<serde_json::Value>)> {
+    let object_id = ObjectId::parse_str(&id)
+        .map_err(|_| json_error(StatusCode::BAD_REQUEST, "invalid category id"))?;
+    let categories = db.collection::<Category>("category");
+    let category = categories
+        .find_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| json_error(StatusCode::NOT_FOUND, "category not found"))?;
+
+    let photos = db.collection::<Photo>("photos");
+    let models = db.collection::<Model>("models");
+    let videos = db.collection::<Video>("videos");
+    let filter = doc! { "category_id": object_id };
+
+    let mut deletion = CategoryDeletion { strategy: query.strategy, photos: 0, models: 0, videos: 0 };
+    match query.strategy {
+        DeleteStrategy::Refuse => {
+            deletion.photos = photos.count_documents(filter.clone(), None).await.map_err(database_error)?;
+            deletion.models = models.count_documents(filter.clone(), None).await.map_err(database_error)?;
+            deletion.videos = videos.count_documents(filter.clone(), None).await.map_err(database_error)?;
+            if deletion.photos + deletion.models + deletion.videos > 0 {
+                return Err((StatusCode::CONFLICT, Json(json!({
+                    "error": "category is not empty; reassign or cascade its media",
+                    "photos": deletion.photos,
+                    "models": deletion.models,
+                    "videos": deletion.videos,
+                }))));
+            }
+        },
+        DeleteStrategy::Reassign => {
+            let target_id = query.target.as_deref()
+                .ok_or_else(|| json_error(StatusCode::BAD_REQUEST, "reassign needs a target category"))
+                .and_then(|target| ObjectId::parse_str(target)
+                    .map_err(|_| json_error(StatusCode::BAD_REQUEST, "invalid target category id")))?;
+            if target_id == object_id {
+                return Err(json_error(StatusCode::BAD_REQUEST, "target must be another category"));
+            }
+            let target = categories
+                .find_one(doc! { "_id": target_id }, None)
+                .await
+                .map_err(database_error)?
+                .ok_or_else(|| json_error(StatusCode::NOT_FOUND, "target category not found"))?;
+
+            // Only the photos moved here are re-rendered, not those already in the target
+            let moved_photos = doc! { "_id": { "$in": photos.distinct("_id", filter.clone(), None).await.map_err(database_error)? } };
+            let update = doc! { "$set": { "category_id": target_id } };
+            deletion.photos = photos.update_many(moved_photos.clone(), update.clone(), None).await.map_err(database_error)?.modified_count;
+            deletion.models = models.update_many(filter.clone(), update.clone(), None).await.map_err(database_error)?.modified_count;
+            deletion.videos = videos.update_many(filter.clone(), update, None).await.map_err(database_error)?.modified_count;
+
+            if deletion.photos > 0 && target.watermark != category.watermark {
+                tokio::spawn(rerender_photos(db.clone(), Some(moved_photos)));
+            }
+        },
+        DeleteStrategy::Cascade => {
+            let deleted_photos: Vec<Photo> = photos.find(filter.clone(), None).await.map_err(database_error)?
+                .try_collect().await.map_err(database_error)?;
+            let deleted_models: Vec<Model> = models.find(filter.clone(), None).await.map_err(database_error)?
+                .try_collect().await.map_err(database_error)?;
+            let deleted_videos: Vec<Video> = videos.find(filter, None).await.map_err(database_error)?
+                .try_collect().await.map_err(database_error)?;
+
+            // Media added to the category meanwhile is left alone, as its files were not looked up
+            let ids = |ids: Vec<Option<ObjectId>>| doc! { "_id": { "$in": ids.into_iter().flatten().collect::<Vec<_>>() } };
+            deletion.photos = photos.delete_many(ids(deleted_photos.iter().map(|p| p.id).collect()), None).await
+                .map_err(database_error)?.deleted_count;
+            deletion.models = models.delete_many(ids(deleted_models.iter().map(|m| m.id).collect()), None).await
+                .map_err(database_error)?.deleted_count;
+            deletion.videos = videos.delete_many(ids(deleted_videos.iter().map(|v| v.id).collect()), None).await
+                .map_err(database_error)?.deleted_count;
+
+            deleted_photos.iter().for_each(remove_photo_files);
+            deleted_models.iter().for_each(remove_stored_model);
+            deleted_videos.iter().for_each(remove_video_files);
+        },
+    }
+
+    categories
+        .delete_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(database_error)?;
+    println!("🗑️ Deleted category {} ({:?}: {} photos, {} models, {} videos)",
+        category.name, deletion.strategy, deletion.photos, deletion.models, deletion.videos);
+
+    Ok(Json(deletion))
+}
+
+/// Rejects a name another category already has, regardless of case
+///
+/// # Arguments
+/// * `collection` - Category collection
+/// * `name` - Normalized name
+/// * `exclude` - Category being renamed, which may keep its own name
+async fn ensure_name_available(
+    collection: &Collection<Category>,
+    name: &str,
+    exclude: Option<ObjectId>,
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let mut filter = doc! { "name": name };
+    if let Some(exclude) = exclude {
+        filter.insert("_id", doc! { "$ne": exclude });
+    }
+    let options = FindOneOptions::builder().collation(Category::name_collation()).build();
+    match collection.find_one(filter, options).await.map_err(database_error)? {
+        Some(existing) => Err(json_error(
+            StatusCode::CONFLICT,
+            format!("a category named '{}' already exists", existing.name),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Picks the slug of a name, numbering it (`landscapes-2`) when another
+/// category already uses it
+///
+/// # Arguments
+/// * `collection` - Category collection
+/// * `name` - Normalized name
+/// * `exclude` - Category being renamed, which may keep its own slug
+async fn unique_slug(
+    collection: &Collection<Category>,
+    name: &str,
+    exclude: Option<ObjectId>,
+) -> mongodb::error::Result<String> {
+    for slug in Category::slug_candidates(name) {
+        let mut filter = doc! { "slug": &slug };
+        if let Some(exclude) = exclude {
+            filter.insert("_id", doc! { "$ne": exclude });
+        }
+        if collection.find_one(filter, None).await?.is_none() {
+            return Ok(slug);
+        }
+    }
+    unreachable!("slug candidates never run out")
+}
+
+/// Reports a write that hit a unique index, because another request took
+/// the name or slug in the meantime, as `CONFLICT`
+fn conflict_or_database_error(e: mongodb::error::Error, name: &str) -> (StatusCode, Json<serde_json::Value>) {
+    match e.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write)) if write.code == DUPLICATE_KEY => {
+            json_error(StatusCode::CONFLICT, format!("a category named '{}' already exists", name))
+        },
+        _ => database_error(e),
+    }
+}
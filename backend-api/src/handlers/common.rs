@@ -0,0 +1,103 @@
+//! Helpers shared by the media handlers
+//!
+//! JSON error responses, lookups by ID and the recording and resuming of
+//! background job states, used alike by photos, models, videos and categories.
+
+use axum::{http::StatusCode, Json};
+use futures_util::StreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    Database,
+};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::models::Job;
+
+/// Builds a JSON error response of the form `{ "error": message }`
+pub(crate) fn json_error(status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<serde_json::Value>) {
+    (status, Json(json!({ "error": message.into() })))
+}
+
+/// Looks up a document by its ID
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `collection` - Name of the collection, e.g. `videos`
+/// * `id` - Hex string of the document ID
+///
+/// # Returns
+/// `BAD_REQUEST` for a malformed ID and `NOT_FOUND` when no document matches
+pub(crate) async fn find_by_id<T>(db: &Database, collection: &str, id: &str) -> Result<T, StatusCode>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let object_id = ObjectId::parse_str(id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    db.collection::<T>(collection)
+        .find_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to query {}: {}", collection, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Records the state of a background job on a document
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `collection` - Name of the collection, e.g. `videos`
+/// * `id` - ID of the document
+/// * `key` - Job key, e.g. `PREVIEW_JOB`
+/// * `job` - New state of the job
+pub(crate) async fn set_job(db: &Database, collection: &str, id: ObjectId, key: &str, job: Job) {
+    let job = match mongodb::bson::to_bson(&job) {
+        Ok(job) => job,
+        Err(e) => {
+            eprintln!("Failed to serialize job: {}", e);
+            return;
+        }
+    };
+    let mut set = Document::new();
+    set.insert(format!("jobs.{}", key), job);
+
+    if let Err(e) = db.collection::<Document>(collection)
+        .update_one(doc! { "_id": id }, doc! { "$set": set }, None)
+        .await
+    {
+        eprintln!("Failed to record {} job of {} {}: {}", key, collection, id, e);
+    }
+}
+
+/// Finds the documents with a job that was queued or running when the
+/// server stopped, so it can be started again
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `collection` - Name of the collection, e.g. `videos`
+/// * `keys` - Job keys to look at, e.g. `[PREVIEW_JOB, HLS_JOB]`
+pub(crate) async fn find_interrupted<T>(db: &Database, collection: &str, keys: &[&str]) -> Vec<T>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let pending: Vec<Document> = keys.iter()
+        .map(|key| doc! { format!("jobs.{}.status", key): { "$in": ["queued", "running"] } })
+        .collect();
+    let mut cursor = match db.collection::<T>(collection).find(doc! { "$or": pending }, None).await {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            eprintln!("Failed to query interrupted jobs of {}: {}", collection, e);
+            return Vec::new();
+        }
+    };
+
+    let mut documents = Vec::new();
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(document) => documents.push(document),
+            Err(e) => eprintln!("Error reading {}: {}", collection, e),
+        }
+    }
+    documents
+}
@@ -7,6 +7,9 @@
 //! - `auth`: Handles authentication and authorization
 //! - `categories`: Handles category management
 //! - `stats`: Handles statistics retrieval
+//! - `stream`: Range-aware streaming of large media files
+//! - `watermark`: Handles watermark settings and re-rendering
+//! - `common`: Error responses, lookups and job tracking shared by the handlers
 
 pub mod photos;
 pub mod models;
@@ -14,4 +17,7 @@ pub mod videos;
 pub mod auth;
 pub mod categories;
 pub mod stats;
+pub mod stream;
+pub mod watermark;
+pub mod common;
 
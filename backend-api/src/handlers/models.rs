@@ -5,15 +5,30 @@
 //! - Model retrieval
 //! - Model listing
 //! - Model deletion
+//! - Thumbnail rendering
+//! - Mesh optimization and levels of detail
+//! - Viewer presets
 
 use axum::{
-    extract::{Multipart, State, Path as AxumPath},
+    extract::{Multipart, Query, State, Path as AxumPath},
     Json,
     http::StatusCode
 };
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{fs, path::{Path, PathBuf}, sync::Arc};
 use mongodb::Database;
-use crate::models::{Model, ModelResponse, Category};  
+use crate::models::{Model, ModelResponse, Category, Job, JobStatus, ViewerPreset};
+use crate::models::model::{ModelLod, OPTIMIZE_JOB, THUMBNAIL_JOB};
+use crate::handlers::{categories::CategoryQuery, common::{find_by_id, find_interrupted, json_error, set_job}};
+use crate::processing::{
+    gltf::GltfStats,
+    mesh_optimize,
+    model_bundle,
+    model_format::{self, InvalidModel, ModelFormat},
+    model_textures::{self, TextureStats},
+    model_thumbnail,
+    splat::{self, SplatStats},
+    ProcessingResult,
+};
 use serde_json::json;
 use std::io::Write;
 use uuid::Uuid;
@@ -24,100 +39,288 @@ use mongodb::bson::doc;
 /// Directory where 3D models are stored
 pub const MODEL_FOLDER: &str = "static/models";
 
+/// Folder inside `MODEL_FOLDER` holding rendered thumbnails and turntables
+pub const THUMBNAILS_FOLDER: &str = "thumbnails";
+
+/// Folder inside `MODEL_FOLDER` holding optimized copies and levels of detail
+pub const LODS_FOLDER: &str = "lods";
+
 /// Handles 3D model upload requests
+///
+/// The format is detected from the file contents and the structure of the
+/// file is validated, so malformed models are rejected with a precise
+/// error. The stored file gets the extension of the detected format.
+///
+/// A `.gltf` model with external buffers and images is uploaded as a zip
+/// file or as several `file` fields. Its bundle is stored in a folder of its
+/// own, or packed into a single GLB when the `pack` field is `true`.
+///
+/// When the `compress_textures` field is `true`, the textures embedded in a
+/// GLB model are downscaled and re-encoded before it is stored.
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
-/// * `multipart` - Multipart form data containing model file and metadata
+/// * `multipart` - Multipart form data containing model file(s) and metadata
 /// 
 /// # Returns
-/// Returns the URL and filename of the uploaded model
+/// Returns the URL, filename and format of the uploaded model, or an error
+/// status with a JSON `error` message
 pub async fn upload_model(
     State(db): State<Arc<Database>>,
     mut multipart: Multipart
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
     let mut name = String::new();
     let mut category_id = String::new();
-    let mut saved_filename = String::new();
+    let mut pack = false;
+    let mut compress_textures = false;
+    let staging = PathBuf::from(MODEL_FOLDER).join(format!(".upload_{}", Uuid::new_v4()));
+    let mut uploads: Vec<PathBuf> = Vec::new();
+    let cleanup = |staging: &Path| {
+        let _ = fs::remove_dir_all(staging);
+    };
 
     while let Some(mut field) = multipart.next_field().await.map_err(|e| { 
         eprintln!("Error getting next field: {}", e);
-        StatusCode::BAD_REQUEST
+        cleanup(&staging);
+        json_error(StatusCode::BAD_REQUEST, "invalid multipart body")
     })? {
         match field.name() {
             Some("name") => {
-                name = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                name = field.text().await
+                    .map_err(|_| {
+                        cleanup(&staging);
+                        json_error(StatusCode::BAD_REQUEST, "invalid name field")
+                    })?;
             },
             Some("category") => {
-                category_id = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+                category_id = field.text().await
+                    .map_err(|_| {
+                        cleanup(&staging);
+                        json_error(StatusCode::BAD_REQUEST, "invalid category field")
+                    })?;
+            },
+            Some("pack") => {
+                pack = field.text().await
+                    .map_err(|_| {
+                        cleanup(&staging);
+                        json_error(StatusCode::BAD_REQUEST, "invalid pack field")
+                    })? == "true";
+            },
+            Some("compress_textures") => {
+                compress_textures = field.text().await
+                    .map_err(|_| {
+                        cleanup(&staging);
+                        json_error(StatusCode::BAD_REQUEST, "invalid compress_textures field")
+                    })? == "true";
             },
             Some("file") => {
-                let filename = field.file_name()
-                    .map(|f| f.to_string())
-                    .unwrap_or_else(|| format!("{}.splat", Uuid::new_v4()));
-                
-                println!("📦 Uploading model: {}", filename);
+                // The uploaded name only hints at the format; it is detected once stored
+                let Some(relative) = model_bundle::upload_path(field.file_name().unwrap_or("model")) else {
+                    cleanup(&staging);
+                    return Err(json_error(StatusCode::BAD_REQUEST, "invalid file name"));
+                };
+                if uploads.contains(&relative) || uploads.len() >= model_bundle::MAX_BUNDLE_FILES {
+                    cleanup(&staging);
+                    return Err(json_error(StatusCode::BAD_REQUEST, format!(
+                        "duplicate file '{}' or more than {} files", relative.display(), model_bundle::MAX_BUNDLE_FILES
+                    )));
+                }
+                let filepath = staging.join(&relative);
                 
-                let filepath = format!("{}/{}", MODEL_FOLDER, filename);
+                println!("📦 Uploading model: {}", field.file_name().unwrap_or("unnamed"));
                 
-                if !PathBuf::from(MODEL_FOLDER).exists() {
-                    fs::create_dir_all(MODEL_FOLDER).map_err(|e| {
-                        eprintln!("Failed to create directory: {}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR
-                    })?;
+                if let Err(e) = fs::create_dir_all(filepath.parent().unwrap_or(&staging)) {
+                    eprintln!("Failed to create directory: {}", e);
+                    cleanup(&staging);
+                    return Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store model"));
                 }
 
-
                 let mut file = std::fs::File::create(&filepath).map_err(|e| {
                     eprintln!("Failed to create file: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
+                    cleanup(&staging);
+                    json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store model")
                 })?;
+                uploads.push(relative);
 
                 while let Some(chunk) = field.chunk().await.map_err(|e| {
                     eprintln!("Error reading chunk: {}", e);
-                    StatusCode::BAD_REQUEST
+                    cleanup(&staging);
+                    json_error(StatusCode::BAD_REQUEST, "upload was interrupted")
                 })? {
                     file.write_all(&chunk).map_err(|e| {
                         eprintln!("Failed to write chunk: {}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR
+                        cleanup(&staging);
+                        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store model")
                     })?;
                 }
-
-                saved_filename = filename.clone();
             },
             _ => {}
         }
     }
 
-
-    if name.is_empty() || category_id.is_empty() || saved_filename.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+    if uploads.is_empty() {
+        cleanup(&staging);
+        return Err(json_error(StatusCode::BAD_REQUEST, "missing file field"));
     }
+    if name.is_empty() || category_id.is_empty() {
+        cleanup(&staging);
+        return Err(json_error(StatusCode::BAD_REQUEST, "missing name or category"));
+    }
+    let Ok(category_object_id) = ObjectId::parse_str(&category_id) else {
+        cleanup(&staging);
+        return Err(json_error(StatusCode::BAD_REQUEST, "invalid category id"));
+    };
 
-    let category_object_id = mongodb::bson::oid::ObjectId::parse_str(&category_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
+    let blocking_staging = staging.clone();
+    let stored = tokio::task::spawn_blocking(move || store_upload(&blocking_staging, &uploads, pack, compress_textures))
+        .await
+        .map_err(|e| e.into())
+        .and_then(|result| result);
+    cleanup(&staging);
+    let stored = stored.map_err(|e| {
+        match e.downcast_ref::<InvalidModel>() {
+            Some(invalid) => json_error(StatusCode::UNPROCESSABLE_ENTITY, invalid.to_string()),
+            None => {
+                eprintln!("Failed to read model: {}", e);
+                json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to read model")
+            }
+        }
+    })?;
+    let StoredModel { filename: saved_filename, format, stats, splat, textures } = stored;
+    println!("✅ Model saved successfully: {} ({:?})", saved_filename, format);
 
-    let model = Model::new(name, saved_filename.clone(), category_object_id);
+    let mut model = Model::new(name, saved_filename.clone(), category_object_id);
+    model.format = Some(format);
+    model.stats = stats.clone();
+    model.splat = splat;
+    model.textures = textures;
+    if model_thumbnail::supports(format) {
+        model.jobs.insert(THUMBNAIL_JOB.to_string(), Job::new(JobStatus::Queued));
+    }
+    if format == ModelFormat::Glb {
+        model.jobs.insert(OPTIMIZE_JOB.to_string(), Job::new(JobStatus::Queued));
+    }
     
     match db.collection::<Model>("models")
         .insert_one(model, None)
         .await {
-        Ok(_) => {
+        Ok(result) => {
+            if let Some(id) = result.inserted_id.as_object_id() {
+                if model_thumbnail::supports(format) {
+                    tokio::spawn(generate_thumbnail(db.clone(), id));
+                }
+                if format == ModelFormat::Glb {
+                    tokio::spawn(generate_lods(db.clone(), id));
+                }
+            }
             let response = json!({
                 "url": format!("/static/models/{}", saved_filename),
                 "filename": saved_filename,
+                "format": format,
+                "stats": stats,
+                "splat": splat,
+                "textures": textures,
                 "success": true
             });
             Ok(Json(response))
         },
         Err(e) => {
             eprintln!("Failed to save model to database: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            remove_model_files(&saved_filename);
+            Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save model"))
         }
     }
 }
 
+/// A model moved into `MODEL_FOLDER` by `store_upload`
+struct StoredModel {
+    /// Filename relative to `MODEL_FOLDER`
+    filename: String,
+    format: ModelFormat,
+    stats: Option<GltfStats>,
+    splat: Option<SplatStats>,
+    textures: Option<TextureStats>,
+}
+
+/// Validates the staged files of an upload and moves the model into place
+///
+/// A single file other than a zip is stored as `model_<id>_<date>.<ext>`;
+/// Gaussian splat PLY files are converted to `.splat`. Bundles are stored
+/// in a `model_<id>_<date>` folder, with the `.gltf` file as the model, or
+/// packed into `model_<id>_<date>.glb`. With `compress_textures`, the
+/// textures of GLB models are downscaled and re-encoded.
+fn store_upload(staging: &Path, uploads: &[PathBuf], pack: bool, compress_textures: bool) -> ProcessingResult<StoredModel> {
+    let stem = format!("model_{}_{}", Uuid::new_v4(), chrono::Local::now().format("%Y%m%d"));
+    let place = |path: &Path| -> ProcessingResult<_> {
+        let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned());
+        let mut format = model_format::detect(path, extension.as_deref())?;
+        let mut source = path.to_path_buf();
+        let textures = match format {
+            ModelFormat::Glb if compress_textures => {
+                let compressed = staging.join(".textures.glb");
+                let max_size = model_textures::max_texture_size();
+                let textures = model_textures::compress(path, &compressed, max_size, model_textures::texture_format())?;
+                if let Some(textures) = textures {
+                    println!("🗜️ Compressed {} textures, {} -> {} bytes",
+                        textures.image_count, textures.original_size, textures.size);
+                    source = compressed;
+                }
+                textures
+            },
+            _ => None,
+        };
+        let stats = model_format::gltf_stats(&source, format)?;
+        let splat = match format {
+            ModelFormat::Ply if splat::is_gaussian_ply(path)? => {
+                source = staging.join(".converted.splat");
+                let stats = splat::convert_ply(path, &source, splat::min_opacity())?;
+                println!("✨ Converted {} Gaussian splats, pruned {}, {} -> {} bytes",
+                    stats.splat_count, stats.pruned_count, stats.original_size, stats.size);
+                format = ModelFormat::Splat;
+                Some(stats)
+            },
+            ModelFormat::Splat => Some(splat::stats(path)?),
+            _ => None,
+        };
+        let filename = format!("{}.{}", stem, format.extension());
+        fs::rename(&source, Path::new(MODEL_FOLDER).join(&filename))?;
+        Ok(StoredModel { filename, format, stats, splat, textures })
+    };
+
+    let folder = match uploads {
+        [upload] if !model_bundle::is_zip(&staging.join(upload))? => return place(&staging.join(upload)),
+        [upload] => {
+            let folder = staging.join(".bundle");
+            model_bundle::extract_zip(&staging.join(upload), &folder)?;
+            folder
+        },
+        _ => staging.to_path_buf(),
+    };
+
+    let bundle = model_bundle::resolve(&folder)?;
+    if bundle.is_glb() {
+        return place(&folder.join(&bundle.root));
+    }
+    if pack {
+        let packed = staging.join(".packed.glb");
+        fs::write(&packed, model_bundle::pack(&folder, &bundle)?)?;
+        return place(&packed);
+    }
+
+    let stats = model_format::gltf_stats(&folder.join(&bundle.root), ModelFormat::Gltf)?;
+    model_bundle::store(&folder, &bundle, &Path::new(MODEL_FOLDER).join(&stem))?;
+    let root: Vec<_> = bundle.root.iter().map(|part| part.to_string_lossy()).collect();
+    Ok(StoredModel { filename: format!("{}/{}", stem, root.join("/")), format: ModelFormat::Gltf, stats, splat: None, textures: None })
+}
+
+/// Removes a stored model, along with the folder of a bundle
+fn remove_model_files(filename: &str) {
+    let _ = match filename.split_once('/') {
+        Some((folder, _)) => fs::remove_dir_all(Path::new(MODEL_FOLDER).join(folder)),
+        None => fs::remove_file(Path::new(MODEL_FOLDER).join(filename)),
+    };
+}
+
 /// Lists all available 3D models
 /// 
 /// # Returns
@@ -133,7 +336,7 @@ pub async fn list_models() -> Result<Json<Vec<String>>, StatusCode> {
             let models = paths
                 .filter_map(|entry| {
                     entry.ok().and_then(|e| {
-                        if e.path().is_file() {
+                        if e.path().is_file() && !e.file_name().to_string_lossy().starts_with('.') {
                             Some(format!("/static/models/{}", 
                                 e.file_name().to_string_lossy()))
                         } else {
@@ -153,13 +356,16 @@ pub async fn list_models() -> Result<Json<Vec<String>>, StatusCode> {
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
+/// * `query` - ID or slug of the category to list, e.g. `?category=landscapes`
 /// 
 /// # Returns
 /// Returns a list of model details with category information
 pub async fn get_models(
-    State(db): State<Arc<Database>>
+    State(db): State<Arc<Database>>,
+    Query(query): Query<CategoryQuery>,
 ) -> Result<Json<Vec<ModelResponse>>, StatusCode> {
     println!("📦 Fetching models from MongoDB");
+    let media_filter = query.media_filter(&db).await?;
     
     let models_collection = db.collection::<Model>("models");
     let categories_collection = db.collection::<Category>("category");
@@ -178,7 +384,7 @@ pub async fn get_models(
         }
     }
 
-    let mut cursor = models_collection.find(None, None).await.map_err(|e| {
+    let mut cursor = models_collection.find(media_filter, None).await.map_err(|e| {
         eprintln!("Failed to query models: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
@@ -210,14 +416,314 @@ pub async fn delete_model(
     State(db): State<Arc<Database>>,
     AxumPath(id): AxumPath<String>
 ) -> Result<StatusCode, StatusCode> {
-    let object_id = ObjectId::parse_str(&id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let model = find_by_id::<Model>(&db, "models", &id).await?;
 
     match db.collection::<Model>("models")
-        .delete_one(doc! { "_id": object_id }, None)
+        .delete_one(doc! { "_id": model.id }, None)
         .await {
-        Ok(result) if result.deleted_count == 1 => Ok(StatusCode::NO_CONTENT),
+        Ok(result) if result.deleted_count == 1 => {
+            remove_stored_model(&model);
+            Ok(StatusCode::NO_CONTENT)
+        },
         Ok(_) => Err(StatusCode::NOT_FOUND),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
-}
\ No newline at end of file
+}
+
+/// Removes the files of a deleted model: the model or its bundle folder,
+/// its thumbnail and turntable, and its levels of detail
+pub(crate) fn remove_stored_model(model: &Model) {
+    remove_model_files(&model.filename);
+    for rendered in [&model.thumbnail_filename, &model.turntable_filename].into_iter().flatten() {
+        let _ = fs::remove_file(Path::new(MODEL_FOLDER).join(rendered));
+    }
+    remove_lod_files(&model.lods);
+}
+/// Renders the thumbnail and, when enabled, the turntable of a model
+///
+/// Runs as a background job; its progress is recorded under `THUMBNAIL_JOB`.
+/// Each run writes new files, so cached images of a previous run are never
+/// served; the previous files are removed once the new ones are in use.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the model
+pub(crate) async fn generate_thumbnail(db: Arc<Database>, id: ObjectId) {
+    let models = db.collection::<Model>("models");
+    let model = match models.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query model: {}", e);
+            return;
+        }
+    };
+    let Some(format) = model.format.filter(|format| model_thumbnail::supports(*format)) else {
+        set_job(&db, "models", id, THUMBNAIL_JOB, Job::failed("thumbnails are not supported for this model format")).await;
+        return;
+    };
+
+    set_job(&db, "models", id, THUMBNAIL_JOB, Job::new(JobStatus::Running)).await;
+    println!("🖼️ Rendering thumbnail of {}", model.filename);
+
+    let version = &Uuid::new_v4().simple().to_string()[..8];
+    let stem = file_stem(&model.filename);
+    let thumbnail = format!("{}/{}_{}.png", THUMBNAILS_FOLDER, stem, version);
+    let frames = model_thumbnail::turntable_frames();
+    let turntable = frames.map(|_| format!("{}/{}_{}.gif", THUMBNAILS_FOLDER, stem, version));
+
+    let source = Path::new(MODEL_FOLDER).join(&model.filename);
+    let (thumbnail_path, turntable_path) = (
+        Path::new(MODEL_FOLDER).join(&thumbnail),
+        turntable.as_ref().map(|t| Path::new(MODEL_FOLDER).join(t)),
+    );
+    let result = tokio::task::spawn_blocking(move || -> ProcessingResult<()> {
+        fs::create_dir_all(Path::new(MODEL_FOLDER).join(THUMBNAILS_FOLDER))?;
+        let turntable = turntable_path.as_deref().zip(frames);
+        model_thumbnail::render(&source, format, &thumbnail_path, turntable)
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result);
+
+    if let Err(e) = result {
+        eprintln!("❌ Failed to render thumbnail of {}: {}", model.filename, e);
+        set_job(&db, "models", id, THUMBNAIL_JOB, Job::failed(e.to_string())).await;
+        return;
+    }
+
+    if let Err(e) = models
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "thumbnail_filename": &thumbnail, "turntable_filename": &turntable } },
+            None,
+        )
+        .await
+    {
+        eprintln!("Failed to save thumbnail of model {}: {}", id, e);
+        set_job(&db, "models", id, THUMBNAIL_JOB, Job::failed("failed to save the thumbnail")).await;
+        return;
+    }
+    for previous in [model.thumbnail_filename, model.turntable_filename].into_iter().flatten() {
+        let _ = fs::remove_file(Path::new(MODEL_FOLDER).join(previous));
+    }
+
+    set_job(&db, "models", id, THUMBNAIL_JOB, Job::new(JobStatus::Done)).await;
+    println!("✅ Thumbnail ready for {}", model.filename);
+}
+
+/// Queues the thumbnail and turntable of a model to be rendered again
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the model
+///
+/// # Returns
+/// `202 Accepted`, or `422` for formats without thumbnails
+pub async fn regenerate_thumbnail(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let model = find_by_id::<Model>(&db, "models", &id).await?;
+    let id = model.id.ok_or(StatusCode::NOT_FOUND)?;
+    if !model.format.is_some_and(model_thumbnail::supports) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    set_job(&db, "models", id, THUMBNAIL_JOB, Job::new(JobStatus::Queued)).await;
+    tokio::spawn(generate_thumbnail(db, id));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": 1 }))))
+}
+
+/// Optimizes the meshes of a GLB model and writes its levels of detail
+///
+/// Runs as a background job; its progress is recorded under `OPTIMIZE_JOB`.
+/// Like thumbnails, each run writes new files and removes those of the
+/// previous run once the new ones are in use.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the model
+pub(crate) async fn generate_lods(db: Arc<Database>, id: ObjectId) {
+    let models = db.collection::<Model>("models");
+    let model = match models.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(model)) => model,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query model: {}", e);
+            return;
+        }
+    };
+    if model.format != Some(ModelFormat::Glb) {
+        set_job(&db, "models", id, OPTIMIZE_JOB, Job::failed("only GLB models can be optimized")).await;
+        return;
+    }
+
+    set_job(&db, "models", id, OPTIMIZE_JOB, Job::new(JobStatus::Running)).await;
+    println!("🔧 Optimizing meshes of {}", model.filename);
+
+    let version = Uuid::new_v4().simple().to_string()[..8].to_string();
+    let stem = file_stem(&model.filename);
+    let source = Path::new(MODEL_FOLDER).join(&model.filename);
+    let result = tokio::task::spawn_blocking(move || -> ProcessingResult<Vec<ModelLod>> {
+        let levels = mesh_optimize::generate(&source, &mesh_optimize::lod_ratios())?;
+        fs::create_dir_all(Path::new(MODEL_FOLDER).join(LODS_FOLDER))?;
+        let mut lods = Vec::new();
+        for level in levels {
+            let filename = format!("{}/{}_{}_lod{}.glb", LODS_FOLDER, stem, version, (level.ratio * 100.0).round());
+            if let Err(e) = fs::write(Path::new(MODEL_FOLDER).join(&filename), &level.glb) {
+                remove_lod_files(&lods);
+                return Err(e.into());
+            }
+            lods.push(ModelLod {
+                ratio: level.ratio,
+                filename,
+                size: level.glb.len() as u64,
+                triangle_count: level.triangle_count,
+            });
+        }
+        Ok(lods)
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result);
+
+    let lods = match result {
+        Ok(lods) => lods,
+        Err(e) => {
+            eprintln!("❌ Failed to optimize {}: {}", model.filename, e);
+            set_job(&db, "models", id, OPTIMIZE_JOB, Job::failed(e.to_string())).await;
+            return;
+        }
+    };
+
+    let saved = match mongodb::bson::to_bson(&lods) {
+        Ok(value) => models.update_one(doc! { "_id": id }, doc! { "$set": { "lods": value } }, None).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = saved {
+        eprintln!("Failed to save levels of detail of model {}: {}", id, e);
+        remove_lod_files(&lods);
+        set_job(&db, "models", id, OPTIMIZE_JOB, Job::failed("failed to save the levels of detail")).await;
+        return;
+    }
+    remove_lod_files(&model.lods);
+
+    set_job(&db, "models", id, OPTIMIZE_JOB, Job::new(JobStatus::Done)).await;
+    println!("✅ {} levels of detail ready for {}", lods.len(), model.filename);
+}
+
+/// Queues the mesh optimization and levels of detail of a model to be
+/// generated again, e.g. after changing `MODEL_LOD_RATIOS`
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the model
+///
+/// # Returns
+/// `202 Accepted`, or `422` for models that are not GLB files
+pub async fn regenerate_lods(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let model = find_by_id::<Model>(&db, "models", &id).await?;
+    let id = model.id.ok_or(StatusCode::NOT_FOUND)?;
+    if model.format != Some(ModelFormat::Glb) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    set_job(&db, "models", id, OPTIMIZE_JOB, Job::new(JobStatus::Queued)).await;
+    tokio::spawn(generate_lods(db, id));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": 1 }))))
+}
+
+/// Starts the jobs of models again that were queued or running when the
+/// server stopped
+///
+/// Called once at startup.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+pub async fn resume_jobs(db: Arc<Database>) {
+    let models = find_interrupted::<Model>(&db, "models", &[THUMBNAIL_JOB, OPTIMIZE_JOB]).await;
+    if !models.is_empty() {
+        println!("🔁 Resuming the jobs of {} models", models.len());
+    }
+
+    for model in models {
+        let Some(id) = model.id else { continue };
+        let pending = |key: &str| model.jobs.get(key).is_some_and(|job| job.is_pending());
+        if pending(THUMBNAIL_JOB) {
+            set_job(&db, "models", id, THUMBNAIL_JOB, Job::new(JobStatus::Queued)).await;
+            tokio::spawn(generate_thumbnail(db.clone(), id));
+        }
+        if pending(OPTIMIZE_JOB) {
+            set_job(&db, "models", id, OPTIMIZE_JOB, Job::new(JobStatus::Queued)).await;
+            tokio::spawn(generate_lods(db.clone(), id));
+        }
+    }
+}
+
+/// Stores the viewer settings a model opens with
+///
+/// The preset replaces any previous one; an empty preset removes it, so
+/// the viewer falls back to its defaults.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the model
+/// * `viewer` - Viewer preset to store
+///
+/// # Returns
+/// * `Ok(Json(ModelResponse))` - The updated model
+/// * `Err(StatusCode::BAD_REQUEST)` - Invalid ID or preset, with the reason
+/// * `Err(StatusCode::NOT_FOUND)` - Model with given ID was not found
+pub async fn update_model_viewer(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    Json(mut viewer): Json<ViewerPreset>,
+) -> Result<Json<ModelResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let status_error = |status: StatusCode| json_error(status, status.canonical_reason().unwrap_or("error"));
+    viewer.validate()
+        .map_err(|e| json_error(StatusCode::BAD_REQUEST, e))?;
+    viewer.background_color = viewer.background_color.map(|color| color.to_ascii_lowercase());
+
+    let mut model = find_by_id::<Model>(&db, "models", &id).await.map_err(status_error)?;
+    let object_id = model.id.ok_or_else(|| status_error(StatusCode::NOT_FOUND))?;
+    model.viewer = if viewer == ViewerPreset::default() { None } else { Some(viewer) };
+
+    let update = match &model.viewer {
+        Some(viewer) => {
+            let viewer = mongodb::bson::to_bson(viewer)
+                .map_err(|_| status_error(StatusCode::INTERNAL_SERVER_ERROR))?;
+            doc! { "$set": { "viewer": viewer } }
+        },
+        None => doc! { "$unset": { "viewer": "" } },
+    };
+    db.collection::<Model>("models")
+        .update_one(doc! { "_id": object_id }, update, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save viewer preset of model {}: {}", object_id, e);
+            status_error(StatusCode::INTERNAL_SERVER_ERROR)
+        })?;
+
+    Ok(Json(model.to_response()))
+}
+
+/// Removes the files of levels of detail
+fn remove_lod_files(lods: &[ModelLod]) {
+    for lod in lods {
+        let _ = fs::remove_file(Path::new(MODEL_FOLDER).join(&lod.filename));
+    }
+}
+
+/// Name of a stored model without its extension, or the folder of a bundle,
+/// used to name the files derived from it
+fn file_stem(filename: &str) -> String {
+    let stem = Path::new(filename).iter().next().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    stem.rsplit_once('.').map_or(stem.clone(), |(stem, _)| stem.to_string())
+}
+
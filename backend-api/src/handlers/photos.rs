@@ -5,30 +5,162 @@
 //! - Photo retrieval
 //! - Photo listing
 //! - Photo deletion
+//! - Non-destructive photo edits
+//! - Near-duplicate detection
 //! - Individual photo file serving
 
 use axum::{
-    extract::{Multipart, Path as AxumPath, State},
+    extract::{Multipart, Path as AxumPath, Query, State},
     Json,
     response::{IntoResponse, Response},
     http::{StatusCode, header},
     body::StreamBody,
 };
-use std::{fs, path::{PathBuf, Path}, sync::Arc};
+use std::{collections::HashMap, fs, path::{PathBuf, Path}, sync::Arc};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio_util::io::ReaderStream;
 use uuid::Uuid;
+use serde::Serialize;
 use serde_json::json;
 use mongodb::Database;
-use crate::models::{Photo, PhotoResponse, Category}; 
+use crate::models::{Photo, PhotoEdits, PhotoResponse, Category}; 
+use crate::handlers::{categories::CategoryQuery, common::find_by_id, watermark::load_watermark};
+use crate::processing::{
+    image_info::{self, ImageInfo},
+    phash,
+    photo as photo_processing,
+    photo::AnimatedRendition,
+    watermark::Watermark,
+    ProcessingResult,
+};
 use futures_util::StreamExt;
 use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
 
-/// Directory where photos are stored
+/// Directory where the public photo derivatives are stored
 pub const PHOTO_FOLDER: &str = "static/photos";
 
+/// Private directory where the untouched uploaded originals are stored
+pub const PHOTO_ORIGINALS_FOLDER: &str = "storage/photos";
+
+/// Renders the public derivative of a stored photo on the blocking thread pool
+///
+/// # Arguments
+/// * `filename` - Stored filename of the photo
+/// * `edits` - Edit recipe to replay on the original, if any
+/// * `watermark` - Watermark to apply, `None` to publish it unmarked
+///
+/// # Returns
+/// The animated renditions when the photo is animated
+pub(crate) async fn render_photo(
+    filename: String,
+    edits: Option<PhotoEdits>,
+    watermark: Option<Arc<Watermark>>,
+) -> ProcessingResult<Option<AnimatedRendition>> {
+    tokio::task::spawn_blocking(move || {
+        photo_processing::render_derivative(&filename, edits.as_ref(), watermark.as_deref())
+    }).await?
+}
+
+/// Renders the public derivative of a photo with its edits and, when its
+/// category opts in, the current watermark
+pub(crate) async fn publish_photo(
+    db: &Database,
+    photo: &Photo,
+) -> ProcessingResult<Option<AnimatedRendition>> {
+    let watermark = if category_watermark(db, photo.category_id).await {
+        load_watermark(db).await?
+    } else {
+        None
+    };
+    render_photo(photo.filename.clone(), photo.edits.clone(), watermark).await
+}
+
+/// An existing photo that looks like the one being checked
+#[derive(Debug, Serialize)]
+pub struct DuplicateMatch {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    pub category_id: String,
+    /// Number of differing bits between the perceptual hashes
+    pub distance: u32,
+}
+
+/// A group of photos that are near-duplicates of each other
+#[derive(Debug, Serialize)]
+pub struct DuplicateCluster {
+    pub photos: Vec<PhotoResponse>,
+    /// Largest hash distance between two linked photos of the cluster
+    pub max_distance: u32,
+}
+
+/// Reads the layout and colour information of a published photo on the blocking thread pool
+pub(crate) async fn inspect_photo(filename: String) -> ProcessingResult<ImageInfo> {
+    tokio::task::spawn_blocking(move || {
+        let original = photo_processing::ensure_original(&filename)?;
+        image_info::inspect(&original, &photo_processing::public_path(&filename))
+    }).await?
+}
+
+/// Computes the perceptual hash of a photo's original on the blocking thread pool
+pub(crate) async fn hash_photo(filename: String) -> ProcessingResult<u64> {
+    tokio::task::spawn_blocking(move || {
+        let original = photo_processing::ensure_original(&filename)?;
+        phash::dhash_file(&original)
+    }).await?
+}
+
+/// Finds the stored photos whose perceptual hash is close to the given one
+async fn find_near_duplicates(
+    db: &Database,
+    hash: u64,
+) -> mongodb::error::Result<Vec<DuplicateMatch>> {
+    let threshold = phash::duplicate_threshold();
+    let mut cursor = db.collection::<Photo>("photos")
+        .find(doc! { "phash": { "$exists": true } }, None)
+        .await?;
+
+    let mut matches = Vec::new();
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                continue;
+            }
+        };
+        let Some(other) = photo.phash.as_deref().and_then(phash::from_hex) else {
+            continue;
+        };
+        let distance = phash::distance(hash, other);
+        if distance <= threshold {
+            let response = photo.to_response();
+            matches.push(DuplicateMatch {
+                id: response.id,
+                name: response.name,
+                url: response.url,
+                category_id: response.category_id,
+                distance,
+            });
+        }
+    }
+    matches.sort_by_key(|m| m.distance);
+    Ok(matches)
+}
+
+/// Whether photos of the given category should be watermarked
+async fn category_watermark(db: &Database, category_id: ObjectId) -> bool {
+    db.collection::<Category>("category")
+        .find_one(doc! { "_id": category_id }, None)
+        .await
+        .ok()
+        .flatten()
+        .map(|c| c.watermark)
+        .unwrap_or(true)
+}
+
 /// Handles photo upload requests
 /// 
 /// # Arguments
@@ -81,12 +213,12 @@ pub async fn upload_photo(
                 );
                 
                 saved_filename = filename.clone();
-                let filepath = format!("{}/{}", PHOTO_FOLDER, filename);
+                let filepath = format!("{}/{}", PHOTO_ORIGINALS_FOLDER, filename);
 
                 println!("💾 Attempting to save photo to: {} (original: {})", filepath, original_filename);
         
-                if !PathBuf::from(PHOTO_FOLDER).exists() {
-                    fs::create_dir_all(PHOTO_FOLDER).map_err(|e| {
+                if !PathBuf::from(PHOTO_ORIGINALS_FOLDER).exists() {
+                    fs::create_dir_all(PHOTO_ORIGINALS_FOLDER).map_err(|e| {
                         eprintln!("Failed to create directory: {}", e);
                         StatusCode::INTERNAL_SERVER_ERROR
                     })?;
@@ -132,6 +264,9 @@ pub async fn upload_photo(
             !category_id.is_empty(), 
             !saved_filename.is_empty()
         );
+        if !saved_filename.is_empty() {
+            let _ = fs::remove_file(photo_processing::original_path(&saved_filename));
+        }
         return Err(StatusCode::BAD_REQUEST);
     }
 
@@ -140,23 +275,57 @@ pub async fn upload_photo(
         Ok(oid) => oid,
         Err(e) => {
             eprintln!("Invalid category ID format: {}", e);
+            let _ = fs::remove_file(photo_processing::original_path(&saved_filename));
             return Err(StatusCode::BAD_REQUEST);
         }
     };
 
+    let mut photo = Photo::new(name, saved_filename.clone(), category_object_id);
 
-    let photo = Photo::new(name, saved_filename.clone(), category_object_id);
+    match publish_photo(&db, &photo).await {
+        Ok(rendition) => photo.set_animation(rendition),
+        Err(e) => {
+            eprintln!("Failed to render public photo: {}", e);
+            let _ = fs::remove_file(photo_processing::original_path(&saved_filename));
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+    }
 
+    match inspect_photo(saved_filename.clone()).await {
+        Ok(info) => photo.set_image_info(info),
+        Err(e) => eprintln!("Failed to inspect photo: {}", e),
+    }
+
+    let mut duplicates = Vec::new();
+    match hash_photo(saved_filename.clone()).await {
+        Ok(hash) => {
+            photo.phash = Some(phash::to_hex(hash));
+            duplicates = find_near_duplicates(&db, hash).await.unwrap_or_else(|e| {
+                eprintln!("Failed to look for duplicates: {}", e);
+                Vec::new()
+            });
+        },
+        Err(e) => eprintln!("Failed to hash photo: {}", e),
+    }
 
+    let animated = photo.animated;
     match db.collection::<Photo>("photos")
         .insert_one(photo, None)
         .await {
         Ok(_) => {
-            let response = json!({
+            let mut response = json!({
                 "url": format!("/static/photos/{}", saved_filename),
                 "filename": saved_filename,
-                "success": true
+                "success": true,
+                "animated": animated,
+                "duplicates": duplicates
             });
+            if !duplicates.is_empty() {
+                println!("⚠️ Uploaded photo looks like {} existing photo(s)", duplicates.len());
+                response["warning"] = json!(format!(
+                    "This photo looks like {} existing photo(s)", duplicates.len()
+                ));
+            }
             Ok(Json(response))
         },
         Err(e) => {
@@ -208,7 +377,7 @@ pub async fn list_photos() -> Result<Json<Vec<String>>, StatusCode> {
             let photos: Vec<String> = entries
                 .filter_map(|entry| {
                     entry.ok().and_then(|e| {
-                        if e.path().is_file() {
+                        if e.path().is_file() && !e.file_name().to_string_lossy().starts_with('.') {
                             Some(format!("/static/photos/{}", 
                                 e.file_name().to_string_lossy()))
                         } else {
@@ -232,13 +401,16 @@ pub async fn list_photos() -> Result<Json<Vec<String>>, StatusCode> {
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
+/// * `query` - ID or slug of the category to list, e.g. `?category=landscapes`
 /// 
 /// # Returns
 /// Returns a list of photo details with category information
 pub async fn get_photos(
-    State(db): State<Arc<Database>>
+    State(db): State<Arc<Database>>,
+    Query(query): Query<CategoryQuery>,
 ) -> Result<Json<Vec<PhotoResponse>>, StatusCode> {
     println!("📸 Fetching photos from MongoDB");
+    let media_filter = query.media_filter(&db).await?;
     
     let photos_collection = db.collection::<Photo>("photos");
     let categories_collection = db.collection::<Category>("category"); 
@@ -268,7 +440,7 @@ pub async fn get_photos(
     }
     println!("📊 Total categories found: {}", categories_vec.len());
 
-    match photos_collection.find(None, None).await {
+    match photos_collection.find(media_filter, None).await {
         Ok(mut cursor) => {
             let mut photos = Vec::new();
             while let Some(result) = cursor.next().await {
@@ -319,7 +491,7 @@ pub async fn get_photos(
     }
 }
 
-/// Deletes a specific photo
+/// Deletes a specific photo along with its files
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
@@ -334,14 +506,219 @@ pub async fn delete_photo(
     State(db): State<Arc<Database>>,
     AxumPath(id): AxumPath<String>,
 ) -> Result<StatusCode, StatusCode> {
-    let object_id = ObjectId::parse_str(&id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let photo = find_by_id::<Photo>(&db, "photos", &id).await?;
 
     match db.collection::<Photo>("photos")
-        .delete_one(doc! { "_id": object_id }, None)
+        .delete_one(doc! { "_id": photo.id }, None)
         .await {
-        Ok(result) if result.deleted_count == 1 => Ok(StatusCode::NO_CONTENT),
+        Ok(result) if result.deleted_count == 1 => {
+            remove_photo_files(&photo);
+            Ok(StatusCode::NO_CONTENT)
+        },
         Ok(_) => Err(StatusCode::NOT_FOUND),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
+
+/// Removes the files of a deleted photo: its private original, its public
+/// copy and, for animated photos, the poster and animated copy
+pub(crate) fn remove_photo_files(photo: &Photo) {
+    let _ = fs::remove_file(photo_processing::original_path(&photo.filename));
+    let _ = fs::remove_file(photo_processing::public_path(&photo.filename));
+    for derived in [&photo.poster_filename, &photo.animation_filename].into_iter().flatten() {
+        let _ = fs::remove_file(photo_processing::public_path(derived));
+    }
+}
+
+/// Stores an edit recipe on a photo and re-renders its public copy
+///
+/// The recipe replaces any previous one and is always replayed on the
+/// untouched original, so edits can be changed at any time.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the photo to edit
+/// * `edits` - Edit recipe to store
+///
+/// # Returns
+/// * `Ok(Json(PhotoResponse))` - The updated photo
+/// * `Err(StatusCode::BAD_REQUEST)` - Invalid ID or recipe, with the reason
+/// * `Err(StatusCode::NOT_FOUND)` - Photo with given ID was not found
+pub async fn update_photo_edits(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    Json(edits): Json<PhotoEdits>,
+) -> Result<Json<PhotoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    edits.validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))))?;
+
+    let edits = if edits == PhotoEdits::default() { None } else { Some(edits) };
+    save_photo_edits(&db, &id, edits).await
+        .map(Json)
+        .map_err(|status| (status, Json(json!({ "error": status.canonical_reason() }))))
+}
+
+/// Removes the edit recipe of a photo, restoring the original rendering
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the photo to revert
+///
+/// # Returns
+/// * `Ok(Json(PhotoResponse))` - The reverted photo
+/// * `Err(StatusCode::NOT_FOUND)` - Photo with given ID was not found
+/// * `Err(StatusCode::BAD_REQUEST)` - Invalid ID format
+pub async fn revert_photo_edits(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<Json<PhotoResponse>, StatusCode> {
+    save_photo_edits(&db, &id, None).await.map(Json)
+}
+
+async fn save_photo_edits(
+    db: &Database,
+    id: &str,
+    edits: Option<PhotoEdits>,
+) -> Result<PhotoResponse, StatusCode> {
+    let object_id = ObjectId::parse_str(id)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let collection = db.collection::<Photo>("photos");
+
+    let mut photo = collection
+        .find_one(doc! { "_id": object_id }, None)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    photo.edits = edits;
+
+    publish_photo(db, &photo).await.map_err(|e| {
+        eprintln!("Failed to render edited photo {}: {}", photo.filename, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let info = inspect_photo(photo.filename.clone()).await.map_err(|e| {
+        eprintln!("Failed to inspect edited photo {}: {}", photo.filename, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut set = mongodb::bson::to_document(&info)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    photo.set_image_info(info);
+
+    let update = match &photo.edits {
+        Some(edits) => {
+            let edits = mongodb::bson::to_bson(edits)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            set.insert("edits", edits);
+            doc! { "$set": set }
+        },
+        None => doc! { "$set": set, "$unset": { "edits": "" } },
+    };
+    collection
+        .update_one(doc! { "_id": object_id }, update, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save photo edits: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(photo.to_response())
+}
+
+/// Reports clusters of near-duplicate photos across the whole library
+///
+/// Only photos with a stored perceptual hash are compared; photos uploaded
+/// before hashing existed are hashed by a migration. Two photos end up in
+/// the same cluster when they are linked by a chain of near-duplicate pairs.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+///
+/// # Returns
+/// Returns the clusters, largest first; photos without duplicates are omitted
+pub async fn get_duplicate_report(
+    State(db): State<Arc<Database>>
+) -> Result<Json<Vec<DuplicateCluster>>, StatusCode> {
+    let photos_collection = db.collection::<Photo>("photos");
+
+    let mut category_names = HashMap::new();
+    let mut cursor = db.collection::<Category>("category").find(None, None).await.map_err(|e| {
+        eprintln!("Failed to query categories: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    while let Some(result) = cursor.next().await {
+        match result {
+            Ok(category) => {
+                if let Some(id) = category.id {
+                    category_names.insert(id, category.name);
+                }
+            },
+            Err(e) => eprintln!("Error reading category: {}", e),
+        }
+    }
+
+    let mut cursor = photos_collection.find(doc! { "phash": { "$exists": true } }, None).await.map_err(|e| {
+        eprintln!("Failed to query photos: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut hashed = Vec::new();
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                continue;
+            }
+        };
+        if let Some(hash) = photo.phash.as_deref().and_then(phash::from_hex) {
+            hashed.push((photo, hash));
+        }
+    }
+
+    let hashes: Vec<u64> = hashed.iter().map(|(_, hash)| *hash).collect();
+    let mut parent: Vec<usize> = (0..hashed.len()).collect();
+    let mut max_distance = vec![0u32; hashed.len()];
+
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for (i, j, distance) in phash::near_pairs(&hashes, phash::duplicate_threshold()) {
+        let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+        let merged = max_distance[a].max(max_distance[b]).max(distance);
+        if a != b {
+            parent[b] = a;
+        }
+        max_distance[a] = merged;
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..hashed.len() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(i);
+    }
+
+    let mut clusters: Vec<DuplicateCluster> = groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(r, members)| DuplicateCluster {
+            photos: members.into_iter().map(|i| {
+                let photo = &hashed[i].0;
+                let mut response = photo.to_response();
+                response.category_name = category_names.get(&photo.category_id)
+                    .cloned()
+                    .unwrap_or_else(|| "Unknown Category".to_string());
+                response
+            }).collect(),
+            max_distance: max_distance[r],
+        })
+        .collect();
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.photos.len()));
+
+    println!("🔍 Found {} near-duplicate clusters", clusters.len());
+    Ok(Json(clusters))
+}
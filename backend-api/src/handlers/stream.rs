@@ -0,0 +1,348 @@
+//! Range-aware file streaming
+//!
+//! Serves large media files with support for:
+//! - `Range` requests, answered with `206 Partial Content`
+//! - Multiple ranges, answered with `multipart/byteranges`
+//! - `If-Range`, `If-None-Match` and `If-Modified-Since` validators
+
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream::{self, BoxStream}, StreamExt, TryStreamExt};
+use std::{io::SeekFrom, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use tokio::{fs::File, io::{AsyncReadExt, AsyncSeekExt}};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Requests asking for more ranges than this are answered with the whole file
+const MAX_RANGES: usize = 32;
+
+/// Share of the file above which a request for several ranges is answered
+/// with the whole file
+const FULL_COVERAGE: f64 = 0.9;
+
+/// Byte ranges requested by a client, with inclusive ends
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable `Range` header; send the whole file
+    Full,
+    /// None of the ranges overlap the file
+    Unsatisfiable,
+    /// Satisfiable ranges, clamped to the file length
+    Ranges(Vec<(u64, u64)>),
+}
+
+/// Parses a `Range` header value against a file of `len` bytes
+///
+/// Malformed headers and units other than `bytes` are ignored, as
+/// RFC 9110 requires, which results in `RangeRequest::Full`.
+pub fn parse_range(value: &str, len: u64) -> RangeRequest {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            let Ok(suffix) = end.parse::<u64>() else { return RangeRequest::Full };
+            if suffix == 0 || len == 0 {
+                None
+            } else {
+                Some((len.saturating_sub(suffix), len - 1))
+            }
+        } else {
+            let Ok(start) = start.parse::<u64>() else { return RangeRequest::Full };
+            let end = if end.is_empty() {
+                u64::MAX
+            } else {
+                let Ok(end) = end.parse::<u64>() else { return RangeRequest::Full };
+                if end < start {
+                    return RangeRequest::Full;
+                }
+                end
+            };
+            (start < len).then(|| (start, end.min(len - 1)))
+        };
+
+        ranges.extend(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    // Overlapping and adjacent ranges are merged, as RFC 9110 allows, so
+    // no byte is sent twice
+    let requested = ranges.len();
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    // Several ranges covering most of the file cost more as multipart parts than the file itself
+    let covered: u64 = merged.iter().map(|(start, end)| end - start + 1).sum();
+    if requested > 1 && covered as f64 >= len as f64 * FULL_COVERAGE {
+        return RangeRequest::Full;
+    }
+    RangeRequest::Ranges(merged)
+}
+
+/// Whether an `If-Range` validator still matches the current file
+fn if_range_matches(value: &str, etag: &str, modified: SystemTime) -> bool {
+    let value = value.trim();
+    if value.starts_with('"') {
+        return value == etag;
+    }
+    if value.starts_with("W/") {
+        return false;
+    }
+    match httpdate::parse_http_date(value) {
+        Ok(date) => unix_seconds(date) == unix_seconds(modified),
+        Err(_) => false,
+    }
+}
+
+/// Whether an `If-None-Match` header lists the current entity tag
+fn none_match(value: &str, etag: &str) -> bool {
+    value.split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Streams `len` bytes of a file starting at `start`
+async fn file_range(path: PathBuf, start: u64, len: u64) -> std::io::Result<BoxStream<'static, std::io::Result<Bytes>>> {
+    let mut file = File::open(&path).await?;
+    file.seek(SeekFrom::Start(start)).await?;
+    Ok(ReaderStream::new(file.take(len)).boxed())
+}
+
+/// Builds a response for a file, honouring range and conditional request headers
+///
+/// # Arguments
+/// * `path` - File to serve
+/// * `content_type` - Value of the `Content-Type` header
+/// * `cache_control` - Value of the `Cache-Control` header
+/// * `headers` - Request headers
+///
+/// # Returns
+/// `200`, `206`, `304` or `416` as appropriate, or `404` if the file is missing
+pub async fn serve_file(
+    path: &Path,
+    content_type: &str,
+    cache_control: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let etag = format!("\"{:x}-{:x}\"", len, unix_seconds(modified));
+    let last_modified = httpdate::fmt_http_date(modified);
+
+    let response = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &last_modified)
+        .header(header::CACHE_CONTROL, cache_control);
+
+    let header_str = |name: header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let not_modified = match header_str(header::IF_NONE_MATCH) {
+        Some(value) => none_match(value, &etag),
+        None => header_str(header::IF_MODIFIED_SINCE)
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .is_some_and(|since| unix_seconds(modified) <= unix_seconds(since)),
+    };
+    if not_modified {
+        return response.status(StatusCode::NOT_MODIFIED)
+            .body(axum::body::boxed(axum::body::Empty::new()))
+            .unwrap();
+    }
+
+    let range_applies = header_str(header::IF_RANGE)
+        .is_none_or(|value| if_range_matches(value, &etag, modified));
+    let ranges = match header_str(header::RANGE) {
+        Some(value) if range_applies => parse_range(value, len),
+        _ => RangeRequest::Full,
+    };
+
+    let path = path.to_path_buf();
+    match ranges {
+        RangeRequest::Full => {
+            let body = match file_range(path, 0, len).await {
+                Ok(stream) => StreamBody::new(stream),
+                Err(_) => return StatusCode::NOT_FOUND.into_response(),
+            };
+            response.status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_LENGTH, len)
+                .body(axum::body::boxed(body))
+                .unwrap()
+        },
+        RangeRequest::Unsatisfiable => {
+            response.status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .body(axum::body::boxed(axum::body::Empty::new()))
+                .unwrap()
+        },
+        RangeRequest::Ranges(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            let body = match file_range(path, start, end - start + 1).await {
+                Ok(stream) => StreamBody::new(stream),
+                Err(_) => return StatusCode::NOT_FOUND.into_response(),
+            };
+            response.status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(axum::body::boxed(body))
+                .unwrap()
+        },
+        RangeRequest::Ranges(ranges) => {
+            let boundary = Uuid::new_v4().simple().to_string();
+
+            enum Part {
+                Bytes(Bytes),
+                File(u64, u64),
+            }
+
+            let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+            let mut content_length = 0;
+            for (i, (start, end)) in ranges.into_iter().enumerate() {
+                let head = format!(
+                    "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    if i == 0 { "" } else { "\r\n" }, boundary, content_type, start, end, len
+                );
+                content_length += head.len() as u64 + (end - start + 1);
+                parts.push(Part::Bytes(Bytes::from(head)));
+                parts.push(Part::File(start, end - start + 1));
+            }
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            content_length += tail.len() as u64;
+            parts.push(Part::Bytes(Bytes::from(tail)));
+
+            let body = stream::iter(parts)
+                .then(move |part| {
+                    let path = path.clone();
+                    async move {
+                        match part {
+                            Part::Bytes(bytes) => Ok(stream::once(async move { Ok(bytes) }).boxed()),
+                            Part::File(start, len) => file_range(path, start, len).await,
+                        }
+                    }
+                })
+                .try_flatten();
+
+            response.status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary)).unwrap(),
+                )
+                .header(header::CONTENT_LENGTH, content_length)
+                .body(axum::body::boxed(StreamBody::new(body)))
+                .unwrap()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), RangeRequest::Ranges(vec![(0, 99)]));
+        assert_eq!(parse_range("bytes=500-", 1000), RangeRequest::Ranges(vec![(500, 999)]));
+        assert_eq!(parse_range("bytes=900-5000", 1000), RangeRequest::Ranges(vec![(900, 999)]));
+        assert_eq!(parse_range(" bytes= 10 - 20 ", 1000), RangeRequest::Ranges(vec![(10, 20)]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), RangeRequest::Ranges(vec![(900, 999)]));
+        assert_eq!(parse_range("bytes=-5000", 1000), RangeRequest::Ranges(vec![(0, 999)]));
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn parses_multiple_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-9, 20-29,-5", 100),
+            RangeRequest::Ranges(vec![(0, 9), (20, 29), (95, 99)])
+        );
+        // Ranges past the end are dropped while the others are kept
+        assert_eq!(parse_range("bytes=0-9,200-300", 100), RangeRequest::Ranges(vec![(0, 9)]));
+    }
+
+    #[test]
+    fn coalesces_overlapping_ranges() {
+        assert_eq!(parse_range("bytes=50-59,0-9,5-14", 100), RangeRequest::Ranges(vec![(0, 14), (50, 59)]));
+        assert_eq!(parse_range("bytes=10-19,20-29,-5", 100), RangeRequest::Ranges(vec![(10, 29), (95, 99)]));
+        assert_eq!(parse_range("bytes=0-9,0-9,0-", 100), RangeRequest::Full);
+        // Several ranges covering most of the file are answered with all of it
+        assert_eq!(parse_range("bytes=0-49,50-89", 100), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=0-49,60-89", 100), RangeRequest::Ranges(vec![(0, 49), (60, 89)]));
+        assert_eq!(parse_range("bytes=0-", 100), RangeRequest::Ranges(vec![(0, 99)]));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=1000-2000,3000-4000", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for value in ["items=0-10", "bytes=abc", "bytes=10-5", "bytes=5", "bytes=-x", "0-10"] {
+            assert_eq!(parse_range(value, 1000), RangeRequest::Full, "{}", value);
+        }
+    }
+
+    #[test]
+    fn limits_the_number_of_ranges() {
+        let ranges = |count: u64| (0..count).map(|i| format!("{}-{}", i * 10, i * 10 + 4)).collect::<Vec<_>>().join(",");
+        match parse_range(&format!("bytes={}", ranges(MAX_RANGES as u64)), 10_000) {
+            RangeRequest::Ranges(ranges) => assert_eq!(ranges.len(), MAX_RANGES),
+            other => panic!("expected {} ranges, got {:?}", MAX_RANGES, other),
+        }
+        assert_eq!(parse_range(&format!("bytes={}", ranges(MAX_RANGES as u64 + 1)), 10_000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn matches_if_range_validators() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let etag = "\"3e8-6553f100\"";
+        let date = httpdate::fmt_http_date(modified);
+
+        assert!(if_range_matches(etag, etag, modified));
+        assert!(!if_range_matches("\"other\"", etag, modified));
+        // Weak tags never match, as If-Range needs a strong comparison
+        assert!(!if_range_matches(&format!("W/{}", etag), etag, modified));
+        assert!(if_range_matches(&date, etag, modified));
+        assert!(if_range_matches(&date, etag, modified + Duration::from_millis(500)));
+        assert!(!if_range_matches(&date, etag, modified + Duration::from_secs(1)));
+        assert!(!if_range_matches("not a date", etag, modified));
+    }
+}
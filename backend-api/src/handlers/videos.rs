@@ -5,47 +5,110 @@
 //! - Video retrieval
 //! - Video listing
 //! - Video deletion
+//! - Video streaming with range requests
+//! - Caption tracks and chapters
+//! - Clip extraction
+//! - Transcoding to a web-safe playback copy
 
 use axum::{
-    extract::{Multipart, State, Path as AxumPath},
+    extract::{Multipart, Query, State, Path as AxumPath},
     Json,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
 use mongodb::bson::oid::ObjectId;
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, fs, path::{Component, Path, PathBuf}, sync::Arc};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 use serde_json::json;
 use mongodb::Database;
-use crate::models::{Video, VideoResponse, Category};
+use crate::models::{Video, VideoResponse, Category, Job, JobStatus, ProcessingState};
+use crate::models::video::{
+    CaptionTrack, Chapter, CAPTIONS_FOLDER, CHAPTERS_FILENAME, CHAPTERS_FOLDER, CLIP_JOB, HLS_JOB, PREVIEW_JOB,
+    TRANSCODE_JOB,
+};
+use crate::handlers::{categories::CategoryQuery, common::{find_by_id, find_interrupted, json_error, set_job}, stream::serve_file};
+use crate::processing::{
+    clip,
+    container::Container,
+    faststart,
+    hls::{self, MASTER_PLAYLIST},
+    preview::{self, CHAPTER_THUMBNAIL_WIDTH, POSTER_FILENAME, SPRITE_FILENAME, THUMBNAILS_FILENAME},
+    transcode,
+    video as video_processing,
+    vtt,
+    ProcessingResult,
+};
 use futures_util::StreamExt;
 use mongodb::bson::doc;
+use serde::Deserialize;
 
 /// Directory where videos are stored
 pub const VIDEO_FOLDER: &str = "static/videos";
 
+/// Cache policy for streamed videos; stored files never change in place
+const VIDEO_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Cache policy for HLS files; every packaging run gets a new folder
+const HLS_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Largest accepted caption file
+const MAX_CAPTION_SIZE: usize = 2 * 1024 * 1024;
+
+/// Longest accepted caption track label
+const MAX_CAPTION_LABEL: usize = 100;
+
+/// Most chapters a video may have
+const MAX_CHAPTERS: usize = 100;
+
+/// Longest accepted chapter title
+const MAX_CHAPTER_TITLE: usize = 200;
+
+/// Names a new video file after a random ID and today's date
+fn new_video_filename(container: Container) -> String {
+    format!("video_{}_{}.{}",
+        Uuid::new_v4(),
+        chrono::Local::now().format("%Y%m%d"),
+        container.extension()
+    )
+}
+
 /// Handles video upload requests
+///
+/// The container is detected from the file contents, and the stored file
+/// gets the matching extension. Containers browsers cannot play are rejected.
+/// MP4 and MOV files are rewritten so that playback can start before they
+/// have fully downloaded. Videos with codecs browsers cannot play get an
+/// H.264/AAC playback copy, and a poster, a thumbnail strip and an HLS ladder
+/// are generated, all in the background.
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
 /// * `multipart` - Multipart form data containing video file and metadata
 /// 
 /// # Returns
-/// Returns the URL and filename of the uploaded video, or an error status
+/// Returns the URL, filename, container, codecs, duration and size of the
+/// uploaded video, or an error status with a JSON `error` message
 pub async fn upload_video(
     State(db): State<Arc<Database>>,
     mut multipart: Multipart
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
     let mut name = String::new();
     let mut category_id = String::new();
-    let mut saved_filename = String::new();
+    let mut upload_path: Option<PathBuf> = None;
+    let discard = |upload_path: &Option<PathBuf>| {
+        if let Some(path) = upload_path {
+            let _ = fs::remove_file(path);
+        }
+    };
 
     println!("Starting video upload...");
 
     while let Some(mut field) = multipart.next_field().await.map_err(|e| {
         eprintln!("Error getting next field: {}", e);
-        StatusCode::BAD_REQUEST
+        discard(&upload_path);
+        json_error(StatusCode::BAD_REQUEST, "invalid multipart body")
     })? {
         println!("Processing field: {:?}", field.name());
 
@@ -53,51 +116,57 @@ pub async fn upload_video(
             Some("name") => {
                 name = field.text().await.map_err(|e| {
                     eprintln!("Error reading name: {}", e);
-                    StatusCode::BAD_REQUEST
+                    discard(&upload_path);
+                    json_error(StatusCode::BAD_REQUEST, "invalid name field")
                 })?;
                 println!("Got name: {}", name);
             },
             Some("category") => {
                 category_id = field.text().await.map_err(|e| {
                     eprintln!("Error reading category: {}", e);
-                    StatusCode::BAD_REQUEST
+                    discard(&upload_path);
+                    json_error(StatusCode::BAD_REQUEST, "invalid category field")
                 })?;
                 println!("Got category_id: {}", category_id);
             },
             Some("file") => {
-                let filename = format!("video_{}_{}.mp4", 
-                    Uuid::new_v4(),
-                    chrono::Local::now().format("%Y%m%d")
-                );
-                
-                let filepath = format!("{}/{}", VIDEO_FOLDER, filename);
-                println!("📹 Saving video to: {}", filepath);
+                if upload_path.is_some() {
+                    discard(&upload_path);
+                    return Err(json_error(StatusCode::BAD_REQUEST, "only one file can be uploaded at a time"));
+                }
+                // The extension is only known once the container has been detected
+                let filepath = PathBuf::from(VIDEO_FOLDER).join(format!(".upload_{}", Uuid::new_v4()));
+                println!("📹 Saving video to: {}", filepath.display());
                 
                 if !PathBuf::from(VIDEO_FOLDER).exists() {
                     fs::create_dir_all(VIDEO_FOLDER).map_err(|e| {
                         eprintln!("Failed to create directory: {}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR
+                        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store video")
                     })?;
                 }
 
                 let mut file = File::create(&filepath).await.map_err(|e| {
                     eprintln!("Failed to create file: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
+                    json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store video")
                 })?;
+                upload_path = Some(filepath.clone());
 
-       
                 while let Some(chunk) = field.chunk().await.map_err(|e| {
                     eprintln!("Error reading chunk: {}", e);
-                    StatusCode::BAD_REQUEST
+                    let _ = fs::remove_file(&filepath);
+                    json_error(StatusCode::BAD_REQUEST, "upload was interrupted")
                 })? {
                     file.write_all(&chunk).await.map_err(|e| {
                         eprintln!("Error writing chunk: {}", e);
-                        StatusCode::INTERNAL_SERVER_ERROR
+                        let _ = fs::remove_file(&filepath);
+                        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store video")
                     })?;
                 }
-
-                saved_filename = filename;
-                println!("✅ Video saved successfully: {}", saved_filename);
+                file.flush().await.map_err(|e| {
+                    eprintln!("Error flushing file: {}", e);
+                    let _ = fs::remove_file(&filepath);
+                    json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store video")
+                })?;
             },
             _ => {
                 println!("Skipping unknown field: {:?}", field.name());
@@ -105,30 +174,108 @@ pub async fn upload_video(
         }
     }
 
-    if name.is_empty() || category_id.is_empty() || saved_filename.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+    let Some(upload_path) = upload_path else {
+        return Err(json_error(StatusCode::BAD_REQUEST, "missing file field"));
+    };
+    if name.is_empty() || category_id.is_empty() {
+        let _ = fs::remove_file(&upload_path);
+        return Err(json_error(StatusCode::BAD_REQUEST, "missing name or category"));
     }
 
+    let Ok(category_object_id) = ObjectId::parse_str(&category_id) else {
+        let _ = fs::remove_file(&upload_path);
+        return Err(json_error(StatusCode::BAD_REQUEST, "invalid category id"));
+    };
+
+    let probe_path = upload_path.clone();
+    let probe = tokio::task::spawn_blocking(move || video_processing::probe(&probe_path))
+        .await
+        .map_err(|e| e.into())
+        .and_then(|result| result);
+    let probe = match probe {
+        Ok(Some(probe)) if probe.container.is_supported() => probe,
+        Ok(detected) => {
+            let _ = fs::remove_file(&upload_path);
+            let detected = detected
+                .map(|probe| format!("{} files", probe.container.extension().to_uppercase()))
+                .unwrap_or_else(|| "this file type".to_string());
+            return Err(json_error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("{} are not supported; upload an MP4, MOV, WebM or MKV video", detected),
+            ));
+        },
+        Err(e) => {
+            eprintln!("Failed to probe video: {}", e);
+            let _ = fs::remove_file(&upload_path);
+            return Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to read video"));
+        }
+    };
 
-    let category_object_id = mongodb::bson::oid::ObjectId::parse_str(&category_id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    // Let playback start before the whole file has downloaded
+    if matches!(probe.container, Container::Mp4 | Container::QuickTime) {
+        let optimize_path = upload_path.clone();
+        let optimized = tokio::task::spawn_blocking(move || faststart::optimize(&optimize_path))
+            .await
+            .map_err(|e| e.into())
+            .and_then(|result| result);
+        match optimized {
+            Ok(true) => println!("⚡ Moved the moov box in front of the media data"),
+            Ok(false) => {},
+            Err(e) => eprintln!("⚠️ Could not optimise video for streaming: {}", e),
+        }
+    }
 
+    let saved_filename = new_video_filename(probe.container);
+    if let Err(e) = fs::rename(&upload_path, Path::new(VIDEO_FOLDER).join(&saved_filename)) {
+        eprintln!("Failed to move video into place: {}", e);
+        let _ = fs::remove_file(&upload_path);
+        return Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store video"));
+    }
+    println!("✅ Video saved successfully: {} ({:?}, {})",
+        saved_filename, probe.container, probe.codec.as_deref().unwrap_or("unknown codec"));
 
-    let video = Video::new(name, saved_filename.clone(), category_object_id);
+    let mut video = Video::new(name, saved_filename.clone(), category_object_id);
+    video.set_probe(probe.clone());
+    let needs_transcode = transcode::needs_transcode(probe.codec.as_deref(), probe.audio_codec.as_deref());
+    if needs_transcode {
+        video.jobs.insert(TRANSCODE_JOB.to_string(), Job::new(JobStatus::Queued));
+    }
+    video.jobs.insert(PREVIEW_JOB.to_string(), Job::new(JobStatus::Queued));
+    video.jobs.insert(HLS_JOB.to_string(), Job::new(JobStatus::Queued));
+    let jobs = video.jobs.clone();
     
     match db.collection::<Video>("videos")
         .insert_one(video, None)
         .await {
-        Ok(_) => {
+        Ok(result) => {
+            if let Some(id) = result.inserted_id.as_object_id() {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    if needs_transcode {
+                        transcode_video(db.clone(), id).await;
+                    }
+                    generate_preview(db.clone(), id).await;
+                    package_hls(db, id).await;
+                });
+            }
             let url = format!("/static/videos/{}", saved_filename);
             Ok(Json(json!({
                 "url": url,
-                "filename": saved_filename
+                "filename": saved_filename,
+                "container": probe.container,
+                "codec": probe.codec,
+                "audio_codec": probe.audio_codec,
+                "duration_ms": probe.duration_ms,
+                "width": probe.width,
+                "height": probe.height,
+                "processing_state": ProcessingState::of(&jobs),
+                "jobs": jobs
             })))
         },
         Err(e) => {
             eprintln!("Failed to save video to database: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+            let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(&saved_filename));
+            Err(json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save video"))
         }
     }
 }
@@ -148,7 +295,8 @@ pub async fn list_videos() -> Result<Json<Vec<String>>, StatusCode> {
             let videos = entries
                 .filter_map(|entry| {
                     entry.ok().and_then(|e| {
-                        if e.path().is_file() {
+                        // Uploads in progress are hidden until their container is known
+                        if e.path().is_file() && !e.file_name().to_string_lossy().starts_with('.') {
                             Some(format!("/static/videos/{}", 
                                 e.file_name().to_string_lossy()))
                         } else {
@@ -168,13 +316,16 @@ pub async fn list_videos() -> Result<Json<Vec<String>>, StatusCode> {
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
+/// * `query` - ID or slug of the category to list, e.g. `?category=landscapes`
 /// 
 /// # Returns
 /// Returns a list of video details with category information
 pub async fn get_videos(
-    State(db): State<Arc<Database>>
+    State(db): State<Arc<Database>>,
+    Query(query): Query<CategoryQuery>,
 ) -> Result<Json<Vec<VideoResponse>>, StatusCode> {
     println!("🎥 Fetching videos from MongoDB");
+    let media_filter = query.media_filter(&db).await?;
     
     let videos_collection = db.collection::<Video>("videos");
     let categories_collection = db.collection::<Category>("category");
@@ -198,7 +349,7 @@ pub async fn get_videos(
         }
     }
 
-    let mut cursor = videos_collection.find(None, None).await.map_err(|e| {
+    let mut cursor = videos_collection.find(media_filter, None).await.map_err(|e| {
         eprintln!("Failed to query videos: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
@@ -221,7 +372,7 @@ pub async fn get_videos(
     Ok(Json(videos))
 }
 
-/// Deletes a specific video
+/// Deletes a specific video along with its file and everything derived from it
 /// 
 /// # Arguments
 /// * `db` - MongoDB database connection
@@ -233,14 +384,1006 @@ pub async fn delete_video(
     State(db): State<Arc<Database>>,
     AxumPath(id): AxumPath<String>,
 ) -> Result<StatusCode, StatusCode> {
-    let object_id = ObjectId::parse_str(&id)
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let video = find_by_id::<Video>(&db, "videos", &id).await?;
 
     match db.collection::<Video>("videos")
-        .delete_one(doc! { "_id": object_id }, None)
+        .delete_one(doc! { "_id": video.id }, None)
         .await {
-        Ok(result) if result.deleted_count == 1 => Ok(StatusCode::NO_CONTENT),
+        Ok(result) if result.deleted_count == 1 => {
+            remove_video_files(&video);
+            Ok(StatusCode::NO_CONTENT)
+        },
         Ok(_) => Err(StatusCode::NOT_FOUND),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
+
+/// Removes the files of a deleted video: the uploaded file and its artifact
+/// folder, which holds the poster, thumbnails, HLS ladder, playback copy,
+/// captions and chapters
+pub(crate) fn remove_video_files(video: &Video) {
+    let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(&video.filename));
+    let _ = fs::remove_dir_all(video_processing::artifact_dir(&video.filename));
+}
+
+/// Streams a video with support for range requests
+///
+/// Browsers seek by requesting byte ranges, so this answers `Range`,
+/// multi-range and `If-Range` requests with `206 Partial Content`. The
+/// `Content-Type` is taken from the actual container, not the file name.
+/// Videos with a web-safe playback copy are streamed from that copy.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video to stream
+/// * `headers` - Request headers carrying the range and validators
+///
+/// # Returns
+/// The requested bytes of the video, or `NOT_FOUND` if it does not exist
+pub async fn stream_video(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Ok(object_id) = ObjectId::parse_str(&id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let video = match db.collection::<Video>("videos")
+        .find_one(doc! { "_id": object_id }, None)
+        .await {
+        Ok(Some(video)) => video,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            eprintln!("Failed to query video: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Some(playback) = &video.playback_filename {
+        return serve_file(&Path::new(VIDEO_FOLDER).join(playback), "video/mp4", VIDEO_CACHE_CONTROL, &headers).await;
+    }
+
+    let path = Path::new(VIDEO_FOLDER).join(&video.filename);
+    let container = match video.container {
+        Some(container) => Some(container),
+        None => {
+            let sniff_path = path.clone();
+            tokio::task::spawn_blocking(move || Container::sniff_file(&sniff_path))
+                .await
+                .ok()
+                .and_then(|result| result.ok().flatten())
+        }
+    };
+    let content_type = container
+        .map(|container| container.mime_type().to_string())
+        .unwrap_or_else(|| mime_guess::from_path(&path).first_or_octet_stream().to_string());
+
+    serve_file(&path, &content_type, VIDEO_CACHE_CONTROL, &headers).await
+}
+
+/// Generates the poster and thumbnail strip of a video with ffmpeg
+///
+/// Runs as a background job; its progress is recorded under `PREVIEW_JOB`.
+/// A custom poster chosen by the admin is kept.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+pub(crate) async fn generate_preview(db: Arc<Database>, id: ObjectId) {
+    let videos = db.collection::<Video>("videos");
+    let video = match videos.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query video: {}", e);
+            return;
+        }
+    };
+
+    set_job(&db, "videos", id, PREVIEW_JOB, Job::new(JobStatus::Running)).await;
+    println!("🎞️ Generating poster and thumbnails for {}", video.filename);
+
+    let filename = video.filename.clone();
+    let (duration_ms, size) = (video.duration_ms, video.width.zip(video.height));
+    let result = tokio::task::spawn_blocking(move || -> ProcessingResult<bool> {
+        let source = video_processing::video_path(&filename);
+        let folder = video_processing::artifact_dir(&filename);
+        fs::create_dir_all(&folder)?;
+
+        let poster_time = preview::default_poster_time(duration_ms);
+        preview::extract_poster(&source, &folder.join(POSTER_FILENAME), poster_time)?;
+
+        match (preview::thumbnail_interval(), duration_ms) {
+            (Some(interval), Some(duration)) => {
+                preview::render_thumbnails(&source, &folder, duration, size, interval)?;
+                Ok(true)
+            },
+            _ => {
+                let _ = fs::remove_file(folder.join(SPRITE_FILENAME));
+                let _ = fs::remove_file(folder.join(THUMBNAILS_FILENAME));
+                Ok(false)
+            }
+        }
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result);
+
+    let thumbnails = match result {
+        Ok(thumbnails) => thumbnails,
+        Err(e) => {
+            eprintln!("❌ Failed to generate preview of {}: {}", video.filename, e);
+            set_job(&db, "videos", id, PREVIEW_JOB, Job::failed(e.to_string())).await;
+            return;
+        }
+    };
+
+    let poster = video_processing::artifact_filename(&video.filename, POSTER_FILENAME);
+    let thumbnails = thumbnails.then(|| video_processing::artifact_filename(&video.filename, THUMBNAILS_FILENAME));
+    let updates = [
+        videos.update_one(
+            doc! { "_id": id, "poster_custom": { "$ne": true } },
+            doc! { "$set": { "poster_filename": poster } },
+            None,
+        ).await,
+        videos.update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "thumbnails_filename": thumbnails } },
+            None,
+        ).await,
+    ];
+    if let Some(Err(e)) = updates.into_iter().find(Result::is_err) {
+        eprintln!("Failed to save preview of video {}: {}", id, e);
+        set_job(&db, "videos", id, PREVIEW_JOB, Job::failed("failed to save preview")).await;
+        return;
+    }
+
+    set_job(&db, "videos", id, PREVIEW_JOB, Job::new(JobStatus::Done)).await;
+    println!("✅ Preview of {} generated", video.filename);
+}
+
+/// Queues the poster and thumbnail strip of a video to be generated again
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+///
+/// # Returns
+/// `ACCEPTED` once the job is queued
+pub async fn regenerate_preview(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await?;
+    let id = video.id.ok_or(StatusCode::NOT_FOUND)?;
+
+    set_job(&db, "videos", id, PREVIEW_JOB, Job::new(JobStatus::Queued)).await;
+    tokio::spawn(generate_preview(db, id));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": 1 }))))
+}
+
+/// Packages a video as HLS with ffmpeg
+///
+/// Runs as a background job; its progress is recorded under `HLS_JOB`. Each
+/// run writes a new folder, so cached segments of a previous run are never
+/// mixed with new ones. The previous folder is removed once the new one is
+/// in use.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+pub(crate) async fn package_hls(db: Arc<Database>, id: ObjectId) {
+    let videos = db.collection::<Video>("videos");
+    let video = match videos.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query video: {}", e);
+            return;
+        }
+    };
+
+    set_job(&db, "videos", id, HLS_JOB, Job::new(JobStatus::Running)).await;
+    println!("📺 Packaging {} as HLS", video.filename);
+
+    let folder = format!("hls_{}", &Uuid::new_v4().simple().to_string()[..8]);
+    let source = video_processing::video_path(&video.filename);
+    let destination = video_processing::artifact_dir(&video.filename).join(&folder);
+    let (size, has_audio) = (video.width.zip(video.height), video.audio_codec.is_some());
+    let result = tokio::task::spawn_blocking(move || -> ProcessingResult<()> {
+        fs::create_dir_all(destination.parent().ok_or("invalid HLS path")?)?;
+        hls::package(&source, &destination, size, has_audio)
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result);
+
+    if let Err(e) = result {
+        eprintln!("❌ Failed to package {} as HLS: {}", video.filename, e);
+        set_job(&db, "videos", id, HLS_JOB, Job::failed(e.to_string())).await;
+        return;
+    }
+
+    let playlist = video_processing::artifact_filename(&video.filename, &format!("{}/{}", folder, MASTER_PLAYLIST));
+    if let Err(e) = videos
+        .update_one(doc! { "_id": id }, doc! { "$set": { "hls_playlist": &playlist } }, None)
+        .await
+    {
+        eprintln!("Failed to save HLS playlist of video {}: {}", id, e);
+        let _ = fs::remove_dir_all(video_processing::artifact_dir(&video.filename).join(&folder));
+        set_job(&db, "videos", id, HLS_JOB, Job::failed("failed to save HLS playlist")).await;
+        return;
+    }
+
+    if let Some(previous) = video.hls_playlist.as_deref().and_then(|p| Path::new(p).parent()) {
+        let _ = fs::remove_dir_all(Path::new(VIDEO_FOLDER).join(previous));
+    }
+
+    set_job(&db, "videos", id, HLS_JOB, Job::new(JobStatus::Done)).await;
+    println!("✅ {} packaged as HLS", video.filename);
+}
+
+/// Queues a video to be packaged as HLS again
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+///
+/// # Returns
+/// `ACCEPTED` once the job is queued
+pub async fn regenerate_hls(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await?;
+    let id = video.id.ok_or(StatusCode::NOT_FOUND)?;
+
+    set_job(&db, "videos", id, HLS_JOB, Job::new(JobStatus::Queued)).await;
+    tokio::spawn(package_hls(db, id));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": 1 }))))
+}
+
+/// Request body for cutting a clip out of a video
+#[derive(Debug, Deserialize)]
+pub struct ClipRequest {
+    /// Start of the clip in milliseconds
+    pub start_ms: u64,
+    /// End of the clip in milliseconds
+    pub end_ms: u64,
+    /// Name of the clip; defaults to the name of the video
+    pub name: Option<String>,
+    /// Re-encode so the clip starts on the exact frame, instead of copying
+    /// the streams from the key frame before `start_ms`
+    #[serde(default)]
+    pub accurate: bool,
+}
+
+/// Cuts a clip out of a video as a new video in the same category
+///
+/// The clip is extracted by a background job recorded under `CLIP_JOB`;
+/// its poster, thumbnails and HLS ladder are generated once it is done.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the parent video
+/// * `body` - Time range and name of the clip
+///
+/// # Returns
+/// `ACCEPTED` with the new video, or an error status with a JSON `error` message
+pub async fn create_clip(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    Json(body): Json<ClipRequest>,
+) -> Result<(StatusCode, Json<VideoResponse>), (StatusCode, Json<serde_json::Value>)> {
+    let parent = find_by_id::<Video>(&db, "videos", &id).await
+        .map_err(|status| json_error(status, status.canonical_reason().unwrap_or("error")))?;
+    let parent_id = parent.id.ok_or_else(|| json_error(StatusCode::NOT_FOUND, "video not found"))?;
+
+    if parent.jobs.get(CLIP_JOB).is_some_and(|job| job.status != JobStatus::Done) {
+        return Err(json_error(StatusCode::CONFLICT, "the video is still being extracted"));
+    }
+    let duration = parent.duration_ms
+        .ok_or_else(|| json_error(StatusCode::UNPROCESSABLE_ENTITY, "the duration of the video is unknown"))?;
+    if body.end_ms <= body.start_ms {
+        return Err(json_error(StatusCode::BAD_REQUEST, "end_ms must be after start_ms"));
+    }
+    if body.end_ms > duration {
+        return Err(json_error(StatusCode::BAD_REQUEST, "end_ms is past the end of the video"));
+    }
+
+    // Copied streams stay in the container of the parent
+    let copy = !body.accurate && parent.container.is_some();
+    let container = parent.container.filter(|_| copy).unwrap_or(Container::Mp4);
+    let name = body.name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("{} (clip)", parent.name));
+
+    let mut video = Video::new(name, new_video_filename(container), parent.category_id);
+    video.container = Some(container);
+    video.parent_id = Some(parent_id);
+    video.clip_start_ms = Some(body.start_ms);
+    video.clip_end_ms = Some(body.end_ms);
+    video.jobs.insert(CLIP_JOB.to_string(), Job::new(JobStatus::Queued));
+
+    let result = db.collection::<Video>("videos")
+        .insert_one(&video, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save clip to database: {}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save clip")
+        })?;
+    video.id = result.inserted_id.as_object_id();
+
+    if let Some(clip_id) = video.id {
+        println!("✂️ Queued clip {} of video {}", clip_id, parent_id);
+        tokio::spawn(extract_clip(db, clip_id, copy));
+    }
+    Ok((StatusCode::ACCEPTED, Json(video.to_response())))
+}
+
+/// Extracts the file of a clip from its parent video with ffmpeg
+///
+/// Runs as a background job; its progress is recorded under `CLIP_JOB`.
+/// Once the file exists, the playback copy, if needed, the poster,
+/// thumbnails and HLS ladder follow.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the clip
+/// * `copy` - Whether to copy the streams instead of re-encoding them
+pub(crate) async fn extract_clip(db: Arc<Database>, id: ObjectId, copy: bool) {
+    let videos = db.collection::<Video>("videos");
+    let clip = match videos.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query clip: {}", e);
+            return;
+        }
+    };
+    let (Some(parent_id), Some(start_ms), Some(end_ms)) = (clip.parent_id, clip.clip_start_ms, clip.clip_end_ms) else {
+        set_job(&db, "videos", id, CLIP_JOB, Job::failed("video is not a clip")).await;
+        return;
+    };
+    let parent = match videos.find_one(doc! { "_id": parent_id }, None).await {
+        Ok(Some(parent)) => parent,
+        Ok(None) => {
+            set_job(&db, "videos", id, CLIP_JOB, Job::failed("the parent video no longer exists")).await;
+            return;
+        },
+        Err(e) => {
+            eprintln!("Failed to query parent video: {}", e);
+            set_job(&db, "videos", id, CLIP_JOB, Job::failed("failed to read the parent video")).await;
+            return;
+        }
+    };
+
+    set_job(&db, "videos", id, CLIP_JOB, Job::new(JobStatus::Running)).await;
+    println!("✂️ Extracting {} from {}", clip.filename, parent.filename);
+
+    let source = video_processing::video_path(&parent.filename);
+    let destination = video_processing::video_path(&clip.filename);
+    let probe_path = destination.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        clip::extract(&source, &destination, start_ms, end_ms, copy)?;
+        video_processing::probe(&probe_path)?.ok_or_else(|| "ffmpeg wrote an unreadable clip".into())
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result: ProcessingResult<_>| result);
+
+    let needs_transcode = result.as_ref()
+        .is_ok_and(|probe| transcode::needs_transcode(probe.codec.as_deref(), probe.audio_codec.as_deref()));
+    let probe = match result.and_then(|probe| Ok(mongodb::bson::to_document(&probe)?)) {
+        Ok(probe) => probe,
+        Err(e) => {
+            eprintln!("Failed to extract clip {}: {}", id, e);
+            let _ = fs::remove_file(video_processing::video_path(&clip.filename));
+            set_job(&db, "videos", id, CLIP_JOB, Job::failed(e.to_string())).await;
+            return;
+        }
+    };
+    if let Err(e) = videos.update_one(doc! { "_id": id }, doc! { "$set": probe }, None).await {
+        eprintln!("Failed to save clip metadata of video {}: {}", id, e);
+    }
+
+    set_job(&db, "videos", id, CLIP_JOB, Job::new(JobStatus::Done)).await;
+    if needs_transcode {
+        set_job(&db, "videos", id, TRANSCODE_JOB, Job::new(JobStatus::Queued)).await;
+    }
+    set_job(&db, "videos", id, PREVIEW_JOB, Job::new(JobStatus::Queued)).await;
+    set_job(&db, "videos", id, HLS_JOB, Job::new(JobStatus::Queued)).await;
+    println!("✅ Clip {} extracted", clip.filename);
+
+    if needs_transcode {
+        transcode_video(db.clone(), id).await;
+    }
+    generate_preview(db.clone(), id).await;
+    package_hls(db, id).await;
+}
+
+/// Transcodes a video to a web-safe H.264/AAC MP4 playback copy with ffmpeg
+///
+/// Runs as a background job; its progress is recorded under `TRANSCODE_JOB`.
+/// The uploaded file is kept untouched as the master. Every run writes a new
+/// file and removes the previous one, so cached copies never go stale.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+pub(crate) async fn transcode_video(db: Arc<Database>, id: ObjectId) {
+    let videos = db.collection::<Video>("videos");
+    let video = match videos.find_one(doc! { "_id": id }, None).await {
+        Ok(Some(video)) => video,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Failed to query video: {}", e);
+            return;
+        }
+    };
+
+    set_job(&db, "videos", id, TRANSCODE_JOB, Job::new(JobStatus::Running)).await;
+    println!("🔄 Transcoding {} to H.264/AAC", video.filename);
+
+    let playback = video_processing::artifact_filename(
+        &video.filename,
+        &format!("playback_{}.mp4", &Uuid::new_v4().simple().to_string()[..8]),
+    );
+    let source = video_processing::video_path(&video.filename);
+    let destination = Path::new(VIDEO_FOLDER).join(&playback);
+    let result = tokio::task::spawn_blocking(move || -> ProcessingResult<()> {
+        fs::create_dir_all(destination.parent().ok_or("invalid playback path")?)?;
+        transcode::transcode(&source, &destination)
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result);
+
+    if let Err(e) = result {
+        eprintln!("❌ Failed to transcode {}: {}", video.filename, e);
+        set_job(&db, "videos", id, TRANSCODE_JOB, Job::failed(e.to_string())).await;
+        return;
+    }
+
+    if let Err(e) = videos
+        .update_one(doc! { "_id": id }, doc! { "$set": { "playback_filename": &playback } }, None)
+        .await
+    {
+        eprintln!("Failed to save playback copy of video {}: {}", id, e);
+        let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(&playback));
+        set_job(&db, "videos", id, TRANSCODE_JOB, Job::failed("failed to save playback copy")).await;
+        return;
+    }
+
+    if let Some(previous) = &video.playback_filename {
+        let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(previous));
+    }
+
+    set_job(&db, "videos", id, TRANSCODE_JOB, Job::new(JobStatus::Done)).await;
+    println!("✅ {} transcoded", video.filename);
+}
+
+/// Queues a video to be transcoded to a web-safe playback copy again
+///
+/// Also works for videos whose codecs were considered playable, e.g. to
+/// replace a 10-bit H.264 file some browsers cannot decode.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+///
+/// # Returns
+/// `ACCEPTED` once the job is queued
+pub async fn regenerate_transcode(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await?;
+    let id = video.id.ok_or(StatusCode::NOT_FOUND)?;
+
+    set_job(&db, "videos", id, TRANSCODE_JOB, Job::new(JobStatus::Queued)).await;
+    tokio::spawn(transcode_video(db, id));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": 1 }))))
+}
+
+/// Starts the jobs of videos again that were queued or running when the
+/// server stopped
+///
+/// Called once at startup. Whether an interrupted clip was to copy its
+/// streams is not stored, so it is cut again with re-encoding, which is
+/// always frame-accurate; the jobs that follow a clip run as usual.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+pub async fn resume_jobs(db: Arc<Database>) {
+    let keys = [CLIP_JOB, TRANSCODE_JOB, PREVIEW_JOB, HLS_JOB];
+    let videos = find_interrupted::<Video>(&db, "videos", &keys).await;
+    if !videos.is_empty() {
+        println!("🔁 Resuming the jobs of {} videos", videos.len());
+    }
+
+    for video in videos {
+        let Some(id) = video.id else { continue };
+        let pending = |key: &str| video.jobs.get(key).is_some_and(|job| job.is_pending());
+        let (clip, transcode, preview, hls) = (pending(CLIP_JOB), pending(TRANSCODE_JOB), pending(PREVIEW_JOB), pending(HLS_JOB));
+        for key in keys.into_iter().filter(|&key| pending(key)) {
+            set_job(&db, "videos", id, key, Job::new(JobStatus::Queued)).await;
+        }
+
+        let db = db.clone();
+        tokio::spawn(async move {
+            if clip {
+                return extract_clip(db, id, false).await;
+            }
+            if transcode {
+                transcode_video(db.clone(), id).await;
+            }
+            if preview {
+                generate_preview(db.clone(), id).await;
+            }
+            if hls {
+                package_hls(db, id).await;
+            }
+        });
+    }
+}
+
+/// Serves HLS playlists and segments
+///
+/// # Arguments
+/// * `path` - Path of the file relative to the video folder, as in `hls_url`
+/// * `headers` - Request headers carrying the range and validators
+///
+/// # Returns
+/// The file with its HLS MIME type, or `NOT_FOUND` for anything that is not
+/// part of an HLS package
+pub async fn serve_hls(
+    AxumPath(path): AxumPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    let relative = Path::new(&path);
+    let in_package = relative.components().all(|c| matches!(c, Component::Normal(_)))
+        && relative.components().any(|c| c.as_os_str().to_string_lossy().starts_with("hls_"));
+    let content_type = match relative.extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    if !in_package {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    serve_file(&Path::new(VIDEO_FOLDER).join(relative), content_type, HLS_CACHE_CONTROL, &headers).await
+}
+
+/// Request body for picking the poster frame of a video
+#[derive(Debug, Deserialize)]
+pub struct PosterFrame {
+    /// Position of the frame in milliseconds
+    pub time_ms: u64,
+}
+
+/// Uses the frame at a given time as the poster of a video
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+/// * `body` - Position of the frame
+///
+/// # Returns
+/// The updated video, or an error status with a JSON `error` message
+pub async fn set_poster_frame(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    Json(body): Json<PosterFrame>,
+) -> Result<Json<VideoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await
+        .map_err(|status| json_error(status, status.canonical_reason().unwrap_or("error")))?;
+    if video.duration_ms.is_some_and(|duration| body.time_ms >= duration) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "time_ms is past the end of the video"));
+    }
+
+    let poster = format!("poster_{}.jpg", Uuid::new_v4().simple());
+    let source = video_processing::video_path(&video.filename);
+    let destination = video_processing::artifact_dir(&video.filename).join(&poster);
+    tokio::task::spawn_blocking(move || -> ProcessingResult<()> {
+        fs::create_dir_all(destination.parent().ok_or("invalid poster path")?)?;
+        preview::extract_poster(&source, &destination, body.time_ms)
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result)
+    .map_err(|e| {
+        eprintln!("Failed to extract poster frame: {}", e);
+        json_error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+    })?;
+
+    save_custom_poster(&db, video, &poster).await
+}
+
+/// Uploads an image to use as the poster of a video
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+/// * `multipart` - Multipart form data with the image in a `file` field
+///
+/// # Returns
+/// The updated video, or an error status with a JSON `error` message
+pub async fn upload_poster(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    mut multipart: Multipart,
+) -> Result<Json<VideoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await
+        .map_err(|status| json_error(status, status.canonical_reason().unwrap_or("error")))?;
+
+    let mut data = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        eprintln!("Error getting next field: {}", e);
+        json_error(StatusCode::BAD_REQUEST, "invalid multipart body")
+    })? {
+        if field.name() == Some("file") {
+            data = Some(field.bytes().await.map_err(|e| {
+                eprintln!("Failed to read poster data: {}", e);
+                json_error(StatusCode::BAD_REQUEST, "upload was interrupted")
+            })?);
+        }
+    }
+    let data = data.ok_or_else(|| json_error(StatusCode::BAD_REQUEST, "missing file field"))?;
+
+    let poster = format!("poster_{}.jpg", Uuid::new_v4().simple());
+    let destination = video_processing::artifact_dir(&video.filename).join(&poster);
+    tokio::task::spawn_blocking(move || preview::write_poster(&data, &destination))
+        .await
+        .map_err(|e| e.into())
+        .and_then(|result| result)
+        .map_err(|e| {
+            eprintln!("Failed to save uploaded poster: {}", e);
+            json_error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "poster must be a JPEG, PNG or WebP image")
+        })?;
+
+    save_custom_poster(&db, video, &poster).await
+}
+
+/// Attaches an SRT or WebVTT caption track to a video
+///
+/// SRT files are converted to WebVTT, and every cue must end after it
+/// starts, follow the previous cue and start before the video ends.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+/// * `multipart` - Multipart form data with the caption file in a `file`
+///   field, a BCP 47 `language` tag and an optional `label`
+///
+/// # Returns
+/// The updated video, or an error status with a JSON `error` message
+pub async fn upload_caption(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    mut multipart: Multipart,
+) -> Result<Json<VideoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let mut video = find_by_id::<Video>(&db, "videos", &id).await
+        .map_err(|status| json_error(status, status.canonical_reason().unwrap_or("error")))?;
+
+    let mut data = None;
+    let mut language = String::new();
+    let mut label = String::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        eprintln!("Error getting next field: {}", e);
+        json_error(StatusCode::BAD_REQUEST, "invalid multipart body")
+    })? {
+        match field.name() {
+            Some("file") => {
+                data = Some(field.bytes().await.map_err(|e| {
+                    eprintln!("Failed to read caption data: {}", e);
+                    json_error(StatusCode::BAD_REQUEST, "upload was interrupted")
+                })?);
+            },
+            Some("language") => {
+                language = field.text().await
+                    .map_err(|_| json_error(StatusCode::BAD_REQUEST, "invalid language field"))?
+                    .trim()
+                    .to_string();
+            },
+            Some("label") => {
+                label = field.text().await
+                    .map_err(|_| json_error(StatusCode::BAD_REQUEST, "invalid label field"))?
+                    .trim()
+                    .to_string();
+            },
+            _ => {}
+        }
+    }
+
+    let data = data.ok_or_else(|| json_error(StatusCode::BAD_REQUEST, "missing file field"))?;
+    if data.len() > MAX_CAPTION_SIZE {
+        return Err(json_error(StatusCode::PAYLOAD_TOO_LARGE, "caption files may be at most 2 MB"));
+    }
+    if !is_language_tag(&language) {
+        return Err(json_error(StatusCode::BAD_REQUEST, "language must be a language tag such as 'en' or 'pt-BR'"));
+    }
+    if label.is_empty() {
+        label = language.clone();
+    }
+    if label.chars().count() > MAX_CAPTION_LABEL {
+        return Err(json_error(StatusCode::BAD_REQUEST, "label may be at most 100 characters"));
+    }
+
+    let cues = vtt::parse(&vtt::decode(&data))
+        .and_then(|cues| vtt::validate(&cues, video.duration_ms).map(|_| cues))
+        .map_err(|e| json_error(StatusCode::UNPROCESSABLE_ENTITY, format!("invalid caption file: {}", e)))?;
+
+    let track_id = Uuid::new_v4().simple().to_string()[..8].to_string();
+    let track = CaptionTrack {
+        filename: video_processing::artifact_filename(
+            &video.filename,
+            &format!("{}/{}.vtt", CAPTIONS_FOLDER, track_id),
+        ),
+        id: track_id,
+        language,
+        label,
+    };
+    let path = Path::new(VIDEO_FOLDER).join(&track.filename);
+    vtt::write_file(&path, &cues).map_err(|e| {
+        eprintln!("Failed to write caption track: {}", e);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store caption track")
+    })?;
+
+    let document = mongodb::bson::to_document(&track).map_err(|e| {
+        eprintln!("Failed to serialize caption track: {}", e);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store caption track")
+    })?;
+    db.collection::<Video>("videos")
+        .update_one(doc! { "_id": video.id }, doc! { "$push": { "captions": document } }, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save caption track: {}", e);
+            let _ = fs::remove_file(&path);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to store caption track")
+        })?;
+
+    println!("💬 Added {} caption track {} to video {}", track.language, track.id, id);
+    video.captions.push(track);
+    Ok(Json(video.to_response()))
+}
+
+/// Removes a caption track from a video
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+/// * `track_id` - ID of the caption track
+///
+/// # Returns
+/// `NO_CONTENT` on success, or `NOT_FOUND` if the video has no such track
+pub async fn delete_caption(
+    State(db): State<Arc<Database>>,
+    AxumPath((id, track_id)): AxumPath<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let video = find_by_id::<Video>(&db, "videos", &id).await?;
+    let track = video.captions.iter()
+        .find(|track| track.id == track_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    db.collection::<Video>("videos")
+        .update_one(doc! { "_id": video.id }, doc! { "$pull": { "captions": { "id": &track.id } } }, None)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to remove caption track: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(&track.filename));
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// One chapter of a chapter list update
+#[derive(Debug, Deserialize)]
+pub struct ChapterInput {
+    /// Start of the chapter in milliseconds
+    pub time_ms: u64,
+    /// Title of the chapter
+    pub title: String,
+    /// Whether to show the frame at `time_ms` next to the title
+    #[serde(default)]
+    pub thumbnail: bool,
+}
+
+/// Request body replacing the chapters of a video
+#[derive(Debug, Deserialize)]
+pub struct ChapterList {
+    pub chapters: Vec<ChapterInput>,
+}
+
+/// Replaces the chapter markers of a video
+///
+/// Chapters must be in order and start before the video ends; each one
+/// runs until the next starts. The chapters are also written as a WebVTT
+/// chapters track. Thumbnails are kept for chapters whose start is unchanged.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `id` - ID of the video
+/// * `body` - The complete new chapter list; an empty list removes all chapters
+///
+/// # Returns
+/// The updated video, or an error status with a JSON `error` message
+pub async fn set_chapters(
+    State(db): State<Arc<Database>>,
+    AxumPath(id): AxumPath<String>,
+    Json(body): Json<ChapterList>,
+) -> Result<Json<VideoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let mut video = find_by_id::<Video>(&db, "videos", &id).await
+        .map_err(|status| json_error(status, status.canonical_reason().unwrap_or("error")))?;
+
+    if body.chapters.len() > MAX_CHAPTERS {
+        return Err(json_error(StatusCode::BAD_REQUEST, format!("a video may have at most {} chapters", MAX_CHAPTERS)));
+    }
+    let duration = video.duration_ms.filter(|&duration| duration > 0);
+    if duration.is_none() && !body.chapters.is_empty() {
+        return Err(json_error(StatusCode::UNPROCESSABLE_ENTITY, "the duration of the video is unknown"));
+    }
+
+    // Thumbnails are only extracted for chapters that did not have one at the same start
+    let existing: HashMap<u64, String> = video.chapters.iter()
+        .filter_map(|chapter| Some((chapter.time_ms, chapter.thumbnail_filename.clone()?)))
+        .collect();
+    let mut chapters = Vec::with_capacity(body.chapters.len());
+    let mut extract = Vec::new();
+    for (i, input) in body.chapters.into_iter().enumerate() {
+        let number = i + 1;
+        let title = input.title.split_whitespace().collect::<Vec<_>>().join(" ");
+        if title.is_empty() {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("chapter {} has no title", number)));
+        }
+        if title.chars().count() > MAX_CHAPTER_TITLE {
+            return Err(json_error(
+                StatusCode::BAD_REQUEST,
+                format!("chapter {} has a title longer than {} characters", number, MAX_CHAPTER_TITLE),
+            ));
+        }
+        if chapters.last().is_some_and(|previous: &Chapter| input.time_ms <= previous.time_ms) {
+            return Err(json_error(
+                StatusCode::BAD_REQUEST,
+                format!("chapter {} must start after the chapter preceding it", number),
+            ));
+        }
+        if duration.is_some_and(|duration| input.time_ms >= duration) {
+            return Err(json_error(StatusCode::BAD_REQUEST, format!("chapter {} starts after the video ends", number)));
+        }
+
+        let thumbnail_filename = match (input.thumbnail, existing.get(&input.time_ms)) {
+            (false, _) => None,
+            (true, Some(filename)) => Some(filename.clone()),
+            (true, None) => {
+                let thumbnail = format!("{}/{}.jpg", CHAPTERS_FOLDER, &Uuid::new_v4().simple().to_string()[..8]);
+                let filename = video_processing::artifact_filename(&video.filename, &thumbnail);
+                extract.push((input.time_ms, Path::new(VIDEO_FOLDER).join(&filename)));
+                Some(filename)
+            },
+        };
+        chapters.push(Chapter { time_ms: input.time_ms, title, thumbnail_filename });
+    }
+
+    let source = video_processing::video_path(&video.filename);
+    let extracted: Vec<PathBuf> = extract.iter().map(|(_, path)| path.clone()).collect();
+    tokio::task::spawn_blocking(move || -> ProcessingResult<()> {
+        for (time_ms, destination) in extract {
+            fs::create_dir_all(destination.parent().ok_or("invalid thumbnail path")?)?;
+            preview::extract_frame(&source, &destination, time_ms, CHAPTER_THUMBNAIL_WIDTH)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| e.into())
+    .and_then(|result| result)
+    .map_err(|e| {
+        eprintln!("Failed to extract chapter thumbnails: {}", e);
+        for path in &extracted {
+            let _ = fs::remove_file(path);
+        }
+        json_error(StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+    })?;
+
+    let chapters_filename = match (chapters.is_empty(), duration) {
+        (false, Some(duration)) => {
+            let cues: Vec<vtt::Cue> = chapters.iter().enumerate()
+                .map(|(i, chapter)| vtt::Cue {
+                    id: None,
+                    start_ms: chapter.time_ms,
+                    end_ms: chapters.get(i + 1).map_or(duration, |next| next.time_ms),
+                    settings: None,
+                    text: chapter.title.clone(),
+                })
+                .collect();
+            let filename = video_processing::artifact_filename(&video.filename, CHAPTERS_FILENAME);
+            vtt::write_file(&Path::new(VIDEO_FOLDER).join(&filename), &cues).map_err(|e| {
+                eprintln!("Failed to write chapters track: {}", e);
+                json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save chapters")
+            })?;
+            Some(filename)
+        },
+        _ => None,
+    };
+
+    let document = mongodb::bson::to_bson(&chapters).map_err(|e| {
+        eprintln!("Failed to serialize chapters: {}", e);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save chapters")
+    })?;
+    db.collection::<Video>("videos")
+        .update_one(
+            doc! { "_id": video.id },
+            doc! { "$set": { "chapters": document, "chapters_filename": &chapters_filename } },
+            None,
+        )
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save chapters: {}", e);
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save chapters")
+        })?;
+
+    for previous in video.chapters.iter().filter_map(|chapter| chapter.thumbnail_filename.as_ref()) {
+        if !chapters.iter().any(|chapter| chapter.thumbnail_filename.as_ref() == Some(previous)) {
+            let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(previous));
+        }
+    }
+    if chapters_filename.is_none() {
+        if let Some(previous) = &video.chapters_filename {
+            let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(previous));
+        }
+    }
+
+    println!("📑 Saved {} chapters for video {}", chapters.len(), id);
+    video.chapters = chapters;
+    video.chapters_filename = chapters_filename;
+    Ok(Json(video.to_response()))
+}
+
+/// Checks for a well-formed BCP 47 tag: a 2-8 letter language subtag
+/// followed by alphanumeric subtags of up to 8 characters
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=8).contains(&language.len())
+        && language.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|s| (1..=8).contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// Points a video at a custom poster and removes the custom poster it replaces
+async fn save_custom_poster(
+    db: &Database,
+    mut video: Video,
+    poster: &str,
+) -> Result<Json<VideoResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let id = video.id.ok_or_else(|| json_error(StatusCode::NOT_FOUND, "video not found"))?;
+    let poster_filename = video_processing::artifact_filename(&video.filename, poster);
+
+    db.collection::<Video>("videos")
+        .update_one(
+            doc! { "_id": id },
+            doc! { "$set": { "poster_filename": &poster_filename, "poster_custom": true } },
+            None,
+        )
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to save poster: {}", e);
+            let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(&poster_filename));
+            json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to save poster")
+        })?;
+
+    if let Some(previous) = video.poster_filename.replace(poster_filename) {
+        if video.poster_custom {
+            let _ = fs::remove_file(Path::new(VIDEO_FOLDER).join(previous));
+        }
+    }
+    video.poster_custom = true;
+
+    Ok(Json(video.to_response()))
+}
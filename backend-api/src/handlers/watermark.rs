@@ -0,0 +1,254 @@
+//! Watermark handling module
+//!
+//! Provides functionality for:
+//! - Reading and updating the watermark settings
+//! - Logo upload
+//! - Re-rendering the public photo derivatives after the watermark changed
+
+use axum::{
+    extract::{Multipart, State},
+    Json,
+    http::StatusCode,
+};
+use futures_util::StreamExt;
+use mongodb::{bson::{doc, Document}, options::ReplaceOptions, Database};
+use serde_json::json;
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+use uuid::Uuid;
+
+use crate::handlers::photos::render_photo;
+use crate::models::{Category, Photo, WatermarkSettings};
+use crate::processing::{photo::AnimatedRendition, watermark::Watermark, ProcessingResult};
+
+/// Private directory holding the uploaded watermark logo
+pub const WATERMARK_FOLDER: &str = "storage/watermark";
+
+/// `_id` of the watermark document in the `settings` collection
+const SETTINGS_ID: &str = "watermark";
+
+/// Loads the watermark settings, falling back to the defaults when none were saved yet
+pub async fn load_settings(db: &Database) -> mongodb::error::Result<WatermarkSettings> {
+    Ok(db.collection::<WatermarkSettings>("settings")
+        .find_one(doc! { "_id": SETTINGS_ID }, None)
+        .await?
+        .unwrap_or_default())
+}
+
+async fn save_settings(db: &Database, settings: &WatermarkSettings) -> mongodb::error::Result<()> {
+    db.collection::<WatermarkSettings>("settings")
+        .replace_one(
+            doc! { "_id": SETTINGS_ID },
+            settings,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Loads the settings and prepares the watermark for rendering
+///
+/// # Returns
+/// `None` when watermarking is disabled globally
+pub(crate) async fn load_watermark(db: &Database) -> ProcessingResult<Option<Arc<Watermark>>> {
+    let settings = load_settings(db).await?;
+    let watermark = tokio::task::spawn_blocking(move || Watermark::load(&settings)).await??;
+    Ok(watermark.map(Arc::new))
+}
+
+/// Re-renders the public derivatives of every photo matching the filter
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `filter` - Photo filter, `None` for the whole library
+///
+/// # Returns
+/// Number of derivatives rendered successfully
+pub(crate) async fn rerender_photos(db: Arc<Database>, filter: Option<Document>) -> usize {
+    let watermark = match load_watermark(&db).await {
+        Ok(watermark) => watermark,
+        Err(e) => {
+            eprintln!("❌ Failed to load watermark: {}", e);
+            return 0;
+        }
+    };
+
+    let mut category_watermark = HashMap::new();
+    if let Ok(mut cursor) = db.collection::<Category>("category").find(None, None).await {
+        while let Some(Ok(category)) = cursor.next().await {
+            if let Some(id) = category.id {
+                category_watermark.insert(id, category.watermark);
+            }
+        }
+    }
+
+    let mut cursor = match db.collection::<Photo>("photos").find(filter, None).await {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            eprintln!("❌ Failed to query photos: {}", e);
+            return 0;
+        }
+    };
+
+    let mut rendered = 0;
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                continue;
+            }
+        };
+        let apply = category_watermark.get(&photo.category_id).copied().unwrap_or(true);
+        let watermark = if apply { watermark.clone() } else { None };
+
+        match render_photo(photo.filename.clone(), photo.edits.clone(), watermark).await {
+            Ok(rendition) => {
+                rendered += 1;
+                if rendition.is_some() != photo.animated {
+                    record_animation(&db, photo, rendition).await;
+                }
+            },
+            Err(e) => eprintln!("❌ Failed to re-render {}: {}", photo.filename, e),
+        }
+    }
+
+    println!("🖼️ Re-rendered {} photo derivatives", rendered);
+    rendered
+}
+
+/// Stores the animation fields of a photo rendered before they existed
+async fn record_animation(db: &Database, mut photo: Photo, rendition: Option<AnimatedRendition>) {
+    let Some(id) = photo.id else { return };
+    photo.set_animation(rendition);
+    let update = doc! { "$set": {
+        "animated": photo.animated,
+        "frame_count": photo.frame_count.map(i64::from),
+        "duration_ms": photo.duration_ms.map(|d| d as i64),
+        "poster_filename": photo.poster_filename,
+        "animation_filename": photo.animation_filename,
+    } };
+    if let Err(e) = db.collection::<Photo>("photos").update_one(doc! { "_id": id }, update, None).await {
+        eprintln!("Failed to record animation of {}: {}", photo.filename, e);
+    }
+}
+
+/// Returns the current watermark settings
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+pub async fn get_watermark(
+    State(db): State<Arc<Database>>,
+) -> Result<Json<WatermarkSettings>, StatusCode> {
+    load_settings(&db).await.map(Json).map_err(|e| {
+        eprintln!("Failed to load watermark settings: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Updates the watermark settings
+///
+/// The logo cannot be changed here; it is kept from the stored settings
+/// and replaced through the logo upload endpoint.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `settings` - New watermark settings
+///
+/// # Returns
+/// The saved settings, or `BAD_REQUEST` with the reason when they are invalid
+pub async fn update_watermark(
+    State(db): State<Arc<Database>>,
+    Json(mut settings): Json<WatermarkSettings>,
+) -> Result<Json<WatermarkSettings>, (StatusCode, Json<serde_json::Value>)> {
+    let internal_error = |e: mongodb::error::Error| {
+        eprintln!("Failed to save watermark settings: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": "database error" })))
+    };
+
+    let current = load_settings(&db).await.map_err(internal_error)?;
+    settings.logo_filename = current.logo_filename;
+
+    settings.validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))))?;
+
+    save_settings(&db, &settings).await.map_err(internal_error)?;
+    Ok(Json(settings))
+}
+
+/// Handles watermark logo uploads
+///
+/// Accepts a PNG in the `file` field and replaces the previous logo.
+/// Existing photos keep the old logo until they are re-rendered.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+/// * `multipart` - Multipart form data containing the PNG logo
+pub async fn upload_logo(
+    State(db): State<Arc<Database>>,
+    mut multipart: Multipart,
+) -> Result<Json<WatermarkSettings>, StatusCode> {
+    let mut logo = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        eprintln!("Error getting next field: {}", e);
+        StatusCode::BAD_REQUEST
+    })? {
+        if field.name() == Some("file") {
+            logo = Some(field.bytes().await.map_err(|e| {
+                eprintln!("Failed to read logo data: {}", e);
+                StatusCode::BAD_REQUEST
+            })?);
+        }
+    }
+
+    let data = logo.ok_or(StatusCode::BAD_REQUEST)?;
+    if image::guess_format(&data).ok() != Some(image::ImageFormat::Png)
+        || image::load_from_memory_with_format(&data, image::ImageFormat::Png).is_err()
+    {
+        eprintln!("Watermark logo is not a valid PNG");
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    fs::create_dir_all(WATERMARK_FOLDER).map_err(|e| {
+        eprintln!("Failed to create directory: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let filename = format!("logo_{}.png", Uuid::new_v4());
+    fs::write(Path::new(WATERMARK_FOLDER).join(&filename), &data).map_err(|e| {
+        eprintln!("Failed to write logo: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut settings = load_settings(&db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let previous = settings.logo_filename.replace(filename);
+    save_settings(&db, &settings).await.map_err(|e| {
+        eprintln!("Failed to save watermark settings: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if let Some(previous) = previous {
+        let _ = fs::remove_file(Path::new(WATERMARK_FOLDER).join(previous));
+    }
+
+    Ok(Json(settings))
+}
+
+/// Re-renders the watermark on every published photo
+///
+/// The work runs in the background; the response only reports how many
+/// photos were queued.
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+pub async fn rerender_watermarks(
+    State(db): State<Arc<Database>>,
+) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
+    let queued = db.collection::<Photo>("photos")
+        .count_documents(None, None)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tokio::spawn(rerender_photos(db, None));
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "queued": queued }))))
+}
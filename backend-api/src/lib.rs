@@ -8,8 +8,12 @@
 //! - Request handlers for all API endpoints
 //! - Routing configuration
 //! - Database connection management
+//! - Database migrations
+//! - Media processing pipelines
 
 pub mod models;
 pub mod handlers;
 pub mod routes;
 pub mod db;
+pub mod migrations;
+pub mod processing;
@@ -2,6 +2,9 @@
 //! 
 //! Main application that:
 //! - Sets up the database connection
+//! - Applies pending database migrations
+//! - Creates the database indexes
+//! - Resumes background jobs interrupted by a restart
 //! - Initializes storage directories
 //! - Configures CORS
 //! - Starts the HTTP server
@@ -11,15 +14,12 @@ use tower_http::cors::{CorsLayer, Any};
 use axum::http::{Method, header};
 use std::sync::Arc;
 
-mod models;
-mod handlers;
-mod routes;
-mod db;
-
-use handlers::{
-    photos::PHOTO_FOLDER,
-    models::MODEL_FOLDER,
-    videos::VIDEO_FOLDER
+use backend_api::{db, migrations, routes};
+use backend_api::handlers::{
+    photos::{PHOTO_FOLDER, PHOTO_ORIGINALS_FOLDER},
+    models::{self, MODEL_FOLDER},
+    videos::{self, VIDEO_FOLDER},
+    watermark::WATERMARK_FOLDER,
 };
 
 /// Application entry point
@@ -27,6 +27,7 @@ use handlers::{
 /// Sets up and runs the backend API server with:
 /// - MongoDB connection
 /// - File storage directories
+/// - Database migrations and indexes
 /// - CORS configuration
 /// - HTTP server on 127.0.0.1:3000
 #[tokio::main]
@@ -37,10 +38,10 @@ async fn main() {
         .await
         .expect("Failed to connect to MongoDB");
    
-    let app_state = Arc::new(database);
-
     for dir in [
         PHOTO_FOLDER,
+        PHOTO_ORIGINALS_FOLDER,
+        WATERMARK_FOLDER,
         MODEL_FOLDER,
         VIDEO_FOLDER,
     ] {
@@ -50,11 +51,26 @@ async fn main() {
         }
     }
 
+    if let Err(e) = migrations::run(&database).await {
+        eprintln!("❌ Failed to apply migrations: {}", e);
+        return;
+    }
+
+    if let Err(e) = db::create_indexes(&database).await {
+        eprintln!("❌ Failed to create database indexes: {}", e);
+        return;
+    }
+
+    let app_state = Arc::new(database);
+    videos::resume_jobs(app_state.clone()).await;
+    models::resume_jobs(app_state.clone()).await;
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods([
             Method::GET,
             Method::POST,
+            Method::PUT,
             Method::DELETE,
             Method::OPTIONS  
         ])
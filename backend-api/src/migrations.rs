@@ -0,0 +1,350 @@
+//! Database migrations
+//!
+//! Migrations run in order at startup. Each one is recorded in the
+//! `migrations` collection once applied, so it only ever runs once per
+//! database. Migrations must also be safe to re-run, in case the server
+//! stops halfway through one.
+
+use futures_util::StreamExt;
+use mongodb::{bson::{doc, DateTime, Document}, options::FindOptions, Database};
+
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::handlers::{models::MODEL_FOLDER, photos::{hash_photo, inspect_photo}, videos::VIDEO_FOLDER};
+use crate::models::{Category, Model, Photo, Video};
+use crate::processing::{model_format, phash, photo as photo_processing, video as video_processing};
+
+/// All migrations, in the order they are applied
+const MIGRATIONS: &[&str] = &[
+    "0001_photo_phash",
+    "0002_photo_image_info",
+    "0003_photo_derived_folder",
+    "0004_video_metadata",
+    "0005_model_format",
+    "0006_model_gltf_stats",
+    "0007_category_slugs",
+];
+
+/// Applies every migration that has not been recorded yet
+///
+/// # Arguments
+/// * `db` - MongoDB database connection
+pub async fn run(db: &Database) -> mongodb::error::Result<()> {
+    let applied = db.collection::<Document>("migrations");
+
+    for &name in MIGRATIONS {
+        if applied.find_one(doc! { "_id": name }, None).await?.is_some() {
+            continue;
+        }
+
+        println!("🛠️ Applying migration {}", name);
+        match name {
+            "0001_photo_phash" => backfill_photo_phash(db).await?,
+            "0002_photo_image_info" => backfill_photo_image_info(db).await?,
+            "0003_photo_derived_folder" => move_photo_derived_files(db).await?,
+            "0004_video_metadata" => backfill_video_metadata(db).await?,
+            "0005_model_format" => backfill_model_format(db).await?,
+            "0006_model_gltf_stats" => backfill_model_gltf_stats(db).await?,
+            "0007_category_slugs" => backfill_category_slugs(db).await?,
+            _ => unreachable!("unknown migration {}", name),
+        }
+
+        applied
+            .insert_one(doc! { "_id": name, "applied_at": DateTime::now() }, None)
+            .await?;
+        println!("✅ Migration {} applied", name);
+    }
+
+    Ok(())
+}
+
+/// Computes the perceptual hash of photos uploaded before near-duplicate
+/// detection existed, so the duplicate report can compare them
+async fn backfill_photo_phash(db: &Database) -> mongodb::error::Result<()> {
+    let photos = db.collection::<Photo>("photos");
+    let mut cursor = photos.find(doc! { "phash": { "$exists": false } }, None).await?;
+
+    let (mut updated, mut failed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(id) = photo.id else { continue };
+
+        match hash_photo(photo.filename.clone()).await {
+            Ok(hash) => {
+                photos.update_one(doc! { "_id": id }, doc! { "$set": { "phash": phash::to_hex(hash) } }, None).await?;
+                updated += 1;
+            },
+            Err(e) => {
+                eprintln!("⚠️ Could not hash {}: {}", photo.filename, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("🔍 Backfilled perceptual hashes for {} photos ({} failed)", updated, failed);
+    Ok(())
+}
+
+/// Fills width, height, orientation and colour information of photos
+/// uploaded before those fields existed, by reading their stored files
+async fn backfill_photo_image_info(db: &Database) -> mongodb::error::Result<()> {
+    let photos = db.collection::<Photo>("photos");
+    let mut cursor = photos.find(doc! { "width": { "$exists": false } }, None).await?;
+
+    let (mut updated, mut failed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(id) = photo.id else { continue };
+
+        match inspect_photo(photo.filename.clone()).await {
+            Ok(info) => {
+                let set = mongodb::bson::to_document(&info)?;
+                photos.update_one(doc! { "_id": id }, doc! { "$set": set }, None).await?;
+                updated += 1;
+            },
+            Err(e) => {
+                eprintln!("⚠️ Could not inspect {}: {}", photo.filename, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("📐 Backfilled image info for {} photos ({} failed)", updated, failed);
+    Ok(())
+}
+
+/// Moves the posters and animated copies of animated photos, which used to
+/// be written next to the photos, into the derived files subfolder
+async fn move_photo_derived_files(db: &Database) -> mongodb::error::Result<()> {
+    let photos = db.collection::<Photo>("photos");
+    let mut cursor = photos.find(doc! { "animated": true }, None).await?;
+
+    let mut moved = 0;
+    while let Some(result) = cursor.next().await {
+        let photo = match result {
+            Ok(photo) => photo,
+            Err(e) => {
+                eprintln!("Error reading photo: {}", e);
+                continue;
+            }
+        };
+        let Some(id) = photo.id else { continue };
+
+        let mut set = Document::new();
+        for (field, filename) in [("poster_filename", &photo.poster_filename), ("animation_filename", &photo.animation_filename)] {
+            let Some(filename) = filename.as_deref() else { continue };
+            if filename == photo.filename || filename.contains('/') {
+                continue;
+            }
+            let path = format!("{}/{}", photo_processing::DERIVED_FOLDER, filename);
+            let (from, to) = (photo_processing::public_path(filename), photo_processing::public_path(&path));
+            if from.exists() {
+                if let Err(e) = fs::create_dir_all(to.parent().unwrap_or(Path::new("."))).and_then(|_| fs::rename(&from, &to)) {
+                    eprintln!("⚠️ Could not move {}: {}", from.display(), e);
+                    continue;
+                }
+            }
+            set.insert(field, path);
+        }
+        if !set.is_empty() {
+            photos.update_one(doc! { "_id": id }, doc! { "$set": set }, None).await?;
+            moved += 1;
+        }
+    }
+
+    println!("🎞️ Moved the derived files of {} animated photos", moved);
+    Ok(())
+}
+
+/// Fills container, codec, duration, size and bitrate of videos uploaded
+/// before those fields existed, by probing their stored files
+async fn backfill_video_metadata(db: &Database) -> mongodb::error::Result<()> {
+    let videos = db.collection::<Video>("videos");
+    let mut cursor = videos.find(doc! { "duration_ms": { "$exists": false } }, None).await?;
+
+    let (mut updated, mut failed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let video = match result {
+            Ok(video) => video,
+            Err(e) => {
+                eprintln!("Error reading video: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(id) = video.id else { continue };
+
+        let path = Path::new(VIDEO_FOLDER).join(&video.filename);
+        let probe = tokio::task::spawn_blocking(move || video_processing::probe(&path))
+            .await
+            .map_err(|e| e.into())
+            .and_then(|result| result);
+        match probe {
+            Ok(Some(probe)) => {
+                let set = mongodb::bson::to_document(&probe)?;
+                videos.update_one(doc! { "_id": id }, doc! { "$set": set }, None).await?;
+                updated += 1;
+            },
+            Ok(None) => {
+                eprintln!("⚠️ Unrecognised container: {}", video.filename);
+                failed += 1;
+            },
+            Err(e) => {
+                eprintln!("⚠️ Could not probe {}: {}", video.filename, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("🎞️ Backfilled metadata for {} videos ({} failed)", updated, failed);
+    Ok(())
+}
+
+/// Fills the format of models uploaded before it was detected, by
+/// validating their stored files
+///
+/// Files that fail validation are left without a format and logged, so
+/// they can be replaced by hand.
+async fn backfill_model_format(db: &Database) -> mongodb::error::Result<()> {
+    let models = db.collection::<Model>("models");
+    let mut cursor = models.find(doc! { "format": { "$exists": false } }, None).await?;
+
+    let (mut updated, mut failed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let model = match result {
+            Ok(model) => model,
+            Err(e) => {
+                eprintln!("Error reading model: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(id) = model.id else { continue };
+
+        let path = Path::new(MODEL_FOLDER).join(&model.filename);
+        let format = tokio::task::spawn_blocking(move || {
+            let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned());
+            model_format::detect(&path, extension.as_deref())
+        })
+        .await
+        .map_err(|e| e.into())
+        .and_then(|result| result);
+        match format {
+            Ok(format) => {
+                let format = mongodb::bson::to_bson(&format)?;
+                models.update_one(doc! { "_id": id }, doc! { "$set": { "format": format } }, None).await?;
+                updated += 1;
+            },
+            Err(e) => {
+                eprintln!("⚠️ Could not detect the format of {}: {}", model.filename, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("📦 Backfilled the format of {} models ({} failed)", updated, failed);
+    Ok(())
+}
+
+/// Fills the statistics of glTF and GLB models uploaded before they were
+/// recorded, by reading their stored files
+async fn backfill_model_gltf_stats(db: &Database) -> mongodb::error::Result<()> {
+    let models = db.collection::<Model>("models");
+    let filter = doc! { "format": { "$in": ["glb", "gltf"] }, "stats": { "$exists": false } };
+    let mut cursor = models.find(filter, None).await?;
+
+    let (mut updated, mut failed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let model = match result {
+            Ok(model) => model,
+            Err(e) => {
+                eprintln!("Error reading model: {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let (Some(id), Some(format)) = (model.id, model.format) else { continue };
+
+        let path = Path::new(MODEL_FOLDER).join(&model.filename);
+        let stats = tokio::task::spawn_blocking(move || model_format::gltf_stats(&path, format))
+            .await
+            .map_err(|e| e.into())
+            .and_then(|result| result);
+        match stats {
+            Ok(Some(stats)) => {
+                let stats = mongodb::bson::to_bson(&stats)?;
+                models.update_one(doc! { "_id": id }, doc! { "$set": { "stats": stats } }, None).await?;
+                updated += 1;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                eprintln!("⚠️ Could not read {}: {}", model.filename, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("📊 Backfilled statistics for {} models ({} failed)", updated, failed);
+    Ok(())
+}
+
+/// Cleans up category names and gives every category a slug, so the
+/// unique indexes on both can be created
+///
+/// Names are trimmed, empty names become `Untitled`, and names that
+/// differ only by case get a number, e.g. `Landscapes (2)`, oldest first.
+async fn backfill_category_slugs(db: &Database) -> mongodb::error::Result<()> {
+    let categories = db.collection::<Category>("category");
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+    let mut cursor = categories.find(None, options).await?;
+
+    let (mut names, mut slugs) = (HashSet::new(), HashSet::new());
+    let (mut updated, mut renamed) = (0, 0);
+    while let Some(result) = cursor.next().await {
+        let category = match result {
+            Ok(category) => category,
+            Err(e) => {
+                eprintln!("Error reading category: {}", e);
+                continue;
+            }
+        };
+        let Some(id) = category.id else { continue };
+
+        let base = category.name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let base = if base.is_empty() { "Untitled".to_string() } else { base };
+        let mut name = base.clone();
+        for number in 2.. {
+            if names.insert(name.to_lowercase()) {
+                break;
+            }
+            name = format!("{} ({})", base, number);
+        }
+        let slug = Category::slug_candidates(&name)
+            .find(|slug| slugs.insert(slug.clone()))
+            .unwrap_or_default();
+
+        if name != category.name {
+            println!("✏️ Renamed category '{}' to '{}'", category.name, name);
+            renamed += 1;
+        }
+        categories.update_one(doc! { "_id": id }, doc! { "$set": { "name": &name, "slug": &slug } }, None).await?;
+        updated += 1;
+    }
+
+    println!("🏷️ Backfilled slugs for {} categories ({} renamed)", updated, renamed);
+    Ok(())
+}
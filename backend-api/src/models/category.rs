@@ -3,7 +3,14 @@
 //! Defines the structure for content categories in the portfolio
 
 use serde::{Serialize, Deserialize};
-use mongodb::bson::oid::ObjectId;
+use mongodb::{bson::oid::ObjectId, options::{Collation, CollationStrength}};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Longest category name, in characters
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Slug of categories whose name has no Latin letter or digit
+const FALLBACK_SLUG: &str = "category";
 
 /// Represents a content category in the database
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -11,6 +18,118 @@ pub struct Category {
     /// MongoDB ObjectId, optional for new categories
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    /// Name of the category
+    /// Name of the category, unique regardless of case
     pub name: String,
+    /// Unique URL-safe identifier derived from the name, e.g. `landscapes`
+    #[serde(default)]
+    pub slug: String,
+    /// Whether photos in this category get the watermark on their public copy
+    #[serde(default = "default_watermark")]
+    pub watermark: bool,
+}
+
+fn default_watermark() -> bool {
+    true
+}
+
+impl Category {
+    /// Trims a category name and collapses the whitespace inside it
+    ///
+    /// # Returns
+    /// The cleaned name, or why it is not a valid name
+    pub fn normalize_name(name: &str) -> Result<String, String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if name.is_empty() {
+            return Err("name must not be empty".to_string());
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!("name must be at most {} characters", MAX_NAME_LENGTH));
+        }
+        Ok(name)
+    }
+
+    /// Derives the URL-safe slug of a category name
+    ///
+    /// Accents are dropped and every run of other characters becomes a
+    /// single `-`, so `Cafés & Bars` becomes `cafes-bars`. Names without
+    /// any Latin letter or digit get `category`.
+    pub fn slugify(name: &str) -> String {
+        let mut slug = String::new();
+        for c in name.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+            // Letters that do not decompose into a base letter and an accent
+            let latin = match c {
+                'æ' => "ae",
+                'œ' => "oe",
+                'ø' => "o",
+                'ß' => "ss",
+                'ł' => "l",
+                'đ' | 'ð' => "d",
+                'þ' => "th",
+                _ => "",
+            };
+            if c.is_ascii_alphanumeric() {
+                slug.push(c);
+            } else if !latin.is_empty() {
+                slug.push_str(latin);
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        let slug = slug.trim_end_matches('-');
+        if slug.is_empty() { FALLBACK_SLUG.to_string() } else { slug.to_string() }
+    }
+
+    /// Slugs to try, in order, for a category name: its slug, then the slug
+    /// numbered from 2 (`landscapes-2`) for when the previous ones are taken
+    pub fn slug_candidates(name: &str) -> impl Iterator<Item = String> {
+        let base = Self::slugify(name);
+        std::iter::once(base.clone()).chain((2..).map(move |number| format!("{}-{}", base, number)))
+    }
+
+    /// Collation under which category names are compared, ignoring case
+    pub fn name_collation() -> Collation {
+        Collation::builder().locale("en").strength(CollationStrength::Secondary).build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(Category::normalize_name("  Street \t  photography\n"), Ok("Street photography".to_string()));
+        assert!(Category::normalize_name(" \t ").is_err());
+        assert!(Category::normalize_name(&"é".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(Category::normalize_name(&"é".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn slugifies_names() {
+        assert_eq!(Category::slugify("Landscapes"), "landscapes");
+        assert_eq!(Category::slugify("Cafés & Bars"), "cafes-bars");
+        assert_eq!(Category::slugify("  --Black/White 2024!  "), "black-white-2024");
+        assert_eq!(Category::slugify("日本"), "category");
+        assert_eq!(Category::slugify(""), "category");
+    }
+
+    #[test]
+    fn folds_compatibility_characters_and_accents() {
+        // NFKD splits accents off their letters and turns ligatures and
+        // full-width or superscript forms into plain ones
+        assert_eq!(Category::slugify("Ångström Éclair"), "angstrom-eclair");
+        assert_eq!(Category::slugify("ﬁne ＡＲＴ ²"), "fine-art-2");
+        assert_eq!(Category::slugify("Œuvres d’Ærø"), "oeuvres-d-aero");
+        assert_eq!(Category::slugify("Straße in Łódź"), "strasse-in-lodz");
+    }
+
+    #[test]
+    fn numbers_colliding_slugs() {
+        let candidates: Vec<String> = Category::slug_candidates("Cafés & Bars").take(3).collect();
+        assert_eq!(candidates, ["cafes-bars", "cafes-bars-2", "cafes-bars-3"]);
+
+        let taken = ["travel", "travel-2", "travel-4"];
+        let slug = Category::slug_candidates("Travel").find(|slug| !taken.contains(&slug.as_str()));
+        assert_eq!(slug.as_deref(), Some("travel-3"));
+    }
 }
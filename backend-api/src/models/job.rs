@@ -0,0 +1,74 @@
+//! Background job model
+//!
+//! Tracks the state of background media processing, such as video poster
+//! extraction, so clients can show progress until the results are available
+
+use mongodb::bson::DateTime;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+/// Lifecycle of a background job
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Overall processing state of a media document, summarising its jobs
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingState {
+    /// A job is queued or running
+    Processing,
+    /// Every job is done
+    Ready,
+    /// No job is pending, and at least one failed
+    Failed,
+}
+
+/// State of one background job, stored on the media document it works on
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub status: JobStatus,
+    /// Why the job failed, for `JobStatus::Failed`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the status last changed
+    pub updated_at: DateTime,
+}
+
+impl Job {
+    /// Creates a job in the given state
+    pub fn new(status: JobStatus) -> Self {
+        Self { status, error: None, updated_at: DateTime::now() }
+    }
+
+    /// Creates a failed job
+    ///
+    /// # Arguments
+    /// * `error` - Reason shown to the admin
+    pub fn failed(error: impl Into<String>) -> Self {
+        Self { status: JobStatus::Failed, error: Some(error.into()), updated_at: DateTime::now() }
+    }
+
+    /// Whether the job is queued or running
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+impl ProcessingState {
+    /// Summarises the jobs of a media document, keyed by job name
+    pub fn of(jobs: &BTreeMap<String, Job>) -> Self {
+        if jobs.values().any(Job::is_pending) {
+            Self::Processing
+        } else if jobs.values().any(|job| job.status == JobStatus::Failed) {
+            Self::Failed
+        } else {
+            Self::Ready
+        }
+    }
+}
@@ -3,17 +3,23 @@
 //! This module contains all the data structures used in the application:
 //! - `admin`: Authentication and user management
 //! - `category`: Content categorization
+//! - `job`: Background processing state
 //! - `photo`: Photo storage and responses
 //! - `model`: 3D model storage and responses
 //! - `video`: Video storage and responses
+//! - `watermark`: Watermark settings for published photos
 
 pub mod admin;
 pub mod category;
+pub mod job;
 pub mod photo;
 pub mod model;
 pub mod video;
+pub mod watermark;
 
 pub use category::Category;
-pub use photo::{Photo, PhotoResponse};
-pub use model::{Model, ModelResponse};
+pub use job::{Job, JobStatus, ProcessingState};
+pub use photo::{Photo, PhotoEdits, PhotoResponse};
+pub use model::{Model, ModelResponse, ViewerPreset};
 pub use video::{Video, VideoResponse};
+pub use watermark::WatermarkSettings;
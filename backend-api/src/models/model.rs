@@ -4,6 +4,118 @@
 
 use mongodb::bson::{oid::ObjectId, DateTime};
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use crate::models::job::{Job, ProcessingState};
+use crate::processing::{gltf::GltfStats, model_format::ModelFormat, model_textures::TextureStats, splat::SplatStats};
+
+/// Job key of the thumbnail and turntable rendering
+pub const THUMBNAIL_JOB: &str = "thumbnail";
+
+/// Job key of the mesh optimization and level-of-detail generation
+pub const OPTIMIZE_JOB: &str = "optimize";
+
+/// An optimized copy of a GLB model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelLod {
+    /// Share of the triangles kept; 1 for the full-detail optimized copy
+    pub ratio: f64,
+    /// Filename of the GLB file, relative to the models folder
+    pub filename: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    pub triangle_count: u64,
+}
+
+/// Optimized copy of a model as returned by the API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelLodResponse {
+    pub ratio: f64,
+    pub url: String,
+    pub size: u64,
+    pub triangle_count: u64,
+}
+
+/// Axis that points up in a model
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxis {
+    /// The glTF convention
+    Y,
+    /// Common in CAD exports and photogrammetry scans
+    Z,
+}
+
+/// Viewer settings an admin curated for a model
+///
+/// Every setting is optional; the viewer uses its own default for those
+/// left out. Positions are in model units, before `scale` is applied.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ViewerPreset {
+    /// Position of the camera
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_position: Option<[f64; 3]>,
+    /// Point the camera looks at and orbits around
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_target: Option<[f64; 3]>,
+    /// Vertical field of view in degrees, between 1 and 179
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fov: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up_axis: Option<UpAxis>,
+    /// Uniform scale applied to the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    /// Name of the lighting environment, e.g. `studio`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// Whether the model spins until the visitor moves the camera
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_rotate: Option<bool>,
+    /// Background colour as `#rrggbb` or `#rrggbbaa`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+}
+
+/// Longest environment name a viewer preset may use
+const MAX_ENVIRONMENT_LENGTH: usize = 64;
+
+impl ViewerPreset {
+    /// Checks that every setting is within its allowed range
+    pub fn validate(&self) -> Result<(), String> {
+        let finite = |point: &[f64; 3]| point.iter().all(|v| v.is_finite());
+        if self.camera_position.as_ref().is_some_and(|p| !finite(p)) {
+            return Err("camera_position must be three finite numbers".to_string());
+        }
+        if self.camera_target.as_ref().is_some_and(|p| !finite(p)) {
+            return Err("camera_target must be three finite numbers".to_string());
+        }
+        if self.camera_position.is_some() && self.camera_position == self.camera_target {
+            return Err("camera_position and camera_target must differ".to_string());
+        }
+        if self.fov.is_some_and(|fov| !(1.0..=179.0).contains(&fov)) {
+            return Err("fov must be between 1 and 179 degrees".to_string());
+        }
+        if self.scale.is_some_and(|scale| !(scale.is_finite() && scale > 0.0)) {
+            return Err("scale must be greater than 0".to_string());
+        }
+        if let Some(environment) = &self.environment {
+            let valid = environment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if environment.is_empty() || environment.len() > MAX_ENVIRONMENT_LENGTH || !valid {
+                return Err(format!(
+                    "environment must be 1 to {} letters, digits, '-' or '_'",
+                    MAX_ENVIRONMENT_LENGTH,
+                ));
+            }
+        }
+        if let Some(color) = &self.background_color {
+            let hex = color.strip_prefix('#').unwrap_or_default();
+            if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err("background_color must be #rrggbb or #rrggbbaa".to_string());
+            }
+        }
+        Ok(())
+    }
+}
 
 /// Represents a 3D model in the database
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +131,33 @@ pub struct Model {
     pub category_id: ObjectId,
     /// Timestamp when the model was created
     pub created_at: DateTime,
+    /// Format detected from the file contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<ModelFormat>,
+    /// Mesh, material and animation counts and bounds of glTF and GLB models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<GltfStats>,
+    /// Splat count and size reduction of Gaussian splat models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub splat: Option<SplatStats>,
+    /// Image count and size reduction of GLB models whose textures were compressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub textures: Option<TextureStats>,
+    /// Rendered PNG thumbnail, relative to the models folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_filename: Option<String>,
+    /// Rendered turntable GIF, relative to the models folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub turntable_filename: Option<String>,
+    /// Optimized copies of GLB models, from full detail to the lightest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lods: Vec<ModelLod>,
+    /// Viewer settings the model opens with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viewer: Option<ViewerPreset>,
+    /// State of the background jobs run on the model, by job key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, Job>,
 }
 
 /// API response structure for 3D models
@@ -31,6 +170,25 @@ pub struct ModelResponse {
     pub category_id: String,
     pub category_name: String,
     pub created_at: DateTime,
+    pub format: Option<ModelFormat>,
+    /// Contents of glTF and GLB models, e.g. for "120k triangles, 3 animations"
+    pub stats: Option<GltfStats>,
+    /// Splat count of `.splat` models and, for converted PLY scans, how
+    /// much smaller the stored file is than the upload
+    pub splat: Option<SplatStats>,
+    /// How many textures were downscaled or re-encoded on upload, and the
+    /// size of the upload next to that of the stored file
+    pub textures: Option<TextureStats>,
+    pub thumbnail_url: Option<String>,
+    pub turntable_url: Option<String>,
+    /// Optimized copies, from full detail to the lightest, so viewers can
+    /// load a light one first
+    pub lods: Vec<ModelLodResponse>,
+    /// Curated framing and look; missing settings use the viewer defaults
+    pub viewer: Option<ViewerPreset>,
+    /// Summary of `jobs`; failed jobs carry their `error`
+    pub processing_state: ProcessingState,
+    pub jobs: BTreeMap<String, Job>,
 }
 
 impl Model {
@@ -47,6 +205,15 @@ impl Model {
             filename,
             category_id,
             created_at: DateTime::now(),
+            format: None,
+            stats: None,
+            splat: None,
+            textures: None,
+            thumbnail_filename: None,
+            turntable_filename: None,
+            lods: Vec::new(),
+            viewer: None,
+            jobs: BTreeMap::new(),
         }
     }
 
@@ -60,6 +227,28 @@ impl Model {
             category_id: self.category_id.to_string(),
             category_name: String::new(),  
             created_at: self.created_at,
+            format: self.format,
+            stats: self.stats.clone(),
+            splat: self.splat,
+            textures: self.textures,
+            thumbnail_url: self.thumbnail_filename.as_ref().map(|f| format!("/static/models/{}", f)),
+            turntable_url: self.turntable_filename.as_ref().map(|f| format!("/static/models/{}", f)),
+            lods: self.lods.iter().map(ModelLod::to_response).collect(),
+            viewer: self.viewer.clone(),
+            processing_state: ProcessingState::of(&self.jobs),
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl ModelLod {
+    /// Converts the ModelLod into a ModelLodResponse
+    pub fn to_response(&self) -> ModelLodResponse {
+        ModelLodResponse {
+            ratio: self.ratio,
+            url: format!("/static/models/{}", self.filename),
+            size: self.size,
+            triangle_count: self.triangle_count,
         }
     }
 }
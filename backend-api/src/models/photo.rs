@@ -4,6 +4,7 @@
 
 use mongodb::bson::{oid::ObjectId, DateTime};
 use serde::{Serialize, Deserialize};
+use crate::processing::{image_info::ImageInfo, photo::AnimatedRendition};
 
 /// Represents a photo in the database
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +20,135 @@ pub struct Photo {
     pub category_id: ObjectId,
     /// Timestamp when the photo was created
     pub created_at: DateTime,
+    /// Non-destructive edit recipe applied when rendering the public copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edits: Option<PhotoEdits>,
+    /// Perceptual hash of the original as 16 hex digits, used to find near-duplicates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+    /// Width of the published image in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height of the published image in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// `width / height` of the published image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<f64>,
+    /// `landscape`, `portrait` or `square`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<String>,
+    /// EXIF orientation (1-8) of the original
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exif_orientation: Option<u16>,
+    /// Bits per channel of the original
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_depth: Option<u8>,
+    /// Pixel layout of the original, e.g. `rgb8`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_type: Option<String>,
+    /// ICC profile description of the original, `sRGB` when untagged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_space: Option<String>,
+    /// Whether the original is an animated GIF, APNG or WebP
+    #[serde(default)]
+    pub animated: bool,
+    /// Number of frames of an animated photo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_count: Option<u32>,
+    /// Playback time of one loop of an animated photo, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Still first-frame poster of an animated photo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_filename: Option<String>,
+    /// Public animated copy of an animated photo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation_filename: Option<String>,
+}
+
+/// Crop rectangle, expressed as fractions of the rotated and flipped image
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct CropRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Point of interest, expressed as fractions of the edited image
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FocalPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Edit recipe stored on a photo
+///
+/// The original file is never modified; the recipe is replayed on it every
+/// time the public copy is rendered, in this order: rotation, flips, crop,
+/// then exposure, contrast and saturation.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PhotoEdits {
+    /// Crop applied after rotating and flipping
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
+    /// Clockwise rotation in degrees; one of 0, 90, 180 or 270
+    #[serde(default)]
+    pub rotation: u16,
+    /// Mirror left to right
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    /// Mirror top to bottom
+    #[serde(default)]
+    pub flip_vertical: bool,
+    /// Exposure change in stops, between -3.0 and 3.0
+    #[serde(default)]
+    pub exposure: f32,
+    /// Contrast change between -1.0 and 1.0
+    #[serde(default)]
+    pub contrast: f32,
+    /// Saturation change between -1.0 (greyscale) and 1.0
+    #[serde(default)]
+    pub saturation: f32,
+    /// Point the frontend should keep visible when cropping thumbnails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+}
+
+impl PhotoEdits {
+    /// Checks that every value of the recipe is within its allowed range
+    pub fn validate(&self) -> Result<(), String> {
+        if ![0, 90, 180, 270].contains(&self.rotation) {
+            return Err("rotation must be 0, 90, 180 or 270".to_string());
+        }
+        if let Some(crop) = &self.crop {
+            let within = |v: f32| (0.0..=1.0).contains(&v);
+            if !(within(crop.x) && within(crop.y))
+                || crop.width <= 0.0
+                || crop.height <= 0.0
+                || crop.x + crop.width > 1.0 + f32::EPSILON
+                || crop.y + crop.height > 1.0 + f32::EPSILON
+            {
+                return Err("crop must be a non-empty rectangle inside the image (fractions 0.0 to 1.0)".to_string());
+            }
+        }
+        if !(-3.0..=3.0).contains(&self.exposure) {
+            return Err("exposure must be between -3.0 and 3.0".to_string());
+        }
+        if !(-1.0..=1.0).contains(&self.contrast) {
+            return Err("contrast must be between -1.0 and 1.0".to_string());
+        }
+        if !(-1.0..=1.0).contains(&self.saturation) {
+            return Err("saturation must be between -1.0 and 1.0".to_string());
+        }
+        if let Some(focal_point) = &self.focal_point {
+            if !(0.0..=1.0).contains(&focal_point.x) || !(0.0..=1.0).contains(&focal_point.y) {
+                return Err("focal_point must be within 0.0 and 1.0".to_string());
+            }
+        }
+        Ok(())
+    }
 }
 
 /// API response structure for photos
@@ -31,6 +161,20 @@ pub struct PhotoResponse {
     pub category_id: String,
     pub category_name: String, 
     pub created_at: DateTime,
+    pub edits: Option<PhotoEdits>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub aspect_ratio: Option<f64>,
+    pub orientation: Option<String>,
+    pub exif_orientation: Option<u16>,
+    pub bit_depth: Option<u8>,
+    pub color_type: Option<String>,
+    pub color_space: Option<String>,
+    pub animated: bool,
+    pub frame_count: Option<u32>,
+    pub duration_ms: Option<u64>,
+    pub poster_url: Option<String>,
+    pub animation_url: Option<String>,
 }
 
 impl Photo {
@@ -47,9 +191,45 @@ impl Photo {
             filename,
             category_id,
             created_at: DateTime::now(),
+            edits: None,
+            phash: None,
+            width: None,
+            height: None,
+            aspect_ratio: None,
+            orientation: None,
+            exif_orientation: None,
+            bit_depth: None,
+            color_type: None,
+            color_space: None,
+            animated: false,
+            frame_count: None,
+            duration_ms: None,
+            poster_filename: None,
+            animation_filename: None,
         }
     }
 
+    /// Records the animated renditions produced for the photo, if any
+    pub fn set_animation(&mut self, rendition: Option<AnimatedRendition>) {
+        self.animated = rendition.is_some();
+        self.frame_count = rendition.as_ref().map(|r| r.info.frame_count);
+        self.duration_ms = rendition.as_ref().map(|r| r.info.duration_ms);
+        self.poster_filename = rendition.as_ref().map(|r| r.poster_filename.clone());
+        self.animation_filename = rendition.and_then(|r| r.animation_filename);
+    }
+
+    /// Copies inspected layout and colour information onto the photo
+    pub fn set_image_info(&mut self, info: ImageInfo) {
+        self.width = Some(info.width);
+        self.height = Some(info.height);
+        self.aspect_ratio = Some(info.aspect_ratio);
+        self.orientation = Some(info.orientation);
+        self.exif_orientation = info.exif_orientation;
+        self.bit_depth = Some(info.bit_depth);
+        self.color_type = Some(info.color_type);
+        self.color_space = Some(info.color_space);
+    }
+
     /// Converts the Photo into a PhotoResponse
     pub fn to_response(&self) -> PhotoResponse {
         PhotoResponse {
@@ -60,6 +240,22 @@ impl Photo {
             category_id: self.category_id.to_string(),
             category_name: String::new(), 
             created_at: self.created_at,
+            edits: self.edits.clone(),
+            width: self.width,
+            height: self.height,
+            aspect_ratio: self.aspect_ratio,
+            orientation: self.orientation.clone(),
+            exif_orientation: self.exif_orientation,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type.clone(),
+            color_space: self.color_space.clone(),
+            animated: self.animated,
+            frame_count: self.frame_count,
+            duration_ms: self.duration_ms,
+            poster_url: self.poster_filename.as_ref()
+                .map(|f| format!("/static/photos/{}", f)),
+            animation_url: self.animation_filename.as_ref()
+                .map(|f| format!("/static/photos/{}", f)),
         }
     }
 }
@@ -4,6 +4,74 @@
 
 use mongodb::bson::{oid::ObjectId, DateTime};
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use crate::models::job::{Job, ProcessingState};
+use crate::processing::{container::Container, video::VideoProbe};
+
+/// Job key of the poster and thumbnail strip generation
+pub const PREVIEW_JOB: &str = "preview";
+
+/// Job key of the HLS packaging
+pub const HLS_JOB: &str = "hls";
+
+/// Job key of the transcoding to a web-safe playback copy
+pub const TRANSCODE_JOB: &str = "transcode";
+
+/// Job key of the extraction of a clip from its parent video
+pub const CLIP_JOB: &str = "clip";
+
+/// Folder inside a video's artifact folder holding its caption tracks
+pub const CAPTIONS_FOLDER: &str = "captions";
+
+/// Folder inside a video's artifact folder holding chapter thumbnails
+pub const CHAPTERS_FOLDER: &str = "chapters";
+
+/// Filename of the WebVTT chapters track inside a video's artifact folder
+pub const CHAPTERS_FILENAME: &str = "chapters.vtt";
+
+/// A chapter marker on the timeline of a video
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    /// Start of the chapter in milliseconds; it runs until the next one starts
+    pub time_ms: u64,
+    /// Title shown in the chapter list
+    pub title: String,
+    /// Frame at the start of the chapter, relative to the video folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_filename: Option<String>,
+}
+
+/// Chapter marker as returned by the API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChapterResponse {
+    pub time_ms: u64,
+    /// Start of the next chapter, or the end of the video
+    pub end_ms: Option<u64>,
+    pub title: String,
+    pub thumbnail_url: Option<String>,
+}
+
+/// A WebVTT caption or subtitle track of a video
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptionTrack {
+    /// Identifier of the track within its video
+    pub id: String,
+    /// BCP 47 language tag, e.g. `en` or `pt-BR`
+    pub language: String,
+    /// Name shown in the player's caption menu
+    pub label: String,
+    /// WebVTT file, relative to the video folder
+    pub filename: String,
+}
+
+/// Caption track as returned by the API
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptionTrackResponse {
+    pub id: String,
+    pub language: String,
+    pub label: String,
+    pub url: String,
+}
 
 /// Represents a video in the database
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +87,68 @@ pub struct Video {
     pub category_id: ObjectId,
     /// Timestamp when the video was created
     pub created_at: DateTime,
+    /// Container detected from the file contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<Container>,
+    /// Codec of the first video track, e.g. `h264` or `vp9`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Codec of the first audio track, e.g. `aac` or `opus`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<String>,
+    /// Playback duration in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Display width in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Display height in pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Average frames per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<f64>,
+    /// Average bitrate in bits per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    /// Poster image, relative to the video folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poster_filename: Option<String>,
+    /// Whether the poster was picked or uploaded by the admin, and must not
+    /// be replaced by the generated one
+    #[serde(default)]
+    pub poster_custom: bool,
+    /// WebVTT index of the thumbnail strip, relative to the video folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnails_filename: Option<String>,
+    /// HLS master playlist, relative to the video folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hls_playlist: Option<String>,
+    /// H.264/AAC MP4 copy for videos browsers cannot play, relative to the
+    /// video folder; the uploaded file is then only kept as the master
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playback_filename: Option<String>,
+    /// Video this one was cut from, if it is a clip
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<ObjectId>,
+    /// Start of the clip in the parent video, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip_start_ms: Option<u64>,
+    /// End of the clip in the parent video, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip_end_ms: Option<u64>,
+    /// Caption tracks, in upload order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub captions: Vec<CaptionTrack>,
+    /// Chapter markers, ordered by start time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    /// WebVTT chapters track, relative to the video folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chapters_filename: Option<String>,
+    /// State of the background jobs run on the video, by job key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, Job>,
 }
 
 /// API response structure for videos
@@ -27,10 +157,43 @@ pub struct VideoResponse {
     pub id: String,
     pub name: String,
     pub filename: String,
+    /// Playable file: the web-safe playback copy if there is one, otherwise the upload
     pub url: String,
+    /// Originally uploaded file, for download
+    pub master_url: String,
+    /// Range-capable streaming endpoint for the video
+    pub stream_url: String,
     pub category_id: String,
     pub category_name: String,
     pub created_at: DateTime,
+    pub container: Option<Container>,
+    pub codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// `width / height`, for sizing the player before playback starts
+    pub aspect_ratio: Option<f64>,
+    pub frame_rate: Option<f64>,
+    pub bitrate: Option<u64>,
+    pub poster_url: Option<String>,
+    pub poster_custom: bool,
+    /// WebVTT index of thumbnails for hover-scrubbing previews
+    pub thumbnails_url: Option<String>,
+    /// HLS master playlist, available once the `hls` job is done
+    pub hls_url: Option<String>,
+    /// Video this one was cut from, if it is a clip
+    pub parent_id: Option<String>,
+    pub clip_start_ms: Option<u64>,
+    pub clip_end_ms: Option<u64>,
+    /// Caption tracks for `<track kind="captions">` elements
+    pub captions: Vec<CaptionTrackResponse>,
+    pub chapters: Vec<ChapterResponse>,
+    /// WebVTT track for `<track kind="chapters">` elements
+    pub chapters_url: Option<String>,
+    /// Summary of `jobs`; failed jobs carry their `error`
+    pub processing_state: ProcessingState,
+    pub jobs: BTreeMap<String, Job>,
 }
 
 impl Video {
@@ -47,19 +210,95 @@ impl Video {
             filename,
             category_id,
             created_at: DateTime::now(),
+            container: None,
+            codec: None,
+            audio_codec: None,
+            duration_ms: None,
+            width: None,
+            height: None,
+            frame_rate: None,
+            bitrate: None,
+            poster_filename: None,
+            poster_custom: false,
+            thumbnails_filename: None,
+            hls_playlist: None,
+            playback_filename: None,
+            parent_id: None,
+            clip_start_ms: None,
+            clip_end_ms: None,
+            captions: Vec::new(),
+            chapters: Vec::new(),
+            chapters_filename: None,
+            jobs: BTreeMap::new(),
         }
     }
 
+    /// Records the container, codecs and playback information detected by `video::probe`
+    pub fn set_probe(&mut self, probe: VideoProbe) {
+        self.container = Some(probe.container);
+        self.codec = probe.codec;
+        self.audio_codec = probe.audio_codec;
+        self.duration_ms = probe.duration_ms;
+        self.width = probe.width;
+        self.height = probe.height;
+        self.frame_rate = probe.frame_rate;
+        self.bitrate = probe.bitrate;
+    }
+
     /// Converts the Video into a VideoResponse
     pub fn to_response(&self) -> VideoResponse {
         VideoResponse {
             id: self.id.unwrap_or_default().to_string(),
             name: self.name.clone(),
             filename: self.filename.clone(),
-            url: format!("/static/videos/{}", self.filename),
+            url: format!("/static/videos/{}", self.playback_filename.as_ref().unwrap_or(&self.filename)),
+            master_url: format!("/static/videos/{}", self.filename),
+            stream_url: format!("/api/videos/{}/stream", self.id.unwrap_or_default()),
             category_id: self.category_id.to_string(),
             category_name: String::new(), 
             created_at: self.created_at,
+            container: self.container,
+            codec: self.codec.clone(),
+            audio_codec: self.audio_codec.clone(),
+            duration_ms: self.duration_ms,
+            width: self.width,
+            height: self.height,
+            aspect_ratio: self.width.zip(self.height)
+                .filter(|&(_, height)| height > 0)
+                .map(|(width, height)| width as f64 / height as f64),
+            frame_rate: self.frame_rate,
+            bitrate: self.bitrate,
+            poster_url: self.poster_filename.as_ref().map(|f| format!("/static/videos/{}", f)),
+            poster_custom: self.poster_custom,
+            thumbnails_url: self.thumbnails_filename.as_ref().map(|f| format!("/static/videos/{}", f)),
+            hls_url: self.hls_playlist.as_ref().map(|f| format!("/api/videos/hls/{}", f)),
+            parent_id: self.parent_id.map(|id| id.to_string()),
+            clip_start_ms: self.clip_start_ms,
+            clip_end_ms: self.clip_end_ms,
+            captions: self.captions.iter().map(CaptionTrack::to_response).collect(),
+            chapters: self.chapters.iter().enumerate()
+                .map(|(i, chapter)| ChapterResponse {
+                    time_ms: chapter.time_ms,
+                    end_ms: self.chapters.get(i + 1).map(|next| next.time_ms).or(self.duration_ms),
+                    title: chapter.title.clone(),
+                    thumbnail_url: chapter.thumbnail_filename.as_ref().map(|f| format!("/static/videos/{}", f)),
+                })
+                .collect(),
+            chapters_url: self.chapters_filename.as_ref().map(|f| format!("/static/videos/{}", f)),
+            processing_state: ProcessingState::of(&self.jobs),
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl CaptionTrack {
+    /// Converts the CaptionTrack into a CaptionTrackResponse
+    pub fn to_response(&self) -> CaptionTrackResponse {
+        CaptionTrackResponse {
+            id: self.id.clone(),
+            language: self.language.clone(),
+            label: self.label.clone(),
+            url: format!("/static/videos/{}", self.filename),
         }
     }
 }
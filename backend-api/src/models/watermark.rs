@@ -0,0 +1,91 @@
+//! Watermark settings model
+//!
+//! Defines the global watermark configuration applied to public photo derivatives
+
+use serde::{Serialize, Deserialize};
+
+/// Kind of mark stamped onto published photos
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkKind {
+    /// Renders `WatermarkSettings::text` with the configured font
+    Text,
+    /// Composites the uploaded PNG logo
+    Logo,
+}
+
+/// Anchor point of the watermark inside the photo
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+/// Global watermark configuration, stored as a single document in the `settings` collection
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatermarkSettings {
+    /// Master switch; when false no photo is watermarked regardless of category
+    pub enabled: bool,
+    /// Whether the text or the logo is used
+    pub kind: WatermarkKind,
+    /// Text to render for `WatermarkKind::Text`
+    #[serde(default)]
+    pub text: String,
+    /// Filename of the uploaded logo inside the watermark folder, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo_filename: Option<String>,
+    /// Where the mark is placed
+    pub position: WatermarkPosition,
+    /// Opacity between 0.0 (invisible) and 1.0 (opaque)
+    pub opacity: f32,
+    /// Width of the mark as a fraction of the photo width
+    pub scale: f32,
+    /// Distance from the photo edges as a fraction of the photo width
+    pub margin: f32,
+}
+
+impl Default for WatermarkSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: WatermarkKind::Text,
+            text: String::new(),
+            logo_filename: None,
+            position: WatermarkPosition::BottomRight,
+            opacity: 0.5,
+            scale: 0.2,
+            margin: 0.02,
+        }
+    }
+}
+
+impl WatermarkSettings {
+    /// Checks that the numeric settings are within range and the selected kind has content
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.opacity) {
+            return Err("opacity must be between 0.0 and 1.0".to_string());
+        }
+        if !(self.scale > 0.0 && self.scale <= 1.0) {
+            return Err("scale must be greater than 0.0 and at most 1.0".to_string());
+        }
+        if !(0.0..0.5).contains(&self.margin) {
+            return Err("margin must be between 0.0 and 0.5".to_string());
+        }
+        if self.enabled {
+            match self.kind {
+                WatermarkKind::Text if self.text.trim().is_empty() => {
+                    return Err("text watermark requires non-empty text".to_string());
+                }
+                WatermarkKind::Logo if self.logo_filename.is_none() => {
+                    return Err("logo watermark requires an uploaded logo".to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
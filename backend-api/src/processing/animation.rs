@@ -0,0 +1,150 @@
+//! Animated image handling
+//!
+//! Detects animated GIF, APNG and animated WebP originals and re-encodes
+//! them frame by frame, so edits and watermarks apply to every frame
+//! instead of flattening the animation into a still image.
+//!
+//! GIF and APNG keep their own format. No pure-Rust encoder for animated
+//! WebP is available, so those are published untouched when nothing changes
+//! them, and otherwise as a still WebP, optionally with an animated GIF
+//! rendition.
+
+use image::{
+    codecs::{gif::{GifDecoder, GifEncoder, Repeat}, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, DynamicImage, Frame, Frames, ImageFormat, RgbaImage,
+};
+use std::{fs::File, io::{BufReader, BufWriter, Write}, path::Path};
+
+use super::ProcessingResult;
+
+/// Encoding speed handed to the GIF quantizer (1 = best quality, 30 = fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Frame count and total playback time of an animated original
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationInfo {
+    pub frame_count: u32,
+    pub duration_ms: u64,
+}
+
+/// Opens the frames of an animated file
+fn open_frames(path: &Path, format: ImageFormat) -> ProcessingResult<Option<Frames<'static>>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(match format {
+        ImageFormat::Gif => Some(GifDecoder::new(reader)?.into_frames()),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            Some(decoder.apng().into_frames())
+        },
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            Some(decoder.into_frames())
+        },
+        _ => None,
+    })
+}
+
+/// Detects whether an image is animated
+///
+/// # Returns
+/// `None` for still images, including single-frame GIFs and APNGs
+pub fn probe(path: &Path) -> ProcessingResult<Option<AnimationInfo>> {
+    let format = ImageFormat::from_path(path)?;
+    let Some(frames) = open_frames(path, format)? else {
+        return Ok(None);
+    };
+
+    let mut info = AnimationInfo { frame_count: 0, duration_ms: 0 };
+    for frame in frames {
+        let (numer, denom) = frame?.delay().numer_denom_ms();
+        info.frame_count += 1;
+        info.duration_ms += numer as u64 / denom.max(1) as u64;
+    }
+
+    Ok((info.frame_count > 1).then_some(info))
+}
+
+/// Decodes the first frame of an animated image
+pub fn first_frame(path: &Path) -> ProcessingResult<DynamicImage> {
+    let format = ImageFormat::from_path(path)?;
+    match open_frames(path, format)?.and_then(|mut frames| frames.next()) {
+        Some(frame) => Ok(DynamicImage::ImageRgba8(frame?.into_buffer())),
+        None => Ok(image::open(path)?),
+    }
+}
+
+/// Re-encodes an animation, passing every frame through `transform`
+///
+/// # Arguments
+/// * `source` - Animated original
+/// * `destination` - File to write; must not be the original
+/// * `output` - `ImageFormat::Gif` or `ImageFormat::Png` (APNG)
+/// * `info` - Result of `probe` for the original
+/// * `transform` - Per-frame processing; must return frames of a constant size
+pub fn render(
+    source: &Path,
+    destination: &Path,
+    output: ImageFormat,
+    info: AnimationInfo,
+    transform: impl Fn(DynamicImage) -> DynamicImage,
+) -> ProcessingResult<()> {
+    let format = ImageFormat::from_path(source)?;
+    let frames = open_frames(source, format)?.ok_or("image is not animated")?;
+    let mut writer = BufWriter::new(File::create(destination)?);
+
+    match output {
+        ImageFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(&mut writer, GIF_SPEED);
+            encoder.set_repeat(Repeat::Infinite)?;
+            for frame in frames {
+                let frame = frame?;
+                let delay = frame.delay();
+                let buffer = transform(DynamicImage::ImageRgba8(frame.into_buffer())).to_rgba8();
+                encoder.encode_frame(Frame::from_parts(buffer, 0, 0, delay))?;
+            }
+        },
+        ImageFormat::Png => {
+            let mut frames = frames.map(|frame| -> ProcessingResult<(RgbaImage, (u32, u32))> {
+                let frame = frame?;
+                let delay = frame.delay().numer_denom_ms();
+                Ok((transform(DynamicImage::ImageRgba8(frame.into_buffer())).to_rgba8(), delay))
+            });
+            let first = frames.next().ok_or("animation has no frames")??;
+
+            let mut encoder = png::Encoder::new(&mut writer, first.0.width(), first.0.height());
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(info.frame_count, 0)?;
+            let mut apng = encoder.write_header()?;
+
+            for frame in std::iter::once(Ok(first)).chain(frames) {
+                let (buffer, (numer, denom)) = frame?;
+                let (numer, denom) = delay_fraction(numer, denom);
+                apng.set_frame_delay(numer, denom)?;
+                apng.write_image_data(buffer.as_raw())?;
+            }
+            apng.finish()?;
+        },
+        _ => return Err(format!("cannot encode animations as {:?}", output).into()),
+    }
+
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// Fits a millisecond delay fraction into the 16-bit APNG fields
+fn delay_fraction(numer: u32, denom: u32) -> (u16, u16) {
+    let ms = numer as f64 / denom.max(1) as f64;
+    if ms <= u16::MAX as f64 {
+        ((ms.round() as u16).max(1), 1000)
+    } else {
+        ((ms / 1000.0).round().min(u16::MAX as f64) as u16, 1)
+    }
+}
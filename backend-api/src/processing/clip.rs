@@ -0,0 +1,55 @@
+//! Clip extraction
+//!
+//! Cuts a time range out of a video. Stream copying keeps the original
+//! quality and takes seconds, but can only start on a key frame; re-encoding
+//! to H.264/AAC MP4 starts on the exact frame requested.
+
+use std::{ffi::OsString, path::Path};
+
+use super::{ffmpeg, preview, ProcessingResult};
+
+/// Writes the range `start_ms..end_ms` of a video to a new file
+///
+/// The output format is picked from the extension of `destination`, which
+/// is written through a temporary file and never left half-written.
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `destination` - Clip to create
+/// * `start_ms` - Start of the range in milliseconds
+/// * `end_ms` - End of the range in milliseconds
+/// * `copy` - Whether to copy the streams instead of re-encoding them; the
+///   clip then starts on the key frame at or before `start_ms`
+pub fn extract(source: &Path, destination: &Path, start_ms: u64, end_ms: u64, copy: bool) -> ProcessingResult<()> {
+    if end_ms <= start_ms {
+        return Err("clip ends before it starts".into());
+    }
+
+    let mut args: Vec<OsString> = vec![
+        "-ss".into(), ffmpeg::seconds(start_ms).into(),
+        "-i".into(), source.into(),
+        "-t".into(), ffmpeg::seconds(end_ms - start_ms).into(),
+        "-map".into(), "0:v:0".into(),
+        "-map".into(), "0:a:0?".into(),
+        "-map_metadata".into(), "-1".into(),
+    ];
+    if copy {
+        args.extend(["-c".into(), "copy".into(), "-avoid_negative_ts".into(), "make_zero".into()]);
+    } else {
+        args.extend([
+            "-c:v".into(), "libx264".into(),
+            "-preset".into(), "medium".into(),
+            "-crf".into(), "20".into(),
+            "-pix_fmt".into(), "yuv420p".into(),
+            "-c:a".into(), "aac".into(),
+            "-b:a".into(), "192k".into(),
+        ]);
+    }
+    if matches!(destination.extension().and_then(|e| e.to_str()), Some("mp4" | "mov")) {
+        args.extend(["-movflags".into(), "+faststart".into()]);
+    }
+    preview::replace_atomically(destination, |tmp| {
+        args.push(tmp.into());
+        ffmpeg::run(args)
+    })
+}
@@ -0,0 +1,91 @@
+//! Video container detection
+//!
+//! Identifies the container of a video from its first bytes rather than
+//! trusting the file extension.
+
+use serde::{Serialize, Deserialize};
+use std::{fs::File, io::Read, path::Path};
+
+/// Number of leading bytes needed to recognise every supported container
+/// (an MPEG-TS file is only recognised by its second sync byte at offset 188)
+pub const SNIFF_LEN: usize = 512;
+
+/// Video container formats recognised from their magic bytes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    Mp4,
+    QuickTime,
+    WebM,
+    Matroska,
+    Avi,
+    Ogg,
+    MpegTs,
+}
+
+impl Container {
+    /// Detects the container from the leading bytes of a file
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.len() >= 12 && &header[4..8] == b"ftyp" {
+            return Some(if &header[8..12] == b"qt  " { Container::QuickTime } else { Container::Mp4 });
+        }
+        if header.len() >= 8 && matches!(&header[4..8], b"moov" | b"mdat" | b"wide" | b"free" | b"skip") {
+            return Some(Container::QuickTime);
+        }
+        if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            let is_webm = header.windows(4).any(|w| w == b"webm");
+            return Some(if is_webm { Container::WebM } else { Container::Matroska });
+        }
+        if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"AVI " {
+            return Some(Container::Avi);
+        }
+        if header.starts_with(b"OggS") {
+            return Some(Container::Ogg);
+        }
+        if header.first() == Some(&0x47) && header.get(188) == Some(&0x47) {
+            return Some(Container::MpegTs);
+        }
+        None
+    }
+
+    /// Detects the container of a file on disk
+    pub fn sniff_file(path: &Path) -> std::io::Result<Option<Self>> {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+        Ok(Self::sniff(&header))
+    }
+
+    /// Whether uploads in this container are accepted
+    ///
+    /// These are the containers browsers can play directly, and the ones
+    /// `video::probe` can read tracks from.
+    pub fn is_supported(self) -> bool {
+        matches!(self, Container::Mp4 | Container::QuickTime | Container::WebM | Container::Matroska)
+    }
+
+    /// MIME type browsers expect for the container
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Container::Mp4 => "video/mp4",
+            Container::QuickTime => "video/quicktime",
+            Container::WebM => "video/webm",
+            Container::Matroska => "video/x-matroska",
+            Container::Avi => "video/x-msvideo",
+            Container::Ogg => "video/ogg",
+            Container::MpegTs => "video/mp2t",
+        }
+    }
+
+    /// Conventional file extension of the container
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::QuickTime => "mov",
+            Container::WebM => "webm",
+            Container::Matroska => "mkv",
+            Container::Avi => "avi",
+            Container::Ogg => "ogv",
+            Container::MpegTs => "ts",
+        }
+    }
+}
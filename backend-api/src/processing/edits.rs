@@ -0,0 +1,73 @@
+//! Photo edit recipes
+//!
+//! Replays a `PhotoEdits` recipe on a decoded original.
+
+use image::{DynamicImage, Rgba32FImage};
+
+use crate::models::PhotoEdits;
+
+/// Applies the edit recipe to an image
+///
+/// Geometry is applied first (rotation, flips, crop), then the tonal
+/// adjustments. An empty recipe returns the image unchanged.
+pub fn apply_edits(image: DynamicImage, edits: &PhotoEdits) -> DynamicImage {
+    let mut image = match edits.rotation {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    };
+    if edits.flip_horizontal {
+        image = image.fliph();
+    }
+    if edits.flip_vertical {
+        image = image.flipv();
+    }
+
+    if let Some(crop) = &edits.crop {
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let x = ((crop.x * width).round() as u32).min(image.width() - 1);
+        let y = ((crop.y * height).round() as u32).min(image.height() - 1);
+        let w = ((crop.width * width).round() as u32).clamp(1, image.width() - x);
+        let h = ((crop.height * height).round() as u32).clamp(1, image.height() - y);
+        image = image.crop_imm(x, y, w, h);
+    }
+
+    if edits.exposure == 0.0 && edits.contrast == 0.0 && edits.saturation == 0.0 {
+        return image;
+    }
+
+    let has_alpha = image.color().has_alpha();
+    let mut pixels: Rgba32FImage = image.to_rgba32f();
+    let gain = 2f32.powf(edits.exposure);
+    let contrast = 1.0 + edits.contrast;
+    let saturation = 1.0 + edits.saturation;
+
+    for pixel in pixels.pixels_mut() {
+        let [mut r, mut g, mut b, _] = pixel.0;
+
+        r *= gain;
+        g *= gain;
+        b *= gain;
+
+        r = (r - 0.5) * contrast + 0.5;
+        g = (g - 0.5) * contrast + 0.5;
+        b = (b - 0.5) * contrast + 0.5;
+
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        r = luma + (r - luma) * saturation;
+        g = luma + (g - luma) * saturation;
+        b = luma + (b - luma) * saturation;
+
+        pixel.0[0] = r.clamp(0.0, 1.0);
+        pixel.0[1] = g.clamp(0.0, 1.0);
+        pixel.0[2] = b.clamp(0.0, 1.0);
+    }
+
+    let adjusted = DynamicImage::ImageRgba32F(pixels);
+    if has_alpha {
+        DynamicImage::ImageRgba8(adjusted.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(adjusted.to_rgb8())
+    }
+}
@@ -0,0 +1,262 @@
+//! MP4 faststart optimisation
+//!
+//! Many encoders write the `moov` box, which describes how to play the
+//! file, after the media data. Browsers then have to download the whole
+//! file before playback can start. This moves `moov` in front of the first
+//! `mdat` box and shifts the chunk offsets it contains accordingly, like
+//! `qt-faststart` and ffmpeg's `-movflags faststart` do.
+
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::Path};
+
+use super::{mp4::{self, TopLevelBox}, preview, ProcessingResult};
+
+/// Moves the `moov` box of an MP4 or QuickTime file in front of its media data
+///
+/// The file is rewritten through a temporary file and replaced atomically.
+/// Chunk offsets into media data before the old `moov` position move by the
+/// size of the relocated `moov`; offsets past it move by its change in size.
+///
+/// # Returns
+/// `true` if the file was rewritten, `false` if it was already optimised
+pub fn optimize(path: &Path) -> ProcessingResult<bool> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_len = file.get_ref().metadata()?.len();
+    let boxes = mp4::top_level_boxes(&mut file, file_len)?;
+
+    let covered = boxes.last().map_or(0, |last| last.offset + last.size);
+    if covered != file_len {
+        return Err("file is truncated or has trailing data".into());
+    }
+
+    let moov_index = boxes.iter().position(|b| &b.kind == b"moov").ok_or("file has no moov box")?;
+    let Some(mdat_index) = boxes.iter().position(|b| &b.kind == b"mdat") else {
+        return Ok(false);
+    };
+    if moov_index < mdat_index {
+        return Ok(false);
+    }
+
+    let moov = boxes[moov_index];
+    let body = mp4::read_body(&mut file, &moov)?;
+    if mp4::boxes(&body).any(|(kind, _)| &kind == b"cmov") {
+        return Err("compressed movie headers are not supported".into());
+    }
+
+    // Everything from the first mdat up to the old moov position moves
+    // forward by the size of the relocated moov, and everything after the
+    // old moov by how much the moov grew or shrank
+    let (start, end, after) = (boxes[mdat_index].offset, moov.offset, moov.offset + moov.size);
+    let relocated = |co64: bool, size: u64| -> ProcessingResult<Option<Vec<u8>>> {
+        let shift = |offset: u64| match offset {
+            offset if (start..end).contains(&offset) => offset + size,
+            offset if offset >= after => offset - moov.size + size,
+            offset => offset,
+        };
+        Ok(rewrite(&body, &shift, co64)?.map(|body| with_header(b"moov", &body)))
+    };
+    // Headers may be rewritten more compactly, so measure the moov first
+    let size = relocated(false, 0)?.ok_or("chunk offsets overflow")?.len() as u64;
+    let new_moov = match relocated(false, size)? {
+        Some(new_moov) => new_moov,
+        None => {
+            // Shifted offsets no longer fit 32 bits; switch to 64-bit chunk offsets
+            let size = relocated(true, 0)?.ok_or("chunk offsets overflow")?.len() as u64;
+            relocated(true, size)?.ok_or("chunk offsets overflow")?
+        }
+    };
+
+    preview::replace_atomically(path, |tmp| write_reordered(&mut file, tmp, &boxes, mdat_index, moov_index, &new_moov))?;
+    Ok(true)
+}
+
+/// Writes the boxes of a file with the relocated `moov` in front of the first `mdat`
+fn write_reordered(
+    source: &mut BufReader<File>,
+    destination: &Path,
+    boxes: &[TopLevelBox],
+    mdat_index: usize,
+    moov_index: usize,
+    moov: &[u8],
+) -> ProcessingResult<()> {
+    let mut writer = BufWriter::new(File::create(destination)?);
+
+    for (i, top) in boxes.iter().enumerate() {
+        if i == mdat_index {
+            writer.write_all(moov)?;
+        }
+        if i == moov_index {
+            continue;
+        }
+        source.seek(SeekFrom::Start(top.offset))?;
+        let copied = io::copy(&mut source.by_ref().take(top.size), &mut writer)?;
+        if copied != top.size {
+            return Err("file changed while it was being optimised".into());
+        }
+    }
+
+    writer.flush()?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// Rebuilds the boxes in `data`, shifting the chunk offsets of every track
+///
+/// # Arguments
+/// * `data` - Body of a container box
+/// * `shift` - Maps an old file offset to its new position
+/// * `co64` - Whether to convert 32-bit `stco` tables into 64-bit `co64` tables
+///
+/// # Returns
+/// `None` if a shifted offset does not fit into an `stco` table
+fn rewrite(data: &[u8], shift: &dyn Fn(u64) -> u64, co64: bool) -> ProcessingResult<Option<Vec<u8>>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut consumed = 0;
+
+    for (kind, body) in mp4::boxes(data) {
+        consumed = body.as_ptr() as usize - data.as_ptr() as usize + body.len();
+
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => match rewrite(body, shift, co64)? {
+                Some(body) => out.extend(with_header(&kind, &body)),
+                None => return Ok(None),
+            },
+            b"stco" | b"co64" => {
+                let wide = &kind == b"co64";
+                let width = if wide { 8 } else { 4 };
+                let count = mp4::be_u32(body, 4).ok_or("truncated chunk offset table")? as usize;
+
+                let mut table = body.get(0..8).ok_or("truncated chunk offset table")?.to_vec();
+                for i in 0..count {
+                    let at = 8 + i * width;
+                    let offset = if wide { mp4::be_u64(body, at) } else { mp4::be_u32(body, at).map(u64::from) };
+                    let offset = shift(offset.ok_or("truncated chunk offset table")?);
+                    if wide || co64 {
+                        table.extend(offset.to_be_bytes());
+                    } else if let Ok(offset) = u32::try_from(offset) {
+                        table.extend(offset.to_be_bytes());
+                    } else {
+                        return Ok(None);
+                    }
+                }
+                out.extend(with_header(if wide || co64 { b"co64" } else { b"stco" }, &table));
+            },
+            _ => out.extend(with_header(&kind, body)),
+        }
+    }
+
+    // QuickTime allows a zero terminator after the last child box
+    let rest = &data[consumed..];
+    if rest.len() >= 8 || rest.iter().any(|&b| b != 0) {
+        return Err("malformed box structure".into());
+    }
+    out.extend(rest);
+
+    Ok(Some(out))
+}
+
+/// Prepends a box header to a body
+fn with_header(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 16);
+    match u32::try_from(body.len() + 8) {
+        Ok(size) => {
+            out.extend(size.to_be_bytes());
+            out.extend(kind);
+        },
+        Err(_) => {
+            out.extend(1u32.to_be_bytes());
+            out.extend(kind);
+            out.extend((body.len() as u64 + 16).to_be_bytes());
+        },
+    }
+    out.extend(body);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf, process};
+
+    /// A box with a 64-bit size, which the rewritten `moov` replaces with a 32-bit one
+    fn large_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = 1u32.to_be_bytes().to_vec();
+        out.extend(kind);
+        out.extend((body.len() as u64 + 16).to_be_bytes());
+        out.extend(body);
+        out
+    }
+
+    /// A `moov` box with one track whose `stco` table holds `offsets`
+    fn moov(offsets: &[u32]) -> Vec<u8> {
+        let mut stco = vec![0; 4];
+        stco.extend((offsets.len() as u32).to_be_bytes());
+        offsets.iter().for_each(|offset| stco.extend(offset.to_be_bytes()));
+        let stbl = with_header(b"stbl", &with_header(b"stco", &stco));
+        let trak = with_header(b"trak", &with_header(b"mdia", &with_header(b"minf", &stbl)));
+        large_box(b"moov", &trak)
+    }
+
+    /// Chunk offsets of the first track of a file
+    fn chunk_offsets(file: &[u8]) -> Vec<u64> {
+        let moov = mp4::boxes(file).find(|(kind, _)| kind == b"moov").unwrap().1;
+        let mut data = moov;
+        for kind in [b"trak", b"mdia", b"minf", b"stbl"] {
+            data = mp4::boxes(data).find(|(k, _)| k == kind).unwrap().1;
+        }
+        let (kind, table) = mp4::boxes(data).next().unwrap();
+        let width = if &kind == b"co64" { 8 } else { 4 };
+        (0..mp4::be_u32(table, 4).unwrap() as usize).map(|i| match width {
+            8 => mp4::be_u64(table, 8 + i * 8).unwrap(),
+            _ => mp4::be_u32(table, 8 + i * 4).unwrap() as u64,
+        }).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("faststart_{}_{}.mp4", process::id(), name))
+    }
+
+    #[test]
+    fn moves_moov_and_shifts_offsets() {
+        let ftyp = with_header(b"ftyp", b"isom\0\0\0\0");
+        let (first, second) = (with_header(b"mdat", b"AAAAAAAA"), with_header(b"free", b"BBBBBBBB"));
+        // Media in the first mdat, and a chunk after the moov
+        let old_moov_len = moov(&[0, 0]).len() as u32;
+        let after = 32 + old_moov_len + 8;
+        let file = [ftyp.clone(), first.clone(), moov(&[24, after]), second.clone()].concat();
+        let path = temp_path("shift");
+        fs::write(&path, &file).unwrap();
+
+        assert!(optimize(&path).unwrap());
+        let optimized = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let kinds: Vec<[u8; 4]> = mp4::boxes(&optimized).map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [*b"ftyp", *b"moov", *b"mdat", *b"free"]);
+        assert_eq!(optimized.len(), file.len() - 8);
+
+        let offsets = chunk_offsets(&optimized);
+        assert_eq!(offsets, [24 + old_moov_len as u64 - 8, after as u64 - 8]);
+        assert_eq!(&optimized[offsets[0] as usize..][..8], b"AAAAAAAA");
+        assert_eq!(&optimized[offsets[1] as usize..][..8], b"BBBBBBBB");
+    }
+
+    #[test]
+    fn leaves_optimized_files_alone() {
+        let file = [with_header(b"ftyp", b"isom"), moov(&[16]), with_header(b"mdat", b"data")].concat();
+        let path = temp_path("optimized");
+        fs::write(&path, &file).unwrap();
+        assert!(!optimize(&path).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), file);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn upgrades_to_64_bit_offsets_when_needed() {
+        let moov = moov(&[8, u32::MAX - 8]);
+        let body = mp4::boxes(&moov).next().unwrap().1;
+        let shift = |offset: u64| offset + 16;
+        assert!(rewrite(body, &shift, false).unwrap().is_none());
+
+        let rewritten = with_header(b"moov", &rewrite(body, &shift, true).unwrap().unwrap());
+        assert_eq!(chunk_offsets(&rewritten), [24, u32::MAX as u64 + 8]);
+    }
+}
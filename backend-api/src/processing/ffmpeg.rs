@@ -0,0 +1,65 @@
+//! ffmpeg invocation
+//!
+//! Work that needs a video decoder is delegated to a locally installed
+//! ffmpeg binary. Its location is read from `FFMPEG_PATH`, falling back to
+//! `ffmpeg` on the `PATH`.
+
+use std::{env, ffi::OsStr, io::ErrorKind, process::{Command, Stdio}};
+
+use super::ProcessingResult;
+
+/// Binary used when `FFMPEG_PATH` is not set
+const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+/// Number of trailing stderr lines kept in error messages
+const ERROR_LINES: usize = 5;
+
+fn ffmpeg_path() -> String {
+    env::var("FFMPEG_PATH").unwrap_or_else(|_| DEFAULT_FFMPEG.to_string())
+}
+
+/// Formats milliseconds as an ffmpeg time, e.g. for `-ss`
+pub fn seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// Runs ffmpeg to completion
+///
+/// Output files are always overwritten and only errors are logged.
+///
+/// # Arguments
+/// * `args` - Arguments after the global options, i.e. inputs, filters and outputs
+///
+/// # Returns
+/// An error carrying the last lines ffmpeg printed if it fails
+pub fn run<I, S>(args: I) -> ProcessingResult<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let path = ffmpeg_path();
+    let output = Command::new(&path)
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y"])
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| -> super::ProcessingError {
+            if e.kind() == ErrorKind::NotFound {
+                format!("ffmpeg not found at '{}'; install it or set FFMPEG_PATH", path).into()
+            } else {
+                e.into()
+            }
+        })?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().filter(|line| !line.trim().is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(ERROR_LINES)..].join("\n");
+    if tail.is_empty() {
+        return Err(format!("ffmpeg exited with {}", output.status).into());
+    }
+    Err(format!("ffmpeg exited with {}: {}", output.status, tail).into())
+}
@@ -0,0 +1,1129 @@
+//! glTF 2.0 and GLB parsing
+//!
+//! Reads the JSON document of `.gltf` files and of binary `.glb` containers,
+//! checks that every index in it points at an existing object, so the
+//! viewer never receives a model it cannot load, and summarises what the
+//! model contains. `.gltf` files with external buffers and images can be
+//! packed into a single GLB.
+
+use serde::{Serialize, Deserialize};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::{fs::{self, File}, io::{BufReader, Read, Seek, SeekFrom}, path::{Component, Path, PathBuf}};
+
+/// Magic number at the start of every GLB file
+pub const GLB_MAGIC: &[u8; 4] = b"glTF";
+
+/// Chunk type of the JSON chunk of a GLB file
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+
+/// Chunk type of the binary buffer chunk of a GLB file
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+/// Largest JSON document accepted
+const MAX_JSON_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Valid `componentType` values of an accessor
+const COMPONENT_TYPES: &[u64] = &[5120, 5121, 5122, 5123, 5125, 5126];
+
+/// Most values an accessor without a buffer view may hold, since they are
+/// allocated rather than read from the file
+const MAX_UNBACKED_VALUES: u64 = 16 * 1024 * 1024;
+
+/// Texture references a material can hold
+const MATERIAL_TEXTURES: &[(&str, &str)] = &[
+    ("pbrMetallicRoughness", "baseColorTexture"),
+    ("pbrMetallicRoughness", "metallicRoughnessTexture"),
+    ("", "normalTexture"),
+    ("", "occlusionTexture"),
+    ("", "emissiveTexture"),
+];
+
+/// Axis-aligned bounds of a model in scene units
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+/// What a glTF model contains, for display and viewer defaults
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GltfStats {
+    pub mesh_count: u64,
+    /// Vertices of all mesh primitives, counting each mesh once
+    pub vertex_count: u64,
+    /// Triangles of all mesh primitives, counting each mesh once
+    pub triangle_count: u64,
+    pub material_count: u64,
+    pub texture_count: u64,
+    pub animation_count: u64,
+    /// Bounds of the default scene with node transforms applied
+    pub bounding_box: Option<BoundingBox>,
+    /// Extensions listed in `extensionsUsed`
+    pub extensions: Vec<String>,
+}
+
+/// The JSON document of a glTF asset, and the binary chunk of a GLB file
+#[derive(Debug)]
+pub struct Document {
+    pub json: Value,
+    /// Length of the GLB binary chunk, for GLB files that have one
+    pub bin_len: Option<u64>,
+}
+
+/// Reads the JSON document of a GLB file, checking its chunk layout
+///
+/// The binary chunk is only measured, not read.
+pub fn read_glb(path: &Path) -> Result<Document, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let file_len = file.get_ref().metadata().map_err(|e| e.to_string())?.len();
+
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).map_err(|_| "file is too short for a GLB header".to_string())?;
+    if &header[0..4] != GLB_MAGIC {
+        return Err("file does not start with the GLB magic 'glTF'".to_string());
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap_or_default());
+    if version != 2 {
+        return Err(format!("GLB version {} is not supported; only version 2 is", version));
+    }
+    let length = u32::from_le_bytes(header[8..12].try_into().unwrap_or_default()) as u64;
+    if length != file_len {
+        return Err(format!("GLB header declares {} bytes, but the file has {}", length, file_len));
+    }
+
+    let mut json = None;
+    let mut bin_len = None;
+    let mut offset = 12;
+    let mut index = 0;
+    while offset < file_len {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk).map_err(|_| format!("chunk {} has a truncated header", index))?;
+        let chunk_len = u32::from_le_bytes(chunk[0..4].try_into().unwrap_or_default()) as u64;
+        let chunk_type = u32::from_le_bytes(chunk[4..8].try_into().unwrap_or_default());
+        if !chunk_len.is_multiple_of(4) {
+            return Err(format!("chunk {} is {} bytes long, which is not 4-byte aligned", index, chunk_len));
+        }
+        if offset + 8 + chunk_len > file_len {
+            return Err(format!("chunk {} runs past the end of the file", index));
+        }
+
+        match (index, chunk_type) {
+            (0, CHUNK_JSON) => {
+                if chunk_len > MAX_JSON_SIZE {
+                    return Err("JSON chunk is too large".to_string());
+                }
+                let mut data = vec![0; chunk_len as usize];
+                file.read_exact(&mut data).map_err(|e| e.to_string())?;
+                // The JSON chunk is padded with trailing spaces
+                json = Some(serde_json::from_slice::<Value>(&data)
+                    .map_err(|e| format!("JSON chunk is not valid JSON: {}", e))?);
+            },
+            (0, _) => return Err("the first chunk must be the JSON chunk".to_string()),
+            (1, CHUNK_BIN) => {
+                bin_len = Some(chunk_len);
+                file.seek(SeekFrom::Current(chunk_len as i64)).map_err(|e| e.to_string())?;
+            },
+            (_, CHUNK_JSON | CHUNK_BIN) => {
+                return Err(format!("chunk {} repeats a JSON or BIN chunk", index));
+            },
+            // Unknown chunk types must be ignored
+            _ => {
+                file.seek(SeekFrom::Current(chunk_len as i64)).map_err(|e| e.to_string())?;
+            },
+        }
+        offset += 8 + chunk_len;
+        index += 1;
+    }
+
+    let json = json.ok_or("file has no JSON chunk")?;
+    Ok(Document { json, bin_len })
+}
+
+/// Reads the JSON document of a `.gltf` file
+pub fn read_gltf(path: &Path) -> Result<Document, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    if file.metadata().map_err(|e| e.to_string())?.len() > MAX_JSON_SIZE {
+        return Err("glTF JSON is too large".to_string());
+    }
+    let json = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("file is not valid JSON: {}", e))?;
+    Ok(Document { json, bin_len: None })
+}
+
+/// The values of an accessor, decoded to floats
+#[derive(Debug, Clone)]
+pub struct AccessorData {
+    /// Components per element, e.g. 3 for `VEC3`
+    pub components: usize,
+    pub values: Vec<f32>,
+}
+
+impl AccessorData {
+    /// Number of elements
+    pub fn len(&self) -> usize {
+        self.values.len() / self.components.max(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Components of one element
+    pub fn get(&self, index: usize) -> &[f32] {
+        &self.values[index * self.components..(index + 1) * self.components]
+    }
+}
+
+/// Reads the data of every buffer of a validated document
+///
+/// # Arguments
+/// * `path` - The `.gltf` or `.glb` file the document was read from; relative
+///   URIs are resolved against its folder
+/// * `document` - The document
+pub fn read_buffers(path: &Path, document: &Document) -> Result<Vec<Vec<u8>>, String> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let buffers = document.json.get("buffers").and_then(Value::as_array).into_iter().flatten();
+    buffers.enumerate().map(|(i, buffer)| {
+        match (buffer.get("uri").and_then(Value::as_str), document.bin_len.filter(|_| i == 0)) {
+            (Some(uri), _) => load_uri(uri, base).map_err(|e| format!("buffers[{}]: {}", i, e)),
+            (None, Some(_)) => read_glb_bin(path),
+            (None, None) => Err(format!("buffers[{}] has no data", i)),
+        }
+    }).collect()
+}
+
+/// Reads the binary chunk of a GLB file whose layout has been checked
+fn read_glb_bin(path: &Path) -> Result<Vec<u8>, String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let mut chunk = [0u8; 8];
+    file.seek(SeekFrom::Start(12)).map_err(|e| e.to_string())?;
+    file.read_exact(&mut chunk).map_err(|e| e.to_string())?;
+    let json_len = u32::from_le_bytes(chunk[0..4].try_into().unwrap_or_default());
+    file.seek(SeekFrom::Current(json_len as i64)).map_err(|e| e.to_string())?;
+    file.read_exact(&mut chunk).map_err(|_| "file has no BIN chunk".to_string())?;
+    if u32::from_le_bytes(chunk[4..8].try_into().unwrap_or_default()) != CHUNK_BIN {
+        return Err("file has no BIN chunk".to_string());
+    }
+    let mut bin = vec![0; u32::from_le_bytes(chunk[0..4].try_into().unwrap_or_default()) as usize];
+    file.read_exact(&mut bin).map_err(|e| e.to_string())?;
+    Ok(bin)
+}
+
+/// Decodes the elements of an accessor, including sparse substitutions
+///
+/// # Arguments
+/// * `json` - The glTF document
+/// * `buffers` - Result of `read_buffers`
+/// * `index` - Index of the accessor
+pub fn read_accessor(json: &Value, buffers: &[Vec<u8>], index: usize) -> Result<AccessorData, String> {
+    let path = format!("accessors[{}]", index);
+    let accessor = json.get("accessors").and_then(|a| a.get(index)).ok_or_else(|| format!("{} is missing", path))?;
+    let count = accessor.get("count").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let component_type = accessor.get("componentType").and_then(Value::as_u64).unwrap_or(0);
+    let normalized = accessor.get("normalized").and_then(Value::as_bool).unwrap_or(false);
+    let components = accessor.get("type").and_then(Value::as_str).and_then(type_components)
+        .ok_or_else(|| format!("{}.type is missing or invalid", path))?;
+    let size = component_size(component_type).ok_or_else(|| format!("{}.componentType is invalid", path))?;
+
+    let view = accessor.get("bufferView").and_then(Value::as_u64);
+    let (data, stride) = match view {
+        Some(view) => view_data(json, buffers, view as usize)?,
+        None => (&[][..], None),
+    };
+    let stride = stride.unwrap_or(components * size);
+    let offset = accessor.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize;
+    // Checked before allocating, so a huge count cannot exhaust memory
+    if view.is_some() && element_end(offset, count, stride, components * size).is_none_or(|end| end > data.len()) {
+        return Err(format!("{} runs past its buffer view", path));
+    }
+    let len = count.checked_mul(components).filter(|&len| view.is_some() || len as u64 <= MAX_UNBACKED_VALUES)
+        .ok_or_else(|| format!("{} has no bufferView and more than {} values", path, MAX_UNBACKED_VALUES))?;
+
+    let mut values = vec![0.0; len];
+    if view.is_some() {
+        for (i, element) in values.chunks_exact_mut(components).enumerate() {
+            for (c, value) in element.iter_mut().enumerate() {
+                let start = offset + i * stride + c * size;
+                *value = decode_component(component_type, &data[start..start + size], normalized);
+            }
+        }
+    }
+
+    if let Some(sparse) = accessor.get("sparse") {
+        let sparse_count = sparse.get("count").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let part = |key: &str| -> Result<(&[u8], u64), String> {
+            let part = sparse.get(key).ok_or_else(|| format!("{}.sparse.{} is missing", path, key))?;
+            let view = part.get("bufferView").and_then(Value::as_u64).ok_or_else(|| format!("{}.sparse.{}.bufferView is missing", path, key))?;
+            let offset = part.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize;
+            let data = view_data(json, buffers, view as usize)?.0;
+            let data = data.get(offset..).ok_or_else(|| format!("{}.sparse.{} starts past its buffer view", path, key))?;
+            Ok((data, part.get("componentType").and_then(Value::as_u64).unwrap_or(component_type)))
+        };
+        let (indices, index_type) = part("indices")?;
+        let (replacements, _) = part("values")?;
+        let index_size = component_size(index_type).ok_or_else(|| format!("{}.sparse.indices.componentType is invalid", path))?;
+        for i in 0..sparse_count {
+            let target = indices.get(i * index_size..(i + 1) * index_size)
+                .map(|bytes| decode_component(index_type, bytes, false) as usize)
+                .filter(|target| *target < count)
+                .ok_or_else(|| format!("{}.sparse.indices[{}] is invalid", path, i))?;
+            for c in 0..components {
+                let start = (i * components + c) * size;
+                let bytes = replacements.get(start..start + size).ok_or_else(|| format!("{}.sparse.values runs past its buffer view", path))?;
+                values[target * components + c] = decode_component(component_type, bytes, normalized);
+            }
+        }
+    }
+
+    Ok(AccessorData { components, values })
+}
+
+/// Reads an index accessor as integers, which `read_accessor` could only
+/// represent exactly up to 2^24
+pub fn read_indices(json: &Value, buffers: &[Vec<u8>], index: usize) -> Result<Vec<u32>, String> {
+    let path = format!("accessors[{}]", index);
+    let accessor = json.get("accessors").and_then(|a| a.get(index)).ok_or_else(|| format!("{} is missing", path))?;
+    let component_type = accessor.get("componentType").and_then(Value::as_u64).unwrap_or(0);
+    if accessor.get("sparse").is_some() || accessor.get("bufferView").is_none() {
+        return Ok(read_accessor(json, buffers, index)?.values.into_iter().map(|v| v as u32).collect());
+    }
+    let size = match component_type {
+        5121 => 1,
+        5123 => 2,
+        5125 => 4,
+        _ => return Err(format!("{} is not an index accessor", path)),
+    };
+    let count = accessor.get("count").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let view = accessor.get("bufferView").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let (data, stride) = view_data(json, buffers, view)?;
+    let (stride, offset) = (stride.unwrap_or(size), accessor.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize);
+    if element_end(offset, count, stride, size).is_none_or(|end| end > data.len()) {
+        return Err(format!("{} runs past its buffer view", path));
+    }
+    (0..count).map(|i| {
+        let bytes = &data[offset + i * stride..offset + i * stride + size];
+        Ok(match size {
+            1 => bytes[0] as u32,
+            2 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            _ => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        })
+    }).collect()
+}
+
+/// The bytes of a buffer view, and its stride if it has one
+fn view_data<'a>(json: &Value, buffers: &'a [Vec<u8>], index: usize) -> Result<(&'a [u8], Option<usize>), String> {
+    let view = json.get("bufferViews").and_then(|v| v.get(index)).ok_or_else(|| format!("bufferViews[{}] is missing", index))?;
+    let buffer = view.get("buffer").and_then(Value::as_u64).and_then(|b| buffers.get(b as usize))
+        .ok_or_else(|| format!("bufferViews[{}].buffer is invalid", index))?;
+    let offset = view.get("byteOffset").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let length = view.get("byteLength").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let data = offset.checked_add(length).and_then(|end| buffer.get(offset..end))
+        .ok_or_else(|| format!("bufferViews[{}] runs past its buffer", index))?;
+    Ok((data, view.get("byteStride").and_then(Value::as_u64).map(|s| s as usize)))
+}
+
+/// Encodes one accessor component as little-endian bytes
+pub fn encode_component(component_type: u64, value: f32, normalized: bool, out: &mut Vec<u8>) {
+    let scale = |max: f32| if normalized { value * max } else { value };
+    match component_type {
+        5120 => out.push(scale(127.0).round().clamp(-128.0, 127.0) as i8 as u8),
+        5121 => out.push(scale(255.0).round().clamp(0.0, 255.0) as u8),
+        5122 => out.extend_from_slice(&(scale(32767.0).round().clamp(-32768.0, 32767.0) as i16).to_le_bytes()),
+        5123 => out.extend_from_slice(&(scale(65535.0).round().clamp(0.0, 65535.0) as u16).to_le_bytes()),
+        5125 => out.extend_from_slice(&(value.round().max(0.0) as u32).to_le_bytes()),
+        _ => out.extend_from_slice(&value.to_le_bytes()),
+    }
+}
+
+/// Components per element of an accessor `type`
+fn type_components(kind: &str) -> Option<usize> {
+    match kind {
+        "SCALAR" => Some(1),
+        "VEC2" => Some(2),
+        "VEC3" => Some(3),
+        "VEC4" | "MAT2" => Some(4),
+        "MAT3" => Some(9),
+        "MAT4" => Some(16),
+        _ => None,
+    }
+}
+
+/// End of the last of `count` elements of `element_size` bytes, `stride`
+/// bytes apart from `offset` on, or `None` if it does not fit in a `usize`
+fn element_end(offset: usize, count: usize, stride: usize, element_size: usize) -> Option<usize> {
+    match count {
+        0 => Some(offset),
+        _ => (count - 1).checked_mul(stride)?.checked_add(element_size)?.checked_add(offset),
+    }
+}
+
+/// Size in bytes of an accessor component type
+pub fn component_size(component_type: u64) -> Option<usize> {
+    match component_type {
+        5120 | 5121 => Some(1),
+        5122 | 5123 => Some(2),
+        5125 | 5126 => Some(4),
+        _ => None,
+    }
+}
+
+/// Decodes one little-endian accessor component
+fn decode_component(component_type: u64, bytes: &[u8], normalized: bool) -> f32 {
+    let (value, max) = match component_type {
+        5120 => (bytes[0] as i8 as f32, 127.0),
+        5121 => (bytes[0] as f32, 255.0),
+        5122 => (i16::from_le_bytes([bytes[0], bytes[1]]) as f32, 32767.0),
+        5123 => (u16::from_le_bytes([bytes[0], bytes[1]]) as f32, 65535.0),
+        5125 => return u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+        _ => return f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+    if normalized { (value / max).max(-1.0) } else { value }
+}
+
+/// Checks a glTF document against the parts of the glTF 2.0 schema that
+/// matter for loading it
+///
+/// Buffers and images must be embedded, either in `data:` URIs or in the
+/// binary chunk of a GLB file, unless `base` is given: then relative URIs
+/// are resolved against it and must name files inside it, and buffer files
+/// must be at least as long as the buffer.
+pub fn validate(document: &Document, base: Option<&Path>) -> Result<(), String> {
+    let json = document.json.as_object().ok_or("glTF document must be a JSON object")?;
+
+    let version = json.get("asset").and_then(|asset| asset.get("version")).and_then(Value::as_str)
+        .ok_or("asset.version is missing")?;
+    if !version.starts_with("2.") {
+        return Err(format!("glTF version {} is not supported; only 2.0 is", version));
+    }
+
+    let len = |name: &str| json.get(name).and_then(Value::as_array).map_or(0, Vec::len);
+    for name in [
+        "accessors", "animations", "buffers", "bufferViews", "cameras", "images", "materials",
+        "meshes", "nodes", "samplers", "scenes", "skins", "textures",
+    ] {
+        match json.get(name) {
+            None => {},
+            Some(Value::Array(items)) if items.iter().all(Value::is_object) => {},
+            Some(_) => return Err(format!("{} must be an array of objects", name)),
+        }
+    }
+    let items = |name: &str| json.get(name).and_then(Value::as_array).into_iter().flatten().enumerate();
+
+    // Buffers and their views
+    let mut buffer_lengths = Vec::new();
+    for (i, buffer) in items("buffers") {
+        let byte_length = buffer.get("byteLength").and_then(Value::as_u64)
+            .ok_or_else(|| format!("buffers[{}].byteLength is missing", i))?;
+        match (buffer.get("uri").and_then(Value::as_str), document.bin_len.filter(|_| i == 0)) {
+            (Some(uri), _) => check_uri(uri, &format!("buffers[{}]", i), base, byte_length)?,
+            (None, Some(bin_len)) => {
+                if byte_length > bin_len {
+                    return Err(format!(
+                        "buffers[0] declares {} bytes, but the BIN chunk only has {}",
+                        byte_length, bin_len
+                    ));
+                }
+            },
+            (None, None) => return Err(format!("buffers[{}] has no uri and is not the GLB binary chunk", i)),
+        }
+        buffer_lengths.push(byte_length);
+    }
+    let mut views = Vec::new();
+    for (i, view) in items("bufferViews") {
+        let path = format!("bufferViews[{}]", i);
+        let buffer = reference(view, "buffer", &path, "buffers", len("buffers"))?
+            .ok_or_else(|| format!("{}.buffer is missing", path))?;
+        let offset = view.get("byteOffset").and_then(Value::as_u64).unwrap_or(0);
+        let length = view.get("byteLength").and_then(Value::as_u64)
+            .ok_or_else(|| format!("{}.byteLength is missing", path))?;
+        match offset.checked_add(length) {
+            Some(end) if end <= buffer_lengths[buffer] => {},
+            _ => return Err(format!(
+                "{} ends past the end of buffers[{}] ({} bytes)",
+                path, buffer, buffer_lengths[buffer]
+            )),
+        }
+        views.push((length, view.get("byteStride").and_then(Value::as_u64)));
+    }
+
+    for (i, accessor) in items("accessors") {
+        let path = format!("accessors[{}]", i);
+        let view = reference(accessor, "bufferView", &path, "bufferViews", len("bufferViews"))?;
+        let size = accessor.get("componentType").and_then(Value::as_u64)
+            .filter(|t| COMPONENT_TYPES.contains(t))
+            .and_then(component_size)
+            .ok_or_else(|| format!("{}.componentType is missing or invalid", path))?;
+        let components = accessor.get("type").and_then(Value::as_str).and_then(type_components)
+            .ok_or_else(|| format!("{}.type is missing or invalid", path))?;
+        let count = accessor.get("count").and_then(Value::as_u64).filter(|&count| count > 0)
+            .ok_or_else(|| format!("{}.count must be at least 1", path))?;
+        let values = count.checked_mul(components as u64);
+        match view {
+            Some(view) => {
+                let (length, stride) = views[view];
+                let element_size = (components * size) as u64;
+                let offset = accessor.get("byteOffset").and_then(Value::as_u64).unwrap_or(0);
+                let end = (count - 1).checked_mul(stride.unwrap_or(element_size))
+                    .and_then(|n| n.checked_add(element_size))
+                    .and_then(|n| n.checked_add(offset));
+                if end.is_none_or(|end| end > length) {
+                    return Err(format!("{} runs past the end of bufferViews[{}] ({} bytes)", path, view, length));
+                }
+            },
+            None if values.is_none_or(|values| values > MAX_UNBACKED_VALUES) => {
+                return Err(format!("{} has no bufferView and more than {} values", path, MAX_UNBACKED_VALUES));
+            },
+            None => {},
+        }
+        if let Some(sparse) = accessor.get("sparse") {
+            if sparse.get("count").and_then(Value::as_u64).is_none_or(|n| n > count) {
+                return Err(format!("{}.sparse.count is missing or larger than the accessor", path));
+            }
+        }
+    }
+
+    // Images, textures and materials
+    for (i, image) in items("images") {
+        let path = format!("images[{}]", i);
+        let in_view = reference(image, "bufferView", &path, "bufferViews", len("bufferViews"))?.is_some();
+        match image.get("uri").and_then(Value::as_str) {
+            Some(uri) => check_uri(uri, &path, base, 0)?,
+            None if in_view => {},
+            None => return Err(format!("{} has neither a uri nor a bufferView", path)),
+        }
+    }
+    for (i, texture) in items("textures") {
+        let path = format!("textures[{}]", i);
+        reference(texture, "source", &path, "images", len("images"))?;
+        reference(texture, "sampler", &path, "samplers", len("samplers"))?;
+    }
+    for (i, material) in items("materials") {
+        for (parent, name) in MATERIAL_TEXTURES {
+            let holder = if parent.is_empty() { Some(material) } else { material.get(*parent) };
+            if let Some(texture) = holder.and_then(|holder| holder.get(*name)) {
+                let path = match parent.is_empty() {
+                    true => format!("materials[{}].{}", i, name),
+                    false => format!("materials[{}].{}.{}", i, parent, name),
+                };
+                reference(texture, "index", &path, "textures", len("textures"))?
+                    .ok_or_else(|| format!("{}.index is missing", path))?;
+            }
+        }
+    }
+
+    // Geometry and scene graph
+    for (i, mesh) in items("meshes") {
+        let primitives = mesh.get("primitives").and_then(Value::as_array)
+            .filter(|primitives| !primitives.is_empty())
+            .ok_or_else(|| format!("meshes[{}] has no primitives", i))?;
+        for (j, primitive) in primitives.iter().enumerate() {
+            let path = format!("meshes[{}].primitives[{}]", i, j);
+            let attributes = primitive.get("attributes").and_then(Value::as_object)
+                .ok_or_else(|| format!("{}.attributes is missing", path))?;
+            for (name, accessor) in attributes {
+                index(accessor, &format!("{}.attributes.{}", path, name), "accessors", len("accessors"))?;
+            }
+            reference(primitive, "indices", &path, "accessors", len("accessors"))?;
+            reference(primitive, "material", &path, "materials", len("materials"))?;
+        }
+    }
+    // Node hierarchies must be trees: one parent per node and no cycles
+    let mut parents = vec![None; len("nodes")];
+    for (i, node) in items("nodes") {
+        let path = format!("nodes[{}]", i);
+        reference(node, "mesh", &path, "meshes", len("meshes"))?;
+        reference(node, "camera", &path, "cameras", len("cameras"))?;
+        reference(node, "skin", &path, "skins", len("skins"))?;
+        for (j, child) in node.get("children").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let child = index(child, &format!("{}.children[{}]", path, j), "nodes", len("nodes"))?;
+            if parents[child].replace(i).is_some() {
+                return Err(format!("nodes[{}] has more than one parent", child));
+            }
+        }
+    }
+    for start in 0..parents.len() {
+        let mut node = start;
+        for _ in 0..parents.len() {
+            match parents[node] {
+                Some(parent) if parent == start => return Err(format!("nodes[{}] is its own ancestor", start)),
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+    for (i, skin) in items("skins") {
+        let path = format!("skins[{}]", i);
+        reference(skin, "inverseBindMatrices", &path, "accessors", len("accessors"))?;
+        for (j, joint) in skin.get("joints").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            index(joint, &format!("{}.joints[{}]", path, j), "nodes", len("nodes"))?;
+        }
+    }
+    for (i, scene) in items("scenes") {
+        for (j, node) in scene.get("nodes").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let path = format!("scenes[{}].nodes[{}]", i, j);
+            if let Some(parent) = parents[index(node, &path, "nodes", len("nodes"))?] {
+                return Err(format!("{} is not a root node; it is a child of nodes[{}]", path, parent));
+            }
+        }
+    }
+    if let Some(scene) = json.get("scene") {
+        index(scene, "scene", "scenes", len("scenes"))?;
+    }
+
+    for (i, animation) in items("animations") {
+        let samplers = animation.get("samplers").and_then(Value::as_array).map_or(0, Vec::len);
+        for (j, sampler) in animation.get("samplers").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let path = format!("animations[{}].samplers[{}]", i, j);
+            reference(sampler, "input", &path, "accessors", len("accessors"))?
+                .ok_or_else(|| format!("{}.input is missing", path))?;
+            reference(sampler, "output", &path, "accessors", len("accessors"))?
+                .ok_or_else(|| format!("{}.output is missing", path))?;
+        }
+        for (j, channel) in animation.get("channels").and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let path = format!("animations[{}].channels[{}]", i, j);
+            reference(channel, "sampler", &path, "samplers", samplers)?
+                .ok_or_else(|| format!("{}.sampler is missing", path))?;
+            if let Some(target) = channel.get("target") {
+                reference(target, "node", &format!("{}.target", path), "nodes", len("nodes"))?;
+            }
+        }
+    }
+
+    let used = extension_names(json.get("extensionsUsed"));
+    for required in extension_names(json.get("extensionsRequired")) {
+        if !used.contains(&required) {
+            return Err(format!("extension {} is required but not listed in extensionsUsed", required));
+        }
+    }
+
+    Ok(())
+}
+
+/// Summarises a validated glTF document
+pub fn stats(document: &Document) -> GltfStats {
+    let json = &document.json;
+    let array = |name: &str| json.get(name).and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let accessors = array("accessors");
+    let count = |accessor: Option<&Value>| {
+        accessor.and_then(Value::as_u64)
+            .and_then(|i| accessors.get(i as usize))
+            .and_then(|a| a.get("count"))
+            .and_then(Value::as_u64)
+            .unwrap_or(0)
+    };
+
+    let (mut vertex_count, mut triangle_count) = (0, 0);
+    let mut mesh_bounds = Vec::new();
+    for mesh in array("meshes") {
+        let mut bounds: Option<BoundingBox> = None;
+        for primitive in mesh.get("primitives").and_then(Value::as_array).into_iter().flatten() {
+            let position = primitive.get("attributes").and_then(|a| a.get("POSITION"));
+            let vertices = count(position);
+            vertex_count += vertices;
+
+            let elements = match primitive.get("indices") {
+                Some(indices) => count(Some(indices)),
+                None => vertices,
+            };
+            triangle_count += match primitive.get("mode").and_then(Value::as_u64).unwrap_or(4) {
+                4 => elements / 3,
+                5 | 6 => elements.saturating_sub(2),
+                _ => 0,
+            };
+
+            let accessor = position.and_then(Value::as_u64).and_then(|i| accessors.get(i as usize));
+            if let Some(primitive_bounds) = accessor.and_then(accessor_bounds) {
+                bounds = Some(bounds.map_or(primitive_bounds, |b| b.union(&primitive_bounds)));
+            }
+        }
+        mesh_bounds.push(bounds);
+    }
+
+    GltfStats {
+        mesh_count: array("meshes").len() as u64,
+        vertex_count,
+        triangle_count,
+        material_count: array("materials").len() as u64,
+        texture_count: array("textures").len() as u64,
+        animation_count: array("animations").len() as u64,
+        bounding_box: scene_bounds(json, &mesh_bounds),
+        extensions: extension_names(json.get("extensionsUsed")),
+    }
+}
+
+impl BoundingBox {
+    fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: std::array::from_fn(|i| self.min[i].min(other.min[i])),
+            max: std::array::from_fn(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    /// Bounds of this box after transforming its corners
+    fn transform(&self, matrix: &Matrix) -> BoundingBox {
+        let corners = (0..8).map(|i| {
+            let corner = [
+                if i & 1 == 0 { self.min[0] } else { self.max[0] },
+                if i & 2 == 0 { self.min[1] } else { self.max[1] },
+                if i & 4 == 0 { self.min[2] } else { self.max[2] },
+            ];
+            let point = transform_point(matrix, corner);
+            BoundingBox { min: point, max: point }
+        });
+        corners.reduce(|a, b| a.union(&b)).unwrap_or(*self)
+    }
+}
+
+/// A column-major 4x4 matrix, as used by glTF
+pub type Matrix = [f64; 16];
+
+pub const IDENTITY: Matrix = [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+
+/// Reads the `min` and `max` of a POSITION accessor, which glTF requires
+fn accessor_bounds(accessor: &Value) -> Option<BoundingBox> {
+    let vector = |key: &str| -> Option<[f64; 3]> {
+        let values = accessor.get(key)?.as_array()?;
+        Some([values.first()?.as_f64()?, values.get(1)?.as_f64()?, values.get(2)?.as_f64()?])
+    };
+    Some(BoundingBox { min: vector("min")?, max: vector("max")? })
+}
+
+/// Bounds of the default scene, or of every scene if there is no default
+fn scene_bounds(json: &Value, mesh_bounds: &[Option<BoundingBox>]) -> Option<BoundingBox> {
+    mesh_instances(json).into_iter()
+        .filter_map(|(mesh, world)| mesh_bounds.get(mesh).copied().flatten().map(|b| b.transform(&world)))
+        .reduce(|a, b| a.union(&b))
+}
+
+/// Meshes placed in the default scene, or in every scene if there is no
+/// default, with the world transform of each node that uses them
+pub fn mesh_instances(json: &Value) -> Vec<(usize, Matrix)> {
+    let nodes = json.get("nodes").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let scenes = json.get("scenes").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let selected: Vec<&Value> = match json.get("scene").and_then(Value::as_u64) {
+        Some(scene) => scenes.get(scene as usize).into_iter().collect(),
+        None => scenes.iter().collect(),
+    };
+    let roots: Vec<u64> = selected.into_iter()
+        .flat_map(|scene| scene.get("nodes").and_then(Value::as_array).into_iter().flatten())
+        .filter_map(Value::as_u64)
+        .collect();
+
+    let mut instances = Vec::new();
+    // Depth-first walk; `validate` rejects graphs that are not trees, and
+    // visiting each node once keeps invalid ones from looping or repeating
+    let mut visited = vec![false; nodes.len()];
+    let mut stack: Vec<(u64, Matrix)> = roots.into_iter().map(|node| (node, IDENTITY)).collect();
+    while let Some((index, parent)) = stack.pop() {
+        let Some(node) = nodes.get(index as usize) else { continue };
+        if std::mem::replace(&mut visited[index as usize], true) {
+            continue;
+        }
+        let world = multiply(&parent, &local_matrix(node));
+        if let Some(mesh) = node.get("mesh").and_then(Value::as_u64) {
+            instances.push((mesh as usize, world));
+        }
+        for child in node.get("children").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_u64) {
+            stack.push((child, world));
+        }
+    }
+    instances
+}
+
+/// Local transform of a node, from its `matrix` or its translation, rotation and scale
+fn local_matrix(node: &Value) -> Matrix {
+    let numbers = |key: &str| -> Option<Vec<f64>> {
+        node.get(key)?.as_array()?.iter().map(Value::as_f64).collect()
+    };
+    if let Some(matrix) = numbers("matrix").filter(|m| m.len() == 16) {
+        return std::array::from_fn(|i| matrix[i]);
+    }
+
+    let t = numbers("translation").filter(|v| v.len() == 3).unwrap_or_else(|| vec![0.0; 3]);
+    let r = numbers("rotation").filter(|v| v.len() == 4).unwrap_or_else(|| vec![0.0, 0.0, 0.0, 1.0]);
+    let s = numbers("scale").filter(|v| v.len() == 3).unwrap_or_else(|| vec![1.0; 3]);
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * s[0], 2.0 * (x * y + z * w) * s[0], 2.0 * (x * z - y * w) * s[0], 0.0,
+        2.0 * (x * y - z * w) * s[1], (1.0 - 2.0 * (x * x + z * z)) * s[1], 2.0 * (y * z + x * w) * s[1], 0.0,
+        2.0 * (x * z + y * w) * s[2], 2.0 * (y * z - x * w) * s[2], (1.0 - 2.0 * (x * x + y * y)) * s[2], 0.0,
+        t[0], t[1], t[2], 1.0,
+    ]
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| {
+        let (column, row) = (i / 4, i % 4);
+        (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum()
+    })
+}
+
+/// Applies a transform to a point
+pub fn transform_point(m: &Matrix, p: [f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|row| m[row] * p[0] + m[4 + row] * p[1] + m[8 + row] * p[2] + m[12 + row])
+}
+
+/// Names listed in `extensionsUsed` or `extensionsRequired`
+pub fn extension_names(value: Option<&Value>) -> Vec<String> {
+    value.and_then(Value::as_array).into_iter().flatten()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks an optional index property of `object`
+///
+/// # Returns
+/// The index if the property is present
+fn reference(object: &Value, key: &str, path: &str, collection: &str, len: usize) -> Result<Option<usize>, String> {
+    object.get(key)
+        .map(|value| index(value, &format!("{}.{}", path, key), collection, len))
+        .transpose()
+}
+
+/// Checks that `value` is a valid index into `collection`
+fn index(value: &Value, path: &str, collection: &str, len: usize) -> Result<usize, String> {
+    let index = value.as_u64().ok_or_else(|| format!("{} must be a non-negative integer", path))? as usize;
+    if index >= len {
+        return Err(format!("{} refers to {}[{}], but there are only {}", path, collection, index, len));
+    }
+    Ok(index)
+}
+
+/// Checks that a buffer or image URI embeds its data, or names a file of at
+/// least `min_len` bytes inside `base`
+fn check_uri(uri: &str, path: &str, base: Option<&Path>, min_len: u64) -> Result<(), String> {
+    if uri.starts_with("data:") {
+        return if uri.contains(";base64,") {
+            Ok(())
+        } else {
+            Err(format!("{}.uri is a data URI that is not base64-encoded", path))
+        };
+    }
+    let Some(base) = base else {
+        return Err(format!(
+            "{} references the external file '{}'; upload a GLB, or a zip of the glTF with its files, instead",
+            path, uri
+        ));
+    };
+    let relative = relative_path(uri).map_err(|e| format!("{}.uri {}", path, e))?;
+    let len = fs::metadata(base.join(&relative)).ok().filter(|m| m.is_file()).map(|m| m.len())
+        .ok_or_else(|| format!("{} references the file '{}', which is missing from the upload", path, relative.display()))?;
+    if len < min_len {
+        return Err(format!(
+            "{} declares {} bytes, but '{}' only has {}",
+            path, min_len, relative.display(), len
+        ));
+    }
+    Ok(())
+}
+
+/// Turns a relative URI into a path that cannot leave the folder it is
+/// resolved against
+pub fn relative_path(uri: &str) -> Result<PathBuf, String> {
+    let decoded = percent_decode_str(uri).decode_utf8()
+        .map_err(|_| format!("'{}' is not valid UTF-8", uri))?;
+    // Checked after decoding too, so `%5C` or `%2F` cannot sneak a separator in
+    for value in [uri, decoded.as_ref()] {
+        if value.contains(':') || value.starts_with('/') || value.contains('\\') {
+            return Err(format!("'{}' is not a relative path", uri));
+        }
+    }
+    let mut path = PathBuf::new();
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            _ => return Err(format!("'{}' points outside the model's folder", uri)),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(format!("'{}' does not name a file", uri));
+    }
+    Ok(path)
+}
+
+/// Lists the files a validated `.gltf` document references, relative to
+/// its folder
+pub fn external_files(document: &Document) -> Vec<PathBuf> {
+    ["buffers", "images"].iter()
+        .flat_map(|name| document.json.get(*name).and_then(Value::as_array).into_iter().flatten())
+        .filter_map(|item| item.get("uri").and_then(Value::as_str))
+        .filter(|uri| !uri.starts_with("data:"))
+        .filter_map(|uri| relative_path(uri).ok())
+        .collect()
+}
+
+/// Packs a validated `.gltf` document and every buffer and image it
+/// references into a self-contained GLB
+///
+/// All buffers are merged into the binary chunk, and images become buffer
+/// views with their MIME type.
+///
+/// # Arguments
+/// * `document` - The glTF document
+/// * `base` - Folder its relative URIs resolve against
+///
+/// # Returns
+/// The bytes of the GLB file
+pub fn pack(document: &Document, base: &Path) -> Result<Vec<u8>, String> {
+    let mut json = document.json.clone();
+    let mut bin = Vec::new();
+
+    let mut buffer_offsets = Vec::new();
+    for (i, buffer) in json.get("buffers").and_then(Value::as_array).into_iter().flatten().enumerate() {
+        let byte_length = buffer.get("byteLength").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let uri = buffer.get("uri").and_then(Value::as_str)
+            .ok_or_else(|| format!("buffers[{}] has no uri", i))?;
+        let data = load_uri(uri, base).map_err(|e| format!("buffers[{}]: {}", i, e))?;
+        if data.len() < byte_length {
+            return Err(format!("buffers[{}] declares {} bytes, but only has {}", i, byte_length, data.len()));
+        }
+        align(&mut bin);
+        buffer_offsets.push(bin.len() as u64);
+        bin.extend_from_slice(&data[..byte_length]);
+    }
+    for view in json.get_mut("bufferViews").and_then(Value::as_array_mut).into_iter().flatten() {
+        let buffer = view.get("buffer").and_then(Value::as_u64).unwrap_or(0) as usize;
+        let offset = view.get("byteOffset").and_then(Value::as_u64).unwrap_or(0);
+        view["buffer"] = json!(0);
+        view["byteOffset"] = json!(buffer_offsets.get(buffer).copied().unwrap_or(0) + offset);
+    }
+
+    // Images become buffer views after the existing ones
+    let first_image_view = json.get("bufferViews").and_then(Value::as_array).map_or(0, Vec::len);
+    let mut image_views = Vec::new();
+    for (i, image) in json.get_mut("images").and_then(Value::as_array_mut).into_iter().flatten().enumerate() {
+        let Some(uri) = image.get("uri").and_then(Value::as_str) else { continue };
+        let data = load_uri(uri, base).map_err(|e| format!("images[{}]: {}", i, e))?;
+        let mime_type = image_mime_type(&data)
+            .ok_or_else(|| format!("images[{}] is not a PNG, JPEG, WebP or KTX2 image", i))?;
+        align(&mut bin);
+        image_views.push(json!({ "buffer": 0, "byteOffset": bin.len(), "byteLength": data.len() }));
+        bin.extend_from_slice(&data);
+
+        let image = image.as_object_mut().ok_or_else(|| format!("images[{}] is not an object", i))?;
+        image.remove("uri");
+        image.insert("mimeType".to_string(), json!(mime_type));
+        image.insert("bufferView".to_string(), json!(first_image_view + image_views.len() - 1));
+    }
+    if !image_views.is_empty() {
+        json.as_object_mut().ok_or("glTF document must be a JSON object")?
+            .entry("bufferViews").or_insert_with(|| json!([]))
+            .as_array_mut().ok_or("bufferViews must be an array")?
+            .extend(image_views);
+    }
+
+    assemble_glb(json, bin)
+}
+
+/// Splits the buffers of a document into the data of each buffer view
+pub fn view_bytes(json: &Value, buffers: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, String> {
+    let count = json.get("bufferViews").and_then(Value::as_array).map_or(0, Vec::len);
+    (0..count).map(|i| view_data(json, buffers, i).map(|(data, _)| data.to_vec())).collect()
+}
+
+/// Writes a document as a GLB, rebuilding its binary chunk from the data of
+/// each buffer view
+///
+/// Buffer views that no accessor or image references are dropped, and the
+/// remaining ones are renumbered, so replaced data does not linger in the
+/// file.
+///
+/// # Arguments
+/// * `json` - The glTF document; `bufferViews[i].buffer` and `byteOffset` are rewritten
+/// * `views` - Data of each entry of `bufferViews`
+///
+/// # Returns
+/// The bytes of the GLB file
+pub fn write_glb(mut json: Value, views: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+    let mut used = vec![false; views.len()];
+    for_each_view_reference(&mut json, |view| {
+        if let Some(used) = view.as_u64().and_then(|i| used.get_mut(i as usize)) {
+            *used = true;
+        }
+    });
+
+    let old_views = json.get("bufferViews").and_then(Value::as_array).cloned().unwrap_or_default();
+    let mut bin = Vec::new();
+    let mut new_views = Vec::new();
+    let mut remap = vec![None; views.len()];
+    for (i, data) in views.iter().enumerate().filter(|(i, _)| used[*i]) {
+        align(&mut bin);
+        let mut view = old_views.get(i).cloned().unwrap_or_else(|| json!({}));
+        view["buffer"] = json!(0);
+        view["byteOffset"] = json!(bin.len());
+        view["byteLength"] = json!(data.len());
+        remap[i] = Some(new_views.len());
+        new_views.push(view);
+        bin.extend_from_slice(data);
+    }
+    for_each_view_reference(&mut json, |view| {
+        if let Some(new) = view.as_u64().and_then(|i| remap.get(i as usize).copied().flatten()) {
+            *view = json!(new);
+        }
+    });
+
+    let object = json.as_object_mut().ok_or("glTF document must be a JSON object")?;
+    if new_views.is_empty() {
+        object.remove("bufferViews");
+    } else {
+        object.insert("bufferViews".to_string(), Value::Array(new_views));
+    }
+    assemble_glb(json, bin)
+}
+
+/// Calls `visit` with every buffer view index held by an accessor or image
+fn for_each_view_reference(json: &mut Value, mut visit: impl FnMut(&mut Value)) {
+    for accessor in json.get_mut("accessors").and_then(Value::as_array_mut).into_iter().flatten() {
+        if let Some(view) = accessor.get_mut("bufferView") {
+            visit(view);
+        }
+        if let Some(sparse) = accessor.get_mut("sparse") {
+            for key in ["indices", "values"] {
+                if let Some(view) = sparse.get_mut(key).and_then(|part| part.get_mut("bufferView")) {
+                    visit(view);
+                }
+            }
+        }
+    }
+    for image in json.get_mut("images").and_then(Value::as_array_mut).into_iter().flatten() {
+        if let Some(view) = image.get_mut("bufferView") {
+            visit(view);
+        }
+    }
+}
+
+/// Assembles a GLB file from its JSON document and binary chunk
+fn assemble_glb(mut json: Value, mut bin: Vec<u8>) -> Result<Vec<u8>, String> {
+    align(&mut bin);
+    if bin.is_empty() {
+        json.as_object_mut().ok_or("glTF document must be a JSON object")?.remove("buffers");
+    } else {
+        json["buffers"] = json!([{ "byteLength": bin.len() }]);
+    }
+
+    let mut json = serde_json::to_vec(&json).map_err(|e| e.to_string())?;
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let total = 12 + 8 + json.len() + if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let total = u32::try_from(total).map_err(|_| "the model is larger than 4 GB".to_string())?;
+
+    let mut glb = Vec::with_capacity(total as usize);
+    glb.extend_from_slice(GLB_MAGIC);
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&total.to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+    glb.extend_from_slice(&json);
+    if !bin.is_empty() {
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+    }
+    Ok(glb)
+}
+
+/// Reads the data of a `data:` URI or of a file relative to `base`
+fn load_uri(uri: &str, base: &Path) -> Result<Vec<u8>, String> {
+    if uri.starts_with("data:") {
+        let (_, data) = uri.split_once(";base64,").ok_or("data URI is not base64-encoded")?;
+        return base64::engine::general_purpose::STANDARD.decode(data)
+            .map_err(|e| format!("data URI is not valid base64: {}", e));
+    }
+    let relative = relative_path(uri)?;
+    fs::read(base.join(&relative)).map_err(|e| format!("cannot read '{}': {}", relative.display(), e))
+}
+
+/// Identifies the image formats glTF viewers can decode
+pub fn image_mime_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if data.starts_with(b"\xABKTX 20\xBB\r\n\x1a\n") {
+        Some("image/ktx2")
+    } else {
+        None
+    }
+}
+
+/// Pads the binary chunk to the 4-byte alignment buffer views need
+fn align(bin: &mut Vec<u8>) {
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_uris() {
+        assert_eq!(relative_path("scene.bin"), Ok(PathBuf::from("scene.bin")));
+        assert_eq!(relative_path("./textures/wood%20floor.png"), Ok(PathBuf::from("textures/wood floor.png")));
+        assert_eq!(relative_path("a/./b//c.png"), Ok(PathBuf::from("a/b/c.png")));
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        for uri in ["../secret.bin", "textures/../../secret.bin", "a/.."] {
+            assert!(relative_path(uri).unwrap_err().contains("points outside"), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_paths_and_schemes() {
+        for uri in ["/etc/passwd", "C:/model.bin", "C:\\model.bin", "textures\\..\\x.png", "file:///etc/passwd", "https://example.com/a.bin"] {
+            assert!(relative_path(uri).unwrap_err().contains("is not a relative path"), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn rejects_percent_encoded_traversal() {
+        for uri in ["%2e%2e/secret.bin", "textures/%2E%2E/%2e%2e/secret.bin"] {
+            assert!(relative_path(uri).unwrap_err().contains("points outside"), "{}", uri);
+        }
+        for uri in ["%2Fetc%2Fpasswd", "..%5Csecret.bin", "c%3A/model.bin"] {
+            assert!(relative_path(uri).unwrap_err().contains("is not a relative path"), "{}", uri);
+        }
+        assert!(relative_path("%ff.bin").unwrap_err().contains("not valid UTF-8"));
+    }
+
+    fn validate_nodes(nodes: Value, scene: Value) -> Result<(), String> {
+        let json = json!({"asset": {"version": "2.0"}, "nodes": nodes, "scenes": [{"nodes": scene}]});
+        validate(&Document { json, bin_len: None }, None)
+    }
+
+    #[test]
+    fn requires_node_trees() {
+        assert_eq!(validate_nodes(json!([{"children": [1, 2]}, {}, {"children": [3]}, {}]), json!([0])), Ok(()));
+        let error = validate_nodes(json!([{"children": [2]}, {"children": [2]}, {}]), json!([0, 1])).unwrap_err();
+        assert!(error.contains("nodes[2] has more than one parent"), "{}", error);
+        let error = validate_nodes(json!([{"children": [1]}, {"children": [0]}]), json!([])).unwrap_err();
+        assert!(error.contains("is its own ancestor"), "{}", error);
+        let error = validate_nodes(json!([{"children": [0]}]), json!([])).unwrap_err();
+        assert!(error.contains("nodes[0] is its own ancestor"), "{}", error);
+        let error = validate_nodes(json!([{"children": [1]}, {}]), json!([1])).unwrap_err();
+        assert!(error.contains("is not a root node"), "{}", error);
+    }
+
+    #[test]
+    fn visits_each_node_once() {
+        let json = json!({"scene": 0, "scenes": [{"nodes": [0, 0]}], "nodes": [{"mesh": 0, "children": [0]}]});
+        assert_eq!(mesh_instances(&json).len(), 1);
+    }
+
+    #[test]
+    fn rejects_empty_paths() {
+        for uri in ["", ".", "./."] {
+            assert!(relative_path(uri).unwrap_err().contains("does not name a file"), "{:?}", uri);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! HLS adaptive bitrate packaging
+//!
+//! Transcodes a video into a ladder of H.264/AAC renditions, each split into
+//! MPEG-TS segments with its own media playlist, plus a master playlist that
+//! lets players switch between them as bandwidth changes.
+
+use std::{ffi::OsString, fs, path::Path};
+
+use super::{ffmpeg, preview, ProcessingResult};
+
+/// Filename of the master playlist inside a packaging folder
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+
+/// Target segment length in seconds
+const SEGMENT_SECONDS: u32 = 6;
+
+/// One step of the bitrate ladder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rendition {
+    /// Name of the rendition folder, e.g. `720p`
+    pub name: &'static str,
+    /// Length of the shorter edge in pixels
+    pub size: u32,
+    /// Video bitrate in kbit/s
+    pub video_bitrate: u32,
+    /// Audio bitrate in kbit/s
+    pub audio_bitrate: u32,
+}
+
+/// Renditions from highest to lowest quality
+const LADDER: &[Rendition] = &[
+    Rendition { name: "1080p", size: 1080, video_bitrate: 5000, audio_bitrate: 192 },
+    Rendition { name: "720p", size: 720, video_bitrate: 2800, audio_bitrate: 128 },
+    Rendition { name: "480p", size: 480, video_bitrate: 1400, audio_bitrate: 128 },
+    Rendition { name: "360p", size: 360, video_bitrate: 800, audio_bitrate: 96 },
+];
+
+/// Picks the renditions for a video, never upscaling beyond the source
+///
+/// # Arguments
+/// * `size` - Display size of the source, if known
+pub fn ladder(size: Option<(u32, u32)>) -> Vec<Rendition> {
+    let Some(shorter) = size.map(|(w, h)| w.min(h)) else {
+        return LADDER[1..].to_vec();
+    };
+    let renditions: Vec<Rendition> = LADDER.iter().copied().filter(|r| r.size <= shorter).collect();
+    if renditions.is_empty() {
+        LADDER[LADDER.len() - 1..].to_vec()
+    } else {
+        renditions
+    }
+}
+
+/// Packages a video as HLS
+///
+/// The output is written to a temporary folder that is renamed into place
+/// once ffmpeg succeeds, so `destination` never holds a partial ladder.
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `destination` - Folder to create; must not exist yet
+/// * `size` - Display size of the source, used to pick the ladder and orientation
+/// * `has_audio` - Whether the source has an audio track to include
+pub fn package(source: &Path, destination: &Path, size: Option<(u32, u32)>, has_audio: bool) -> ProcessingResult<()> {
+    preview::replace_atomically(destination, |tmp| {
+        for rendition in ladder(size) {
+            fs::create_dir_all(tmp.join(rendition.name))?;
+        }
+        ffmpeg::run(package_args(source, tmp, size, has_audio))?;
+        match tmp.join(MASTER_PLAYLIST).is_file() {
+            true => Ok(()),
+            false => Err("ffmpeg wrote no master playlist".into()),
+        }
+    })
+}
+
+/// Builds the ffmpeg arguments for a single pass producing every rendition
+fn package_args(source: &Path, folder: &Path, size: Option<(u32, u32)>, has_audio: bool) -> Vec<OsString> {
+    let renditions = ladder(size);
+    let portrait = size.is_some_and(|(w, h)| h > w);
+
+    let outputs: Vec<String> = (0..renditions.len()).map(|i| format!("[v{}]", i)).collect();
+    let mut filter = format!("[0:v]split={}{}", renditions.len(), outputs.concat());
+    for (i, rendition) in renditions.iter().enumerate() {
+        let scale = if portrait {
+            format!("scale={}:-2", rendition.size)
+        } else {
+            format!("scale=-2:{}", rendition.size)
+        };
+        filter.push_str(&format!(";[v{i}]{scale}[out{i}]"));
+    }
+
+    let mut args: Vec<OsString> = vec!["-i".into(), source.into(), "-filter_complex".into(), filter.into()];
+    for (i, rendition) in renditions.iter().enumerate() {
+        args.extend([
+            "-map".into(), format!("[out{}]", i).into(),
+            format!("-b:v:{}", i).into(), format!("{}k", rendition.video_bitrate).into(),
+            format!("-maxrate:v:{}", i).into(), format!("{}k", rendition.video_bitrate * 107 / 100).into(),
+            format!("-bufsize:v:{}", i).into(), format!("{}k", rendition.video_bitrate * 3 / 2).into(),
+        ]);
+        if has_audio {
+            args.extend([
+                "-map".into(), "0:a:0".into(),
+                format!("-b:a:{}", i).into(), format!("{}k", rendition.audio_bitrate).into(),
+            ]);
+        }
+    }
+
+    let stream_map: Vec<String> = renditions.iter().enumerate()
+        .map(|(i, rendition)| match has_audio {
+            true => format!("v:{i},a:{i},name:{}", rendition.name),
+            false => format!("v:{i},name:{}", rendition.name),
+        })
+        .collect();
+
+    // Key frames on every segment boundary keep the renditions switchable
+    let key_frames = format!("expr:gte(t,n_forced*{})", SEGMENT_SECONDS);
+    args.extend([
+        "-c:v".into(), "libx264".into(),
+        "-preset".into(), "veryfast".into(),
+        "-profile:v".into(), "high".into(),
+        "-pix_fmt".into(), "yuv420p".into(),
+        "-force_key_frames".into(), key_frames.into(),
+        "-sc_threshold".into(), "0".into(),
+        "-c:a".into(), "aac".into(),
+        "-ac".into(), "2".into(),
+        "-f".into(), "hls".into(),
+        "-hls_time".into(), SEGMENT_SECONDS.to_string().into(),
+        "-hls_playlist_type".into(), "vod".into(),
+        "-hls_flags".into(), "independent_segments".into(),
+        "-hls_segment_filename".into(), folder.join("%v").join("segment_%04d.ts").into(),
+        "-master_pl_name".into(), MASTER_PLAYLIST.into(),
+        "-var_stream_map".into(), stream_map.join(" ").into(),
+        folder.join("%v").join("index.m3u8").into(),
+    ]);
+    args
+}
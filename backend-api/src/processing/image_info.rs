@@ -0,0 +1,203 @@
+//! Image header inspection
+//!
+//! Reads dimensions, EXIF orientation, pixel format and colour profile of
+//! photos without decoding the pixel data.
+
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder},
+    ColorType, DynamicImage, ImageDecoder, ImageFormat,
+};
+use serde::{Serialize, Deserialize};
+use std::{fs::File, io::{BufReader, Read}, path::Path};
+
+use super::ProcessingResult;
+
+/// How many bytes of a JPEG are scanned for the EXIF segment
+const EXIF_SCAN_LIMIT: u64 = 256 * 1024;
+
+/// EXIF tag holding the orientation
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+/// Layout and colour information recorded on `Photo`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImageInfo {
+    /// Width of the published image in pixels
+    pub width: u32,
+    /// Height of the published image in pixels
+    pub height: u32,
+    /// `width / height` of the published image
+    pub aspect_ratio: f64,
+    /// `landscape`, `portrait` or `square`
+    pub orientation: String,
+    /// EXIF orientation (1-8) of the original, when it carries one
+    pub exif_orientation: Option<u16>,
+    /// Bits per channel of the original
+    pub bit_depth: u8,
+    /// Pixel layout of the original, e.g. `rgb8` or `rgba16`
+    pub color_type: String,
+    /// Description of the embedded ICC profile, or `sRGB` when untagged
+    pub color_space: String,
+}
+
+/// Inspects a photo
+///
+/// # Arguments
+/// * `original` - Private original, describes the pixel format and colour space
+/// * `public` - Published copy, describes the layout visitors will see
+pub fn inspect(original: &Path, public: &Path) -> ProcessingResult<ImageInfo> {
+    let (mut width, mut height) = image::image_dimensions(public)?;
+    if matches!(read_orientation(public), Some(5..=8)) {
+        std::mem::swap(&mut width, &mut height);
+    }
+
+    let (color_type, icc_profile) = read_color(original)?;
+    let channels = color_type.channel_count().max(1) as u16;
+
+    Ok(ImageInfo {
+        width,
+        height,
+        aspect_ratio: width as f64 / height.max(1) as f64,
+        orientation: match width.cmp(&height) {
+            std::cmp::Ordering::Greater => "landscape",
+            std::cmp::Ordering::Less => "portrait",
+            std::cmp::Ordering::Equal => "square",
+        }.to_string(),
+        exif_orientation: read_orientation(original),
+        bit_depth: (color_type.bits_per_pixel() / channels) as u8,
+        color_type: format!("{:?}", color_type).to_lowercase(),
+        color_space: icc_profile
+            .as_deref()
+            .and_then(icc_description)
+            .unwrap_or_else(|| "sRGB".to_string()),
+    })
+}
+
+/// Reads the colour type and ICC profile from the image header
+fn read_color(path: &Path) -> ProcessingResult<(ColorType, Option<Vec<u8>>)> {
+    let format = ImageFormat::from_path(path)?;
+    let reader = BufReader::new(File::open(path)?);
+
+    Ok(match format {
+        ImageFormat::Jpeg => {
+            let mut decoder = JpegDecoder::new(reader)?;
+            (decoder.color_type(), decoder.icc_profile())
+        },
+        ImageFormat::Png => {
+            let mut decoder = PngDecoder::new(reader)?;
+            (decoder.color_type(), decoder.icc_profile())
+        },
+        ImageFormat::WebP => {
+            let mut decoder = WebPDecoder::new(reader)?;
+            (decoder.color_type(), decoder.icc_profile())
+        },
+        ImageFormat::Tiff => {
+            let mut decoder = TiffDecoder::new(reader)?;
+            (decoder.color_type(), decoder.icc_profile())
+        },
+        ImageFormat::Gif => (GifDecoder::new(reader)?.color_type(), None),
+        _ => (image::open(path)?.color(), None),
+    })
+}
+
+/// Extracts the profile description from an ICC profile (`desc` or `mluc` tag)
+fn icc_description(profile: &[u8]) -> Option<String> {
+    let be_u32 = |at: usize| -> Option<u32> {
+        profile.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let tag_count = be_u32(128)? as usize;
+    let (offset, size) = (0..tag_count).find_map(|i| {
+        let entry = 132 + i * 12;
+        (profile.get(entry..entry + 4)? == b"desc")
+            .then(|| Some((be_u32(entry + 4)? as usize, be_u32(entry + 8)? as usize)))
+            .flatten()
+    })?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+
+    let description = match tag.get(0..4)? {
+        b"desc" => {
+            let length = u32::from_be_bytes(tag.get(8..12)?.try_into().ok()?) as usize;
+            let text = tag.get(12..12 + length)?;
+            String::from_utf8_lossy(text).trim_end_matches('\0').to_string()
+        },
+        b"mluc" => {
+            let record_length = u32::from_be_bytes(tag.get(20..24)?.try_into().ok()?) as usize;
+            let record_offset = u32::from_be_bytes(tag.get(24..28)?.try_into().ok()?) as usize;
+            let text = tag.get(record_offset..record_offset + record_length)?;
+            let units: Vec<u16> = text.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units).trim_end_matches('\0').to_string()
+        },
+        _ => return None,
+    };
+
+    let description = description.trim().to_string();
+    (!description.is_empty()).then_some(description)
+}
+
+/// Reads the EXIF orientation of a JPEG
+///
+/// # Returns
+/// The orientation (1-8), or `None` for other formats and JPEGs without EXIF
+pub fn read_orientation(path: &Path) -> Option<u16> {
+    let mut data = Vec::new();
+    File::open(path).ok()?.take(EXIF_SCAN_LIMIT).read_to_end(&mut data).ok()?;
+    if data.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        if marker == 0xD9 || marker == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return tiff_orientation(&segment[6..]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Finds the orientation tag in IFD0 of a TIFF structure
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let b = tiff.get(at..at + 2)?;
+        Some(if little_endian { u16::from_le_bytes([b[0], b[1]]) } else { u16::from_be_bytes([b[0], b[1]]) })
+    };
+    let u32_at = |at: usize| -> Option<u32> {
+        let b: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let entries = u16_at(ifd)? as usize;
+    (0..entries).find_map(|i| {
+        let entry = ifd + 2 + i * 12;
+        (u16_at(entry)? == EXIF_ORIENTATION_TAG).then(|| u16_at(entry + 8)).flatten()
+    }).filter(|o| (1..=8).contains(o))
+}
+
+/// Rotates and mirrors an image so that it is displayed upright
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
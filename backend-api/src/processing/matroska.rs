@@ -0,0 +1,294 @@
+//! Matroska and WebM parsing
+//!
+//! Reads the EBML structure of Matroska and WebM files up to the segment
+//! information and track list. Clusters holding the media data are never
+//! loaded.
+
+use std::{fs::File, io::{BufReader, Read}, path::Path};
+
+use super::{video::{MediaInfo, Track, TrackKind}, ProcessingResult};
+
+const ID_EBML: u64 = 0x1A45DFA3;
+const ID_SEGMENT: u64 = 0x18538067;
+const ID_INFO: u64 = 0x1549A966;
+const ID_TIMECODE_SCALE: u64 = 0x2AD7B1;
+const ID_DURATION: u64 = 0x4489;
+const ID_TRACKS: u64 = 0x1654AE6B;
+const ID_CLUSTER: u64 = 0x1F43B675;
+const ID_TRACK_ENTRY: u64 = 0xAE;
+const ID_TRACK_TYPE: u64 = 0x83;
+const ID_CODEC_ID: u64 = 0x86;
+const ID_DEFAULT_DURATION: u64 = 0x23E383;
+const ID_VIDEO: u64 = 0xE0;
+const ID_PIXEL_WIDTH: u64 = 0xB0;
+const ID_PIXEL_HEIGHT: u64 = 0xBA;
+
+/// Timestamps are in milliseconds unless the segment says otherwise
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/// Largest top-level element that will be loaded into memory
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Decodes a variable-length integer from its first byte and the bytes after it
+///
+/// # Returns
+/// The value, its encoded length, and whether it was the reserved "unknown size"
+fn decode_vint(first: u8, rest: &[u8], keep_marker: bool) -> Option<(u64, usize, bool)> {
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || rest.len() < len - 1 {
+        return None;
+    }
+    let marker = 0x80u8 >> (len - 1);
+    let mut value = if keep_marker { first as u64 } else { (first & !marker) as u64 };
+    for &byte in &rest[..len - 1] {
+        value = (value << 8) | byte as u64;
+    }
+    let unknown = !keep_marker && value == (1u64 << (7 * len)) - 1;
+    Some((value, len, unknown))
+}
+
+/// Iterates over the elements contained in `data`, yielding their ID and body
+fn elements(data: &[u8]) -> impl Iterator<Item = (u64, &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let (id, id_len, _) = decode_vint(*data.get(pos)?, data.get(pos + 1..)?, true)?;
+        let at = pos + id_len;
+        let (size, size_len, unknown) = decode_vint(*data.get(at)?, data.get(at + 1..)?, false)?;
+        let start = at + size_len;
+        let end = if unknown { data.len() } else { start.checked_add(size as usize)?.min(data.len()) };
+        pos = end;
+        Some((id, &data[start..end]))
+    })
+}
+
+/// Finds the first child element with the given ID
+fn child(data: &[u8], id: u64) -> Option<&[u8]> {
+    elements(data).find(|(i, _)| *i == id).map(|(_, body)| body)
+}
+
+/// Reads an unsigned integer element
+fn uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+/// Reads a 4 or 8 byte float element
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Reads a variable-length integer from a file
+fn read_vint(file: &mut impl Read, keep_marker: bool) -> ProcessingResult<(u64, bool)> {
+    let mut first = [0u8; 1];
+    file.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err("invalid EBML integer".into());
+    }
+    let mut rest = [0u8; 7];
+    file.read_exact(&mut rest[..len - 1])?;
+    let (value, _, unknown) = decode_vint(first[0], &rest[..len - 1], keep_marker)
+        .ok_or("invalid EBML integer")?;
+    Ok((value, unknown))
+}
+
+/// Reads the top-level elements of the segment that are needed for probing
+///
+/// Reading stops at the first cluster, since the track list always precedes
+/// the media data in files written for streaming.
+fn read_segment_elements(path: &Path, wanted: &[u64]) -> ProcessingResult<Vec<(u64, Vec<u8>)>> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let (id, _) = read_vint(&mut file, true)?;
+    if id != ID_EBML {
+        return Err("not an EBML file".into());
+    }
+    let (size, _) = read_vint(&mut file, false)?;
+    file.seek_relative(i64::try_from(size).map_err(|_| "invalid EBML header size")?)?;
+
+    let (id, _) = read_vint(&mut file, true)?;
+    if id != ID_SEGMENT {
+        return Err("file has no Matroska segment".into());
+    }
+    read_vint(&mut file, false)?;
+
+    let mut found = Vec::new();
+    while found.len() < wanted.len() {
+        let Ok((id, _)) = read_vint(&mut file, true) else { break };
+        let (size, unknown) = read_vint(&mut file, false)?;
+        if id == ID_CLUSTER || unknown {
+            break;
+        }
+
+        if wanted.contains(&id) {
+            if size > MAX_ELEMENT_SIZE {
+                return Err("Matroska element is too large".into());
+            }
+            let mut body = vec![0u8; size as usize];
+            file.read_exact(&mut body)?;
+            found.push((id, body));
+        } else {
+            file.seek_relative(i64::try_from(size).map_err(|_| "invalid Matroska element size")?)?;
+        }
+    }
+
+    Ok(found)
+}
+
+/// Reads the duration and tracks of a Matroska or WebM file
+pub fn read(path: &Path) -> ProcessingResult<MediaInfo> {
+    let found = read_segment_elements(path, &[ID_INFO, ID_TRACKS])?;
+    let element = |id: u64| found.iter().find(|(i, _)| *i == id).map(|(_, body)| body.as_slice());
+    let tracks = element(ID_TRACKS).ok_or("file has no track list")?;
+
+    // Files recorded by browsers often carry no duration at all
+    let duration_ms = element(ID_INFO).and_then(|info| {
+        let scale = child(info, ID_TIMECODE_SCALE).map(uint).unwrap_or(DEFAULT_TIMECODE_SCALE);
+        let duration = child(info, ID_DURATION).and_then(float)?;
+        (duration.is_finite() && duration > 0.0)
+            .then(|| (duration * scale as f64 / 1_000_000.0).round() as u64)
+    });
+
+    let tracks = elements(tracks)
+        .filter(|(id, _)| *id == ID_TRACK_ENTRY)
+        .filter_map(|(_, entry)| {
+            let kind = match child(entry, ID_TRACK_TYPE).map(uint) {
+                Some(1) => TrackKind::Video,
+                Some(2) => TrackKind::Audio,
+                _ => TrackKind::Other,
+            };
+            let codec_id = String::from_utf8_lossy(child(entry, ID_CODEC_ID)?);
+            let mut track = Track {
+                kind,
+                codec: codec_name(codec_id.trim_end_matches('\0')),
+                ..Track::default()
+            };
+
+            if kind == TrackKind::Video {
+                let video = child(entry, ID_VIDEO);
+                track.width = video.and_then(|v| child(v, ID_PIXEL_WIDTH)).map(|w| uint(w) as u32);
+                track.height = video.and_then(|v| child(v, ID_PIXEL_HEIGHT)).map(|h| uint(h) as u32);
+                // DefaultDuration is the duration of one frame in nanoseconds
+                track.frame_rate = child(entry, ID_DEFAULT_DURATION)
+                    .map(uint)
+                    .filter(|&ns| ns > 0)
+                    .map(|ns| 1_000_000_000.0 / ns as f64);
+            }
+
+            Some(track)
+        })
+        .collect();
+
+    Ok(MediaInfo { duration_ms, tracks })
+}
+
+/// Maps a Matroska codec ID to a codec name
+fn codec_name(codec_id: &str) -> String {
+    match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_AV1" => "av1",
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_THEORA" => "theora",
+        "V_PRORES" => "prores",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_FLAC" => "flac",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        "A_MPEG/L3" => "mp3",
+        id if id.starts_with("A_AAC") => "aac",
+        id => {
+            let id = id.strip_prefix("V_").or_else(|| id.strip_prefix("A_")).unwrap_or(id);
+            return id.to_lowercase();
+        },
+    }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// An element with an 8-byte size
+    fn element(id: u64, body: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = id.to_be_bytes().into_iter().skip_while(|&b| b == 0).collect();
+        out.push(0x01);
+        out.extend(&(body.len() as u64).to_be_bytes()[1..]);
+        out.extend(body);
+        out
+    }
+
+    fn uint_element(id: u64, value: u64) -> Vec<u8> {
+        element(id, &value.to_be_bytes())
+    }
+
+    #[test]
+    fn decodes_variable_length_integers() {
+        assert_eq!(decode_vint(0x81, &[], false), Some((1, 1, false)));
+        assert_eq!(decode_vint(0x40, &[0x02], false), Some((2, 2, false)));
+        assert_eq!(decode_vint(0x1A, &[0x45, 0xDF, 0xA3], true), Some((ID_EBML, 4, false)));
+        assert_eq!(decode_vint(0xFF, &[], false), Some((0x7F, 1, true)));
+        assert_eq!(decode_vint(0x01, &[0xFF; 7], false), Some(((1 << 56) - 1, 8, true)));
+        // IDs keep their marker, so all ones is a valid ID rather than an unknown size
+        assert_eq!(decode_vint(0xFF, &[], true), Some((0xFF, 1, false)));
+        assert_eq!(decode_vint(0x00, &[0; 8], false), None);
+        assert_eq!(decode_vint(0x40, &[], false), None);
+    }
+
+    #[test]
+    fn walks_elements() {
+        let data = [element(ID_TRACK_TYPE, &[1]), element(ID_CODEC_ID, b"V_VP9")].concat();
+        let found: Vec<(u64, &[u8])> = elements(&data).collect();
+        assert_eq!(found, [(ID_TRACK_TYPE, &[1][..]), (ID_CODEC_ID, b"V_VP9")]);
+        // Sizes past the end are clamped, and unknown sizes run to the end
+        assert_eq!(elements(&[0x83, 0x85, 1, 2]).collect::<Vec<_>>(), [(ID_TRACK_TYPE, &[1, 2][..])]);
+        assert_eq!(elements(&[0x83, 0xFF, 1, 2]).collect::<Vec<_>>(), [(ID_TRACK_TYPE, &[1, 2][..])]);
+        assert_eq!(uint(&[0x01, 0x00]), 256);
+        assert_eq!(float(&5000f64.to_be_bytes()), Some(5000.0));
+        assert_eq!(float(&[0; 3]), None);
+    }
+
+    #[test]
+    fn reads_duration_and_tracks() {
+        let info = [uint_element(ID_TIMECODE_SCALE, 1_000_000), element(ID_DURATION, &12_345.4f64.to_be_bytes())].concat();
+        let video = [
+            uint_element(ID_TRACK_TYPE, 1),
+            element(ID_CODEC_ID, b"V_VP9"),
+            uint_element(ID_DEFAULT_DURATION, 40_000_000),
+            element(ID_VIDEO, &[uint_element(ID_PIXEL_WIDTH, 640), uint_element(ID_PIXEL_HEIGHT, 360)].concat()),
+        ].concat();
+        let audio = [uint_element(ID_TRACK_TYPE, 2), element(ID_CODEC_ID, b"A_OPUS\0")].concat();
+        let tracks = [element(ID_TRACK_ENTRY, &video), element(ID_TRACK_ENTRY, &audio)].concat();
+        // A live recording: the segment has an unknown size
+        let mut segment = vec![0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        segment.extend([element(ID_INFO, &info), element(ID_TRACKS, &tracks), element(ID_CLUSTER, &[0; 8])].concat());
+        let file = [element(ID_EBML, &element(0x4282, b"webm")), segment].concat();
+
+        let path = env::temp_dir().join(format!("matroska_{}.webm", process::id()));
+        fs::write(&path, file).unwrap();
+        let media = read(&path);
+        let _ = fs::remove_file(&path);
+        let media = media.unwrap();
+
+        assert_eq!(media.duration_ms, Some(12_345));
+        assert_eq!(media.tracks.len(), 2);
+        let video = &media.tracks[0];
+        assert_eq!((video.kind, video.codec.as_str()), (TrackKind::Video, "vp9"));
+        assert_eq!((video.width, video.height, video.frame_rate), (Some(640), Some(360), Some(25.0)));
+        assert_eq!((media.tracks[1].kind, media.tracks[1].codec.as_str()), (TrackKind::Audio, "opus"));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = env::temp_dir().join(format!("matroska_{}.bin", process::id()));
+        fs::write(&path, element(0x4282, b"webm")).unwrap();
+        let error = read(&path).unwrap_err().to_string();
+        let _ = fs::remove_file(&path);
+        assert_eq!(error, "not an EBML file");
+    }
+}
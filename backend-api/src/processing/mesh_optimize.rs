@@ -0,0 +1,709 @@
+//! glTF mesh optimization and level-of-detail generation
+//!
+//! Rewrites the triangle meshes of GLB models so GPUs draw them with less
+//! work, and generates simplified copies so viewers on slow connections can
+//! show a light level of detail first:
+//! - identical vertices are merged,
+//! - triangles are reordered for the post-transform vertex cache (Tipsify),
+//! - vertices are reordered in the order the triangles use them,
+//! - levels of detail are simplified with quadric-error edge collapses.
+
+use serde_json::{json, Value};
+use std::{collections::HashMap, env, path::Path};
+
+use super::{gltf::{self, AccessorData, Document}, ProcessingResult};
+
+/// Triangle ratios of the levels of detail when `MODEL_LOD_RATIOS` is not set
+const DEFAULT_LOD_RATIOS: &[f64] = &[0.5, 0.25];
+
+/// Size of the vertex cache modelled when ordering triangles
+const CACHE_SIZE: usize = 16;
+
+/// Extensions that store meshes in compressed buffer views this module cannot rewrite
+const COMPRESSION_EXTENSIONS: &[&str] = &["KHR_draco_mesh_compression", "EXT_meshopt_compression"];
+
+/// `target` of buffer views holding vertex attributes
+const ARRAY_BUFFER: u64 = 34962;
+
+/// `target` of buffer views holding indices
+const ELEMENT_ARRAY_BUFFER: u64 = 34963;
+
+/// One optimized copy of a model
+#[derive(Debug)]
+pub struct Level {
+    /// Share of the triangles of each mesh kept; 1 for the full-detail copy
+    pub ratio: f64,
+    pub triangle_count: u64,
+    /// The GLB file
+    pub glb: Vec<u8>,
+}
+
+/// Triangle ratios of the levels of detail, largest first
+///
+/// Configured as a comma-separated list with `MODEL_LOD_RATIOS`; ratios
+/// outside `(0, 1)` are ignored, so `0` disables levels of detail.
+pub fn lod_ratios() -> Vec<f64> {
+    let mut ratios: Vec<f64> = match env::var("MODEL_LOD_RATIOS") {
+        Ok(value) => value.split(',').filter_map(|r| r.trim().parse().ok()).collect(),
+        Err(_) => DEFAULT_LOD_RATIOS.to_vec(),
+    };
+    ratios.retain(|r| *r > 0.0 && *r < 1.0);
+    ratios.sort_by(|a, b| b.total_cmp(a));
+    ratios.dedup();
+    ratios
+}
+
+/// A triangle list primitive whose accessors are used by nothing else, so
+/// they can be rewritten in place
+struct Primitive {
+    mesh: usize,
+    index: usize,
+    /// Attribute name, accessor index and merged vertex data
+    attributes: Vec<(String, usize, AccessorData)>,
+    indices_accessor: Option<usize>,
+    /// Indices into the merged vertices
+    indices: Vec<u32>,
+    positions: Vec<[f32; 3]>,
+}
+
+/// Optimizes the meshes of a GLB model and generates its levels of detail
+///
+/// Only triangle lists without morph targets whose accessors are not shared
+/// with other primitives are rewritten; everything else is copied as is.
+///
+/// # Arguments
+/// * `path` - Validated GLB file
+/// * `ratios` - Triangle ratios of the levels of detail, as from `lod_ratios`
+///
+/// # Returns
+/// The full-detail optimized copy followed by each level of detail that has
+/// fewer triangles than the one before it
+pub fn generate(path: &Path, ratios: &[f64]) -> ProcessingResult<Vec<Level>> {
+    let document = gltf::read_glb(path)?;
+    let json = &document.json;
+    let used = gltf::extension_names(json.get("extensionsUsed"));
+    if let Some(extension) = COMPRESSION_EXTENSIONS.iter().find(|e| used.iter().any(|u| u == *e)) {
+        return Err(format!("meshes compressed with {} cannot be optimized", extension).into());
+    }
+    let buffers = gltf::read_buffers(path, &document)?;
+    let views = gltf::view_bytes(json, &buffers)?;
+
+    let primitives = read_primitives(json, &buffers)?;
+    if primitives.is_empty() {
+        return Err("the model has no triangle meshes that can be optimized".into());
+    }
+
+    let mut levels: Vec<Level> = Vec::new();
+    for ratio in std::iter::once(1.0).chain(ratios.iter().copied()) {
+        let mut json = json.clone();
+        let mut views = views.clone();
+        for primitive in &primitives {
+            let indices = if ratio < 1.0 {
+                let target = ((primitive.indices.len() / 3) as f64 * ratio).round().max(1.0) as usize;
+                simplify(&primitive.positions, &primitive.indices, target)
+            } else {
+                primitive.indices.clone()
+            };
+            let mut indices = optimize_cache(&indices, primitive.positions.len());
+            let order = optimize_fetch(&mut indices, primitive.positions.len());
+            write_primitive(&mut json, &mut views, primitive, &order, &indices)?;
+        }
+
+        let triangle_count = gltf::stats(&Document { json: json.clone(), bin_len: None }).triangle_count;
+        if levels.last().is_some_and(|level| level.triangle_count <= triangle_count) {
+            continue;
+        }
+        let glb = gltf::write_glb(json, &views)?;
+        levels.push(Level { ratio, triangle_count, glb });
+    }
+    Ok(levels)
+}
+
+/// Reads the primitives that can be rewritten, with identical vertices merged
+fn read_primitives(json: &Value, buffers: &[Vec<u8>]) -> ProcessingResult<Vec<Primitive>> {
+    let usage = accessor_usage(json);
+    let mut primitives = Vec::new();
+
+    let meshes = json.get("meshes").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    for (mesh, mesh_json) in meshes.iter().enumerate() {
+        let list = mesh_json.get("primitives").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+        for (index, primitive) in list.iter().enumerate() {
+            let Some(attributes) = primitive.get("attributes").and_then(Value::as_object) else { continue };
+            let indices_accessor = primitive.get("indices").and_then(Value::as_u64).map(|i| i as usize);
+            let accessors: Vec<usize> = attributes.values().filter_map(Value::as_u64).map(|i| i as usize)
+                .chain(indices_accessor)
+                .collect();
+            let eligible = primitive.get("mode").and_then(Value::as_u64).unwrap_or(4) == 4
+                && primitive.get("targets").is_none()
+                && primitive.get("extensions").is_none()
+                && accessors.iter().all(|a| usage.get(a) == Some(&1));
+            if !eligible {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            for (name, accessor) in attributes {
+                let accessor = accessor.as_u64().unwrap_or_default() as usize;
+                data.push((name.clone(), accessor, gltf::read_accessor(json, buffers, accessor)?));
+            }
+            let Some(position) = data.iter().position(|(name, _, d)| name == "POSITION" && d.components == 3) else { continue };
+            let vertex_count = data[position].2.len();
+            if data.iter().any(|(_, _, d)| d.len() != vertex_count) {
+                continue;
+            }
+            let indices = match indices_accessor {
+                Some(accessor) => gltf::read_indices(json, buffers, accessor)?,
+                None => (0..vertex_count as u32).collect(),
+            };
+            if indices.iter().any(|i| *i as usize >= vertex_count) {
+                return Err(format!("meshes[{}].primitives[{}] has an index past its vertices", mesh, index).into());
+            }
+
+            let mut attribute_data: Vec<AccessorData> = data.iter().map(|(_, _, d)| d.clone()).collect();
+            let indices = deduplicate(&mut attribute_data, &indices);
+            let positions = attribute_data[position].values.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect();
+            let attributes = data.into_iter().zip(attribute_data)
+                .map(|((name, accessor, _), merged)| (name, accessor, merged))
+                .collect();
+            primitives.push(Primitive { mesh, index, attributes, indices_accessor, indices, positions });
+        }
+    }
+    Ok(primitives)
+}
+
+/// Counts how often each accessor is referenced
+fn accessor_usage(json: &Value) -> HashMap<usize, usize> {
+    let mut usage = HashMap::new();
+    let mut count = |value: Option<&Value>| {
+        if let Some(index) = value.and_then(Value::as_u64) {
+            *usage.entry(index as usize).or_insert(0) += 1;
+        }
+    };
+    let array = |value: Option<&Value>| value.and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default().to_vec();
+
+    for mesh in array(json.get("meshes")) {
+        for primitive in array(mesh.get("primitives")) {
+            primitive.get("attributes").and_then(Value::as_object).into_iter().flatten().for_each(|(_, a)| count(Some(a)));
+            count(primitive.get("indices"));
+            for target in array(primitive.get("targets")) {
+                target.as_object().into_iter().flatten().for_each(|(_, a)| count(Some(a)));
+            }
+        }
+    }
+    for skin in array(json.get("skins")) {
+        count(skin.get("inverseBindMatrices"));
+    }
+    for animation in array(json.get("animations")) {
+        for sampler in array(animation.get("samplers")) {
+            count(sampler.get("input"));
+            count(sampler.get("output"));
+        }
+    }
+    for node in array(json.get("nodes")) {
+        let instancing = node.pointer("/extensions/EXT_mesh_gpu_instancing/attributes").and_then(Value::as_object);
+        instancing.into_iter().flatten().for_each(|(_, a)| count(Some(a)));
+    }
+    usage
+}
+
+/// Merges vertices whose attributes are all identical
+///
+/// # Returns
+/// The indices into the merged vertices, without degenerate triangles
+fn deduplicate(attributes: &mut [AccessorData], indices: &[u32]) -> Vec<u32> {
+    let vertex_count = attributes.first().map_or(0, AccessorData::len);
+    let mut unique: HashMap<Vec<u32>, u32> = HashMap::new();
+    let mut remap = vec![0u32; vertex_count];
+    let mut merged: Vec<Vec<f32>> = vec![Vec::new(); attributes.len()];
+    for (vertex, slot) in remap.iter_mut().enumerate() {
+        let key: Vec<u32> = attributes.iter().flat_map(|a| a.get(vertex).iter().map(|v| v.to_bits())).collect();
+        *slot = *unique.entry(key).or_insert_with(|| {
+            for (data, attribute) in merged.iter_mut().zip(attributes.iter()) {
+                data.extend_from_slice(attribute.get(vertex));
+            }
+            (merged[0].len() / attributes[0].components.max(1)) as u32 - 1
+        });
+    }
+    for (attribute, data) in attributes.iter_mut().zip(merged) {
+        attribute.values = data;
+    }
+
+    indices.chunks_exact(3)
+        .map(|t| [remap[t[0] as usize], remap[t[1] as usize], remap[t[2] as usize]])
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect()
+}
+
+/// An error quadric: the sum of squared distances to a set of planes
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Quadric of the plane `n·p + d = 0`, weighted by `weight`
+    fn plane(n: [f64; 3], d: f64, weight: f64) -> Self {
+        let [a, b, c] = n;
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|v| v * weight))
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        Quadric(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+
+    /// Squared distance of a point to the planes
+    fn error(&self, p: [f64; 3]) -> f64 {
+        let [a2, ab, ac, ad, b2, bc, bd, c2, cd, d2] = self.0;
+        let [x, y, z] = p;
+        a2 * x * x + 2.0 * ab * x * y + 2.0 * ac * x * z + 2.0 * ad * x
+            + b2 * y * y + 2.0 * bc * y * z + 2.0 * bd * y
+            + c2 * z * z + 2.0 * cd * z + d2
+    }
+}
+
+/// Simplifies a triangle list towards `target` triangles by collapsing
+/// vertices into their neighbours, cheapest first
+///
+/// Vertices on borders and on attribute seams (several vertices at one
+/// position, e.g. where UVs are cut) are kept in place, so the outline and
+/// the texture mapping survive; simplification stops early if only such
+/// vertices remain. Collapses that would flip a triangle are skipped.
+fn simplify(positions: &[[f32; 3]], indices: &[u32], target: usize) -> Vec<u32> {
+    let point = |v: u32| positions[v as usize].map(f64::from);
+    let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut alive = vec![true; triangles.len()];
+    let mut live = triangles.len();
+    if live <= target {
+        return indices.to_vec();
+    }
+
+    // Seams: several vertices at one position
+    let mut groups: HashMap<[u32; 3], u32> = HashMap::new();
+    let group: Vec<u32> = positions.iter().map(|p| {
+        let next = groups.len() as u32;
+        *groups.entry(p.map(f32::to_bits)).or_insert(next)
+    }).collect();
+    let mut group_sizes = vec![0u32; groups.len()];
+    group.iter().for_each(|g| group_sizes[*g as usize] += 1);
+    let mut locked: Vec<bool> = group.iter().map(|g| group_sizes[*g as usize] > 1).collect();
+
+    // Borders: edges between positions that only one triangle uses
+    let edge_key = |a: u32, b: u32| {
+        let (a, b) = (group[a as usize], group[b as usize]);
+        (a.min(b), a.max(b))
+    };
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for t in &triangles {
+        for k in 0..3 {
+            *edges.entry(edge_key(t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+        }
+    }
+    for t in &triangles {
+        for k in 0..3 {
+            if edges[&edge_key(t[k], t[(k + 1) % 3])] == 1 {
+                locked[t[k] as usize] = true;
+                locked[t[(k + 1) % 3] as usize] = true;
+            }
+        }
+    }
+
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    for t in &triangles {
+        let (p0, p1, p2) = (point(t[0]), point(t[1]), point(t[2]));
+        let normal = cross(sub(p1, p0), sub(p2, p0));
+        let length = dot(normal, normal).sqrt();
+        if length > 0.0 {
+            let n = normal.map(|c| c / length);
+            let quadric = Quadric::plane(n, -dot(n, p0), length / 2.0);
+            for v in t {
+                quadrics[*v as usize] = quadrics[*v as usize].add(&quadric);
+            }
+        }
+    }
+
+    // Each pass collapses an independent set of the cheapest edges
+    while live > target {
+        let mut candidates: Vec<(f64, u32, u32)> = Vec::new();
+        for tri in triangles.iter().zip(&alive).filter(|(_, alive)| **alive).map(|(tri, _)| tri) {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                for (u, v) in [(a, b), (b, a)] {
+                    if !locked[u as usize] {
+                        let cost = quadrics[u as usize].add(&quadrics[v as usize]).error(point(v));
+                        candidates.push((cost, u, v));
+                    }
+                }
+            }
+        }
+        if candidates.is_empty() {
+            break;
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // Triangles around each vertex, in compressed rows
+        let mut offsets = vec![0usize; positions.len() + 1];
+        for tri in triangles.iter().zip(&alive).filter(|(_, alive)| **alive).map(|(tri, _)| tri) {
+            tri.iter().for_each(|v| offsets[*v as usize + 1] += 1);
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+        let mut fill = offsets.clone();
+        let mut adjacency = vec![0usize; offsets[positions.len()]];
+        for (t, tri) in triangles.iter().enumerate().filter(|(t, _)| alive[*t]) {
+            for v in tri {
+                adjacency[fill[*v as usize]] = t;
+                fill[*v as usize] += 1;
+            }
+        }
+
+        let mut touched = vec![false; positions.len()];
+        let mut collapsed = 0;
+        for (_, u, v) in candidates {
+            if live <= target {
+                break;
+            }
+            if touched[u as usize] || touched[v as usize] {
+                continue;
+            }
+            let around = &adjacency[offsets[u as usize]..offsets[u as usize + 1]];
+            let flips = around.iter().any(|&t| {
+                let tri = triangles[t];
+                if !alive[t] || tri.contains(&v) {
+                    return false;
+                }
+                let moved = tri.map(|w| if w == u { v } else { w });
+                let before = cross(sub(point(tri[1]), point(tri[0])), sub(point(tri[2]), point(tri[0])));
+                let after = cross(sub(point(moved[1]), point(moved[0])), sub(point(moved[2]), point(moved[0])));
+                dot(before, after) <= 0.0
+            });
+            if flips {
+                continue;
+            }
+
+            for &t in around {
+                if !alive[t] {
+                    continue;
+                }
+                let tri = triangles[t].map(|w| if w == u { v } else { w });
+                triangles[t] = tri;
+                tri.iter().for_each(|w| touched[*w as usize] = true);
+                if tri[0] == tri[1] || tri[1] == tri[2] || tri[0] == tri[2] {
+                    alive[t] = false;
+                    live -= 1;
+                }
+            }
+            quadrics[v as usize] = quadrics[v as usize].add(&quadrics[u as usize]);
+            touched[u as usize] = true;
+            touched[v as usize] = true;
+            collapsed += 1;
+        }
+        if collapsed == 0 {
+            break;
+        }
+    }
+
+    triangles.into_iter().zip(alive).filter(|(_, alive)| *alive).flat_map(|(t, _)| t).collect()
+}
+
+/// Orders triangles so consecutive ones reuse recently transformed vertices,
+/// with the Tipsify algorithm (Sander, Nehab and Barczak, 2007)
+fn optimize_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let mut offsets = vec![0usize; vertex_count + 1];
+    indices.iter().for_each(|v| offsets[*v as usize + 1] += 1);
+    for i in 1..offsets.len() {
+        offsets[i] += offsets[i - 1];
+    }
+    let mut fill = offsets.clone();
+    let mut adjacency = vec![0usize; indices.len()];
+    for (i, v) in indices.iter().enumerate() {
+        adjacency[fill[*v as usize]] = i / 3;
+        fill[*v as usize] += 1;
+    }
+
+    let mut live: Vec<usize> = offsets.windows(2).map(|w| w[1] - w[0]).collect();
+    let mut cache_time = vec![0usize; vertex_count];
+    let mut emitted = vec![false; indices.len() / 3];
+    let mut dead_end: Vec<u32> = Vec::new();
+    let mut output = Vec::with_capacity(indices.len());
+    let mut time = CACHE_SIZE + 1;
+    let mut cursor = 0;
+
+    let mut fanning = indices.first().copied();
+    while let Some(f) = fanning {
+        let mut candidates = Vec::new();
+        for &t in &adjacency[offsets[f as usize]..offsets[f as usize + 1]] {
+            if emitted[t] {
+                continue;
+            }
+            for &v in &indices[t * 3..t * 3 + 3] {
+                output.push(v);
+                dead_end.push(v);
+                candidates.push(v);
+                live[v as usize] -= 1;
+                if time - cache_time[v as usize] > CACHE_SIZE {
+                    cache_time[v as usize] = time;
+                    time += 1;
+                }
+            }
+            emitted[t] = true;
+        }
+
+        // Prefer a vertex still in the cache whose triangles all fit before it is evicted
+        let mut best: Option<(usize, u32)> = None;
+        for &v in &candidates {
+            if live[v as usize] == 0 {
+                continue;
+            }
+            let age = time - cache_time[v as usize];
+            let priority = if age + 2 * live[v as usize] <= CACHE_SIZE { age } else { 0 };
+            if best.is_none_or(|(p, _)| priority > p) {
+                best = Some((priority, v));
+            }
+        }
+        fanning = best.map(|(_, v)| v).or_else(|| {
+            while let Some(v) = dead_end.pop() {
+                if live[v as usize] > 0 {
+                    return Some(v);
+                }
+            }
+            while cursor < vertex_count {
+                if live[cursor] > 0 {
+                    return Some(cursor as u32);
+                }
+                cursor += 1;
+            }
+            None
+        });
+    }
+    output
+}
+
+/// Renumbers vertices in the order the triangles first use them
+///
+/// # Returns
+/// The old index of each new vertex; unused vertices are left out
+fn optimize_fetch(indices: &mut [u32], vertex_count: usize) -> Vec<u32> {
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut order = Vec::new();
+    for index in indices.iter_mut() {
+        if remap[*index as usize] == u32::MAX {
+            remap[*index as usize] = order.len() as u32;
+            order.push(*index);
+        }
+        *index = remap[*index as usize];
+    }
+    order
+}
+
+/// Replaces the accessors of a primitive with its rewritten vertices and
+/// indices, each in a buffer view of its own
+fn write_primitive(
+    json: &mut Value,
+    views: &mut Vec<Vec<u8>>,
+    primitive: &Primitive,
+    order: &[u32],
+    indices: &[u32],
+) -> Result<(), String> {
+    let mut add_view = |json: &mut Value, data: Vec<u8>, target: u64, stride: Option<usize>| -> Result<usize, String> {
+        let list = json.as_object_mut().ok_or("glTF document must be a JSON object")?
+            .entry("bufferViews").or_insert_with(|| json!([]))
+            .as_array_mut().ok_or("bufferViews must be an array")?;
+        let mut view = json!({ "buffer": 0, "byteLength": data.len(), "target": target });
+        if let Some(stride) = stride {
+            view["byteStride"] = json!(stride);
+        }
+        list.push(view);
+        views.push(data);
+        Ok(list.len() - 1)
+    };
+
+    for (name, accessor_index, data) in &primitive.attributes {
+        let accessor = json.pointer(&format!("/accessors/{}", accessor_index)).cloned()
+            .ok_or_else(|| format!("accessors[{}] is missing", accessor_index))?;
+        let component_type = accessor.get("componentType").and_then(Value::as_u64).unwrap_or(5126);
+        let normalized = accessor.get("normalized").and_then(Value::as_bool).unwrap_or(false);
+        let size = gltf::component_size(component_type).unwrap_or(4);
+        // Every vertex attribute element must start on a 4-byte boundary
+        let element = data.components * size;
+        let stride = element.div_ceil(4) * 4;
+
+        let mut bytes = Vec::with_capacity(order.len() * stride);
+        for &vertex in order {
+            for value in data.get(vertex as usize) {
+                gltf::encode_component(component_type, *value, normalized, &mut bytes);
+            }
+            bytes.resize(bytes.len() + stride - element, 0);
+        }
+        let view = add_view(json, bytes, ARRAY_BUFFER, (stride != element).then_some(stride))?;
+
+        let mut rewritten = accessor;
+        let object = rewritten.as_object_mut().ok_or_else(|| format!("accessors[{}] is not an object", accessor_index))?;
+        for key in ["byteOffset", "sparse", "min", "max"] {
+            object.remove(key);
+        }
+        object.insert("bufferView".to_string(), json!(view));
+        object.insert("count".to_string(), json!(order.len()));
+        if name == "POSITION" {
+            let (mut min, mut max) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+            for &vertex in order {
+                let p = data.get(vertex as usize);
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(p[axis]);
+                    max[axis] = max[axis].max(p[axis]);
+                }
+            }
+            object.insert("min".to_string(), json!(min));
+            object.insert("max".to_string(), json!(max));
+        }
+        json["accessors"][*accessor_index] = rewritten;
+    }
+
+    let (component_type, mut bytes): (u64, Vec<u8>) = if order.len() <= u16::MAX as usize {
+        (5123, indices.iter().flat_map(|i| (*i as u16).to_le_bytes()).collect())
+    } else {
+        (5125, indices.iter().flat_map(|i| i.to_le_bytes()).collect())
+    };
+    bytes.resize(bytes.len().div_ceil(4) * 4, 0);
+    let view = add_view(json, bytes, ELEMENT_ARRAY_BUFFER, None)?;
+    let accessor = json!({ "bufferView": view, "componentType": component_type, "count": indices.len(), "type": "SCALAR" });
+    let accessor_index = match primitive.indices_accessor {
+        Some(index) => {
+            json["accessors"][index] = accessor;
+            index
+        },
+        None => {
+            let list = json.as_object_mut().ok_or("glTF document must be a JSON object")?
+                .entry("accessors").or_insert_with(|| json!([]))
+                .as_array_mut().ok_or("accessors must be an array")?;
+            list.push(accessor);
+            list.len() - 1
+        },
+    };
+    json["meshes"][primitive.mesh]["primitives"][primitive.index]["indices"] = json!(accessor_index);
+    Ok(())
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    /// Positions and indices of a flat `n` by `n` grid of quads in the XY plane
+    fn grid(n: u32) -> (Vec<[f32; 3]>, Vec<u32>) {
+        let positions = (0..=n).flat_map(|y| (0..=n).map(move |x| [x as f32, y as f32, 0.0])).collect();
+        let indices = (0..n).flat_map(|y| (0..n).flat_map(move |x| {
+            let v = y * (n + 1) + x;
+            [v, v + 1, v + n + 2, v, v + n + 2, v + n + 1]
+        })).collect();
+        (positions, indices)
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn deduplicate_merges_identical_vertices() {
+        let positions = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0];
+        let mut attributes = [AccessorData { components: 3, values: positions.to_vec() }];
+        // Vertex 4 repeats vertex 0, which makes the last triangle degenerate
+        let indices = deduplicate(&mut attributes, &[0, 1, 2, 4, 2, 3, 0, 4, 1]);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(attributes[0].values, positions[..12]);
+    }
+
+    #[test]
+    fn simplify_keeps_borders_and_seams() {
+        let (mut positions, mut indices) = grid(4);
+        // Split the centre vertex into a seam for the triangles right of it
+        positions.push(positions[12]);
+        for t in indices.chunks_exact_mut(3) {
+            if t.contains(&12) && t.iter().any(|&v| v % 5 > 2) {
+                t.iter_mut().filter(|v| **v == 12).for_each(|v| *v = 25);
+            }
+        }
+
+        let simplified = simplify(&positions, &indices, 16);
+        assert_eq!(simplified.len() / 3, 16);
+        let border = (0..25).filter(|v| v % 5 == 0 || v % 5 == 4 || v / 5 == 0 || v / 5 == 4);
+        for vertex in border.chain([12, 25]) {
+            assert!(simplified.contains(&vertex), "vertex {} was collapsed", vertex);
+        }
+        // Locked vertices cannot collapse, so simplification stops short of impossible targets
+        assert!(simplify(&positions, &indices, 1).len() / 3 > 1);
+    }
+
+    #[test]
+    fn optimize_cache_emits_each_triangle_once() {
+        let (positions, indices) = grid(6);
+        let ordered = optimize_cache(&indices, positions.len());
+        assert_eq!(sorted_triangles(&ordered), sorted_triangles(&indices));
+    }
+
+    #[test]
+    fn optimize_fetch_renumbers_densely() {
+        let mut indices = [5, 3, 5, 7, 3, 9];
+        assert_eq!(optimize_fetch(&mut indices, 10), [5, 3, 7, 9]);
+        assert_eq!(indices, [0, 1, 0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn generates_readable_levels() {
+        // A cube with a vertex per face corner, which deduplication merges to 8
+        let corners: Vec<[f32; 3]> = (0..8).map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32]).collect();
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        let positions: Vec<u8> = faces.iter().flatten()
+            .flat_map(|&c: &usize| corners[c])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        let indices: Vec<u8> = (0..6u16).flat_map(|f| [0, 1, 2, 0, 2, 3].map(|i| f * 4 + i))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "bufferViews": [{}, {}],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "type": "VEC3", "count": 24, "min": [0, 0, 0], "max": [1, 1, 1] },
+                { "bufferView": 1, "componentType": 5123, "type": "SCALAR", "count": 36 },
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+        });
+        let path = std::env::temp_dir().join(format!("mesh_optimize_{}.glb", process::id()));
+        fs::write(&path, gltf::write_glb(json, &[positions, indices]).unwrap()).unwrap();
+        let levels = generate(&path, &[0.5]);
+        let _ = fs::remove_file(&path);
+        let levels = levels.unwrap();
+
+        assert_eq!(levels[0].triangle_count, 12);
+        for level in &levels {
+            let path = std::env::temp_dir().join(format!("mesh_optimize_{}_{}.glb", process::id(), level.ratio));
+            fs::write(&path, &level.glb).unwrap();
+            let document = gltf::read_glb(&path)
+                .and_then(|document| gltf::read_buffers(&path, &document).map(|_| document));
+            let _ = fs::remove_file(&path);
+            let document = document.unwrap();
+
+            gltf::validate(&document, None).unwrap();
+            assert_eq!(gltf::stats(&document).triangle_count, level.triangle_count);
+            let vertices = document.json["accessors"][0]["count"].as_u64().unwrap();
+            assert!(if level.ratio == 1.0 { vertices == 8 } else { vertices < 8 }, "{} vertices", vertices);
+        }
+        assert!(levels.len() == 2 && levels[1].triangle_count < 12);
+    }
+}
@@ -0,0 +1,62 @@
+//! Media processing pipelines
+//!
+//! This module contains the CPU-bound work performed on uploaded media.
+//! Everything here is synchronous and is expected to be run through
+//! `tokio::task::spawn_blocking` by the handlers:
+//! - `animation`: Animated GIF, APNG and WebP detection and re-encoding
+//! - `clip`: Cutting time ranges out of videos
+//! - `container`: Video container detection
+//! - `edits`: Non-destructive photo edit recipes
+//! - `faststart`: Moving the MP4 `moov` box in front of the media data
+//! - `ffmpeg`: Invocation of the local ffmpeg binary
+//! - `gltf`: glTF 2.0 and GLB parsing and validation
+//! - `hls`: HLS adaptive bitrate packaging of videos
+//! - `image_info`: Dimensions, orientation and colour information of photos
+//! - `matroska`: Matroska and WebM parsing
+//! - `mesh_optimize`: glTF mesh optimization and level-of-detail generation
+//! - `model_bundle`: Multi-file glTF bundle uploads
+//! - `model_format`: 3D model format detection and validation
+//! - `model_textures`: glTF texture downscaling and re-encoding
+//! - `model_thumbnail`: Software-rendered 3D model thumbnails and turntables
+//! - `mp4`: MP4 and QuickTime box parsing
+//! - `phash`: Perceptual hashes for near-duplicate detection
+//! - `photo`: Rendering of the public photo derivatives from private originals
+//! - `ply`: PLY header parsing and validation
+//! - `preview`: Video poster frames and thumbnail strips
+//! - `splat`: Gaussian splat PLY to `.splat` conversion and compaction
+//! - `transcode`: Web-safe H.264/AAC playback copies of videos
+//! - `video`: Container, codec and metadata probing of uploaded videos
+//! - `vtt`: SRT and WebVTT parsing, validation and writing
+//! - `watermark`: Text and logo watermark rendering
+
+pub mod animation;
+pub mod clip;
+pub mod container;
+pub mod edits;
+pub mod faststart;
+pub mod ffmpeg;
+pub mod gltf;
+pub mod hls;
+pub mod image_info;
+pub mod matroska;
+pub mod mesh_optimize;
+pub mod model_bundle;
+pub mod model_format;
+pub mod model_textures;
+pub mod model_thumbnail;
+pub mod mp4;
+pub mod phash;
+pub mod photo;
+pub mod ply;
+pub mod preview;
+pub mod splat;
+pub mod transcode;
+pub mod video;
+pub mod vtt;
+pub mod watermark;
+
+/// Error type shared by the processing pipelines
+pub type ProcessingError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Result type shared by the processing pipelines
+pub type ProcessingResult<T> = Result<T, ProcessingError>;
@@ -0,0 +1,341 @@
+//! Multi-file glTF model bundles
+//!
+//! A `.gltf` file usually references `.bin` buffers and texture images that
+//! sit next to it. Bundles are uploaded as a zip archive or as several
+//! files, staged in a folder, and checked so that every URI in the glTF
+//! resolves to a file inside the bundle.
+
+use std::{ffi::OsStr, fs::{self, File}, io::{self, Read}, path::{Path, PathBuf}};
+
+use super::{gltf, model_format::InvalidModel, ProcessingResult};
+
+/// Most files a bundle may contain
+pub const MAX_BUNDLE_FILES: usize = 1000;
+
+/// Largest total size of the files extracted from a zip bundle
+pub const MAX_BUNDLE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// A staged bundle
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// The `.gltf` or `.glb` file, relative to the staging folder
+    pub root: PathBuf,
+    /// The root and every file it references, relative to the staging folder
+    pub files: Vec<PathBuf>,
+}
+
+impl Bundle {
+    /// Whether the root is a binary GLB, which needs no other files
+    pub fn is_glb(&self) -> bool {
+        has_extension(&self.root, "glb")
+    }
+}
+
+/// Turns the file name of an uploaded file into a path inside the staging
+/// folder, keeping the subfolders of folder uploads
+///
+/// # Returns
+/// `None` for names that are empty or point outside the folder
+pub fn upload_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {},
+            ".." => return None,
+            part if part.contains(':') => return None,
+            part => path.push(part),
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Checks whether a file is a zip archive
+pub fn is_zip(path: &Path) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(4);
+    File::open(path)?.take(4).read_to_end(&mut magic)?;
+    Ok(magic == b"PK\x03\x04" || magic == b"PK\x05\x06")
+}
+
+/// Extracts a zip archive into a folder
+///
+/// Entries with absolute paths or `..` components are rejected, as are
+/// archives with more than `MAX_BUNDLE_FILES` files or `MAX_BUNDLE_SIZE`
+/// bytes of content.
+pub fn extract_zip(archive: &Path, folder: &Path) -> ProcessingResult<()> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)
+        .map_err(|e| InvalidModel(format!("invalid zip file: {}", e)))?;
+    if zip.len() > MAX_BUNDLE_FILES {
+        return Err(InvalidModel(format!("zip file has more than {} entries", MAX_BUNDLE_FILES)).into());
+    }
+
+    let mut total = 0u64;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| InvalidModel(format!("invalid zip file: {}", e)))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let relative = entry.enclosed_name().map(Path::to_path_buf)
+            .ok_or_else(|| InvalidModel(format!("zip entry '{}' points outside the archive", entry.name())))?;
+
+        // The declared sizes cannot be trusted, so the copy is capped too
+        let remaining = MAX_BUNDLE_SIZE - total;
+        let destination = folder.join(&relative);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&destination)?;
+        let written = io::copy(&mut (&mut entry).take(remaining + 1), &mut file)
+            .map_err(|e| InvalidModel(format!("cannot extract '{}' from the zip file: {}", relative.display(), e)))?;
+        total += written;
+        if total > MAX_BUNDLE_SIZE {
+            return Err(InvalidModel(format!("zip file expands to more than {} bytes", MAX_BUNDLE_SIZE)).into());
+        }
+    }
+    Ok(())
+}
+
+/// Finds the model in a staged bundle and checks that every file it
+/// references is present
+///
+/// The bundle must contain exactly one `.gltf` file or, failing that,
+/// exactly one `.glb` file. Files it does not reference are left out.
+///
+/// # Returns
+/// The bundle, or an `InvalidModel` error describing what is wrong with it
+pub fn resolve(folder: &Path) -> ProcessingResult<Bundle> {
+    let mut files = Vec::new();
+    list_files(folder, &PathBuf::new(), &mut files)?;
+
+    let candidates = |extension: &str| -> Vec<&PathBuf> {
+        files.iter().filter(|f| has_extension(f, extension)).collect()
+    };
+    let root = match (candidates("gltf").as_slice(), candidates("glb").as_slice()) {
+        ([root], _) | ([], [root]) => (*root).clone(),
+        ([], []) => return Err(InvalidModel("the upload has no .gltf or .glb file".to_string()).into()),
+        ([], roots) | (roots, _) => {
+            let extension = roots[0].extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+            return Err(InvalidModel(format!("the upload has {} .{} files; it must have exactly one", roots.len(), extension)).into());
+        },
+    };
+
+    let mut bundle = Bundle { root: root.clone(), files: vec![root.clone()] };
+    if bundle.is_glb() {
+        return Ok(bundle);
+    }
+
+    let root_folder = root.parent().map(Path::to_path_buf).unwrap_or_default();
+    let document = gltf::read_gltf(&folder.join(&root))
+        .and_then(|document| gltf::validate(&document, Some(&folder.join(&root_folder))).map(|_| document))
+        .map_err(|e| InvalidModel(format!("invalid GLTF file: {}", e)))?;
+    for file in gltf::external_files(&document) {
+        let file = root_folder.join(file);
+        if !bundle.files.contains(&file) {
+            bundle.files.push(file);
+        }
+    }
+    Ok(bundle)
+}
+
+/// Moves the files of a bundle from the staging folder into `destination`,
+/// keeping their relative paths
+pub fn store(folder: &Path, bundle: &Bundle, destination: &Path) -> io::Result<()> {
+    for file in &bundle.files {
+        let target = destination.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(folder.join(file), target)?;
+    }
+    Ok(())
+}
+
+/// Packs the `.gltf` root of a bundle and its files into a single GLB
+///
+/// # Returns
+/// The bytes of the GLB file
+pub fn pack(folder: &Path, bundle: &Bundle) -> ProcessingResult<Vec<u8>> {
+    let root_folder = folder.join(bundle.root.parent().unwrap_or(Path::new("")));
+    let document = gltf::read_gltf(&folder.join(&bundle.root)).map_err(InvalidModel)?;
+    Ok(gltf::pack(&document, &root_folder).map_err(|e| InvalidModel(format!("cannot pack the model as GLB: {}", e)))?)
+}
+
+/// Lists the files under a folder, relative to it, skipping hidden files
+/// and the `__MACOSX` metadata folders of zips made on macOS
+fn list_files(folder: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder.join(relative))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with('.') || name == OsStr::new("__MACOSX") {
+            continue;
+        }
+        let path = relative.join(&name);
+        if entry.file_type()?.is_dir() {
+            list_files(folder, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the extension of a path, ignoring case
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::{env, io::Write, process};
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Creates an empty folder under the temporary directory
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("model_bundle_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Stages a `.gltf` file in a subfolder with a buffer, a texture and files it does not reference
+    fn stage_gltf(folder: &Path) {
+        let gltf = json!({
+            "asset": {"version": "2.0"},
+            "buffers": [{"uri": "data/scene.bin", "byteLength": 4}],
+            "images": [{"uri": "wood%20floor.png"}],
+        });
+        fs::create_dir_all(folder.join("model/data")).unwrap();
+        fs::create_dir_all(folder.join("__MACOSX/model")).unwrap();
+        fs::write(folder.join("model/scene.gltf"), gltf.to_string()).unwrap();
+        fs::write(folder.join("model/data/scene.bin"), [1, 2, 3, 4]).unwrap();
+        image::RgbaImage::new(1, 1).save(folder.join("model/wood floor.png")).unwrap();
+        fs::write(folder.join("model/notes.txt"), "unused").unwrap();
+        fs::write(folder.join("__MACOSX/model/._scene.gltf"), "{}").unwrap();
+    }
+
+    fn error(result: ProcessingResult<impl std::fmt::Debug>) -> String {
+        result.unwrap_err().downcast_ref::<InvalidModel>().expect("an InvalidModel error").to_string()
+    }
+
+    #[test]
+    fn maps_upload_names_into_the_staging_folder() {
+        assert_eq!(upload_path("model/textures\\wood.png"), Some(PathBuf::from("model/textures/wood.png")));
+        assert_eq!(upload_path("./scene.gltf"), Some(PathBuf::from("scene.gltf")));
+        for name in ["", "/", "../scene.gltf", "C:\\scene.gltf"] {
+            assert_eq!(upload_path(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn extracts_zips_within_limits() {
+        let folder = temp_folder("extract");
+        let archive = folder.join("upload.zip");
+        write_zip(&archive, &[("model/scene.gltf", b"{}"), ("model/data/scene.bin", &[1, 2, 3, 4])]);
+        assert!(is_zip(&archive).unwrap());
+        extract_zip(&archive, &folder.join("out")).unwrap();
+        assert_eq!(fs::read(folder.join("out/model/data/scene.bin")).unwrap(), [1, 2, 3, 4]);
+
+        write_zip(&archive, &[("model/scene.gltf", b"{}"), ("../escape.bin", b"x")]);
+        assert_eq!(error(extract_zip(&archive, &folder.join("traversal"))), "zip entry '../escape.bin' points outside the archive");
+        assert!(!folder.join("escape.bin").exists());
+
+        let names: Vec<String> = (0..=MAX_BUNDLE_FILES).map(|i| format!("{}.bin", i)).collect();
+        write_zip(&archive, &names.iter().map(|name| (name.as_str(), &b""[..])).collect::<Vec<_>>());
+        assert_eq!(error(extract_zip(&archive, &folder.join("many"))), "zip file has more than 1000 entries");
+        assert!(!folder.join("many").exists());
+
+        fs::write(&archive, "not a zip").unwrap();
+        assert!(!is_zip(&archive).unwrap());
+        assert!(error(extract_zip(&archive, &folder.join("invalid"))).starts_with("invalid zip file"));
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn resolves_the_gltf_root_and_its_files() {
+        let folder = temp_folder("resolve");
+        assert_eq!(error(resolve(&folder)), "the upload has no .gltf or .glb file");
+
+        stage_gltf(&folder);
+        fs::write(folder.join("preview.glb"), "glb").unwrap();
+        let bundle = resolve(&folder).unwrap();
+        assert!(!bundle.is_glb());
+        assert_eq!(bundle.root, PathBuf::from("model/scene.gltf"));
+        assert_eq!(bundle.files, ["model/scene.gltf", "model/data/scene.bin", "model/wood floor.png"].map(PathBuf::from));
+
+        fs::write(folder.join("copy.GLTF"), "{}").unwrap();
+        assert_eq!(error(resolve(&folder)), "the upload has 2 .gltf files; it must have exactly one");
+        fs::remove_file(folder.join("copy.GLTF")).unwrap();
+
+        fs::remove_file(folder.join("model/data/scene.bin")).unwrap();
+        assert!(error(resolve(&folder)).contains("'data/scene.bin', which is missing from the upload"));
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn resolves_a_lone_glb() {
+        let folder = temp_folder("resolve_glb");
+        fs::write(folder.join("model.glb"), "glb").unwrap();
+        fs::write(folder.join("readme.txt"), "unused").unwrap();
+        let bundle = resolve(&folder).unwrap();
+        assert!(bundle.is_glb());
+        assert_eq!(bundle.files, [PathBuf::from("model.glb")]);
+
+        fs::write(folder.join("other.glb"), "glb").unwrap();
+        assert_eq!(error(resolve(&folder)), "the upload has 2 .glb files; it must have exactly one");
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn stores_only_the_bundle_files() {
+        let folder = temp_folder("store");
+        stage_gltf(&folder);
+        let bundle = resolve(&folder).unwrap();
+        let destination = folder.join("stored");
+        store(&folder, &bundle, &destination).unwrap();
+
+        assert!(destination.join("model/scene.gltf").is_file());
+        assert_eq!(fs::read(destination.join("model/data/scene.bin")).unwrap(), [1, 2, 3, 4]);
+        assert!(destination.join("model/wood floor.png").is_file());
+        assert!(!destination.join("model/notes.txt").exists());
+        assert!(!folder.join("model/scene.gltf").exists());
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn packs_buffers_and_images_into_a_glb() {
+        let folder = temp_folder("pack");
+        stage_gltf(&folder);
+        let bundle = resolve(&folder).unwrap();
+        let glb = folder.join("packed.glb");
+        fs::write(&glb, pack(&folder, &bundle).unwrap()).unwrap();
+
+        let document = gltf::read_glb(&glb).unwrap();
+        gltf::validate(&document, None).unwrap();
+        assert_eq!(document.json["buffers"], json!([{"byteLength": document.bin_len.unwrap()}]));
+        let image = &document.json["images"][0];
+        assert_eq!(image["mimeType"], "image/png");
+        assert_eq!(image.get("uri"), None::<&Value>);
+        // The BIN chunk comes last
+        let data = fs::read(&glb).unwrap();
+        let bin = &data[data.len() - document.bin_len.unwrap() as usize..];
+        let view = &document.json["bufferViews"][image["bufferView"].as_u64().unwrap() as usize];
+        assert!(bin[view["byteOffset"].as_u64().unwrap_or(0) as usize..].starts_with(b"\x89PNG"));
+        assert_eq!(&bin[..4], [1, 2, 3, 4]);
+
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
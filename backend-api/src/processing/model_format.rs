@@ -0,0 +1,368 @@
+//! 3D model format detection and validation
+//!
+//! Identifies the format of an uploaded model from its contents, falling
+//! back to the file extension only to tell apart formats without a
+//! signature, and checks its structure so malformed files are rejected at
+//! upload rather than failing in the viewer.
+
+use serde::{Serialize, Deserialize};
+use std::{error::Error, fmt, fs::File, io::{BufRead, BufReader, Read}, path::Path};
+
+use super::{gltf::{self, GltfStats}, ply, ProcessingResult};
+
+/// Size of one record of a `.splat` file: position, scale, colour, rotation
+pub const SPLAT_RECORD_SIZE: u64 = 32;
+
+/// Supported 3D model formats
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelFormat {
+    /// Binary glTF
+    Glb,
+    /// glTF JSON, with its buffers and images embedded or next to it in a bundle
+    Gltf,
+    Ply,
+    Obj,
+    /// Gaussian splats in the 32-byte record layout
+    Splat,
+}
+
+impl ModelFormat {
+    /// Conventional file extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ModelFormat::Glb => "glb",
+            ModelFormat::Gltf => "gltf",
+            ModelFormat::Ply => "ply",
+            ModelFormat::Obj => "obj",
+            ModelFormat::Splat => "splat",
+        }
+    }
+}
+
+/// A model file that does not match the structure of its format
+#[derive(Debug)]
+pub struct InvalidModel(pub String);
+
+impl fmt::Display for InvalidModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for InvalidModel {}
+
+/// Detects and validates the format of a model file
+///
+/// # Arguments
+/// * `path` - Model file
+/// * `extension` - Extension of the uploaded file name, used to tell OBJ
+///   and `.splat` files apart from other files without a signature
+///
+/// # Returns
+/// The format, or an `InvalidModel` error describing what is wrong with the file
+pub fn detect(path: &Path, extension: Option<&str>) -> ProcessingResult<ModelFormat> {
+    let mut header = Vec::with_capacity(16);
+    File::open(path)?.take(16).read_to_end(&mut header)?;
+    let extension = extension.map(str::to_ascii_lowercase);
+
+    let format = if header.starts_with(gltf::GLB_MAGIC) {
+        ModelFormat::Glb
+    } else if header.starts_with(b"ply") {
+        ModelFormat::Ply
+    } else if header.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        ModelFormat::Gltf
+    } else {
+        match extension.as_deref() {
+            Some("obj") => ModelFormat::Obj,
+            Some("splat") => ModelFormat::Splat,
+            Some("glb" | "gltf" | "ply") => {
+                return Err(InvalidModel(format!("file is not a valid .{} file", extension.unwrap_or_default())).into());
+            },
+            _ => {
+                return Err(InvalidModel(
+                    "unrecognised model format; upload a GLB, glTF, PLY, OBJ or .splat file".to_string(),
+                ).into());
+            },
+        }
+    };
+
+    let result = match format {
+        ModelFormat::Glb => gltf::read_glb(path).and_then(|document| gltf::validate(&document, None)),
+        ModelFormat::Gltf => gltf::read_gltf(path).and_then(|document| gltf::validate(&document, None)),
+        ModelFormat::Ply => ply::validate(path).map(|_| ()),
+        ModelFormat::Obj => validate_obj(path),
+        ModelFormat::Splat => validate_splat(path),
+    };
+    result.map_err(|e| InvalidModel(format!("invalid {} file: {}", format.extension().to_uppercase(), e)))?;
+    Ok(format)
+}
+
+/// Summarises the contents of a glTF or GLB model
+///
+/// # Returns
+/// `None` for formats without a scene description
+pub fn gltf_stats(path: &Path, format: ModelFormat) -> ProcessingResult<Option<GltfStats>> {
+    let document = match format {
+        ModelFormat::Glb => gltf::read_glb(path)?,
+        ModelFormat::Gltf => gltf::read_gltf(path)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(gltf::stats(&document)))
+}
+
+/// Checks the syntax of a Wavefront OBJ file and the face indices in it
+fn validate_obj(path: &Path) -> Result<(), String> {
+    let reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let (mut positions, mut texcoords, mut normals) = (0usize, 0usize, 0usize);
+
+    for (number, line) in reader.split(b'\n').enumerate() {
+        let number = number + 1;
+        let line = line.map_err(|e| e.to_string())?;
+        let line = String::from_utf8_lossy(&line);
+        let mut words = line.split('#').next().unwrap_or_default().split_whitespace();
+        let Some(keyword) = words.next() else { continue };
+        let args: Vec<&str> = words.collect();
+
+        let numbers = |min: usize, max: usize| -> Result<(), String> {
+            if args.len() < min || args.len() > max || args.iter().any(|a| a.parse::<f64>().map_or(true, |v| !v.is_finite())) {
+                return Err(format!("line {}: '{}' needs {} to {} numbers", number, keyword, min, max));
+            }
+            Ok(())
+        };
+        match keyword {
+            "v" => {
+                // Some exporters append an RGB vertex colour
+                numbers(3, 7)?;
+                positions += 1;
+            },
+            "vt" => {
+                numbers(1, 3)?;
+                texcoords += 1;
+            },
+            "vn" => {
+                numbers(3, 3)?;
+                normals += 1;
+            },
+            "vp" => numbers(1, 3)?,
+            "f" | "l" | "p" => {
+                let min = match keyword { "f" => 3, "l" => 2, _ => 1 };
+                if args.len() < min {
+                    return Err(format!("line {}: '{}' needs at least {} vertices", number, keyword, min));
+                }
+                for vertex in &args {
+                    let counts = [positions, texcoords, normals];
+                    for (slot, reference) in vertex.split('/').enumerate() {
+                        if slot >= 3 || (slot == 0 && reference.is_empty()) {
+                            return Err(format!("line {}: invalid vertex reference '{}'", number, vertex));
+                        }
+                        if !reference.is_empty() {
+                            check_obj_index(reference, counts[slot])
+                                .map_err(|e| format!("line {}: {}", number, e))?;
+                        }
+                    }
+                }
+            },
+            "o" | "g" | "s" | "mtllib" | "usemtl" | "mg" | "lod" | "bevel" | "c_interp" | "d_interp"
+            | "shadow_obj" | "trace_obj" | "cstype" | "deg" | "bmat" | "step" | "curv" | "curv2"
+            | "surf" | "parm" | "trim" | "hole" | "scrv" | "sp" | "end" | "con" | "ctech" | "stech" => {},
+            _ => return Err(format!("line {}: unknown statement '{}'", number, keyword)),
+        }
+    }
+
+    if positions == 0 {
+        return Err("file has no vertices".to_string());
+    }
+    Ok(())
+}
+
+/// Checks a 1-based or negative relative OBJ index against the elements defined so far
+fn check_obj_index(reference: &str, count: usize) -> Result<(), String> {
+    let index: i64 = reference.parse().map_err(|_| format!("invalid index '{}'", reference))?;
+    let valid = match index {
+        0 => false,
+        i if i > 0 => i as u64 <= count as u64,
+        i => i.unsigned_abs() <= count as u64,
+    };
+    if !valid {
+        return Err(format!("index {} refers to an element that is not defined yet ({} defined)", index, count));
+    }
+    Ok(())
+}
+
+/// Checks that a `.splat` file consists of whole 32-byte records with
+/// finite positions and scales
+fn validate_splat(path: &Path) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    if len == 0 {
+        return Err("file is empty".to_string());
+    }
+    if len % SPLAT_RECORD_SIZE != 0 {
+        return Err(format!(
+            "file is {} bytes long, which is not a whole number of {}-byte splat records",
+            len, SPLAT_RECORD_SIZE
+        ));
+    }
+
+    let mut reader = BufReader::new(file);
+    let mut record = [0u8; SPLAT_RECORD_SIZE as usize];
+    for index in 0..len / SPLAT_RECORD_SIZE {
+        reader.read_exact(&mut record).map_err(|e| e.to_string())?;
+        let floats = record[..24].chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        if floats.into_iter().any(|v| !v.is_finite()) {
+            return Err(format!("splat {} has a non-finite position or scale", index));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf, process};
+
+    /// Writes a file to the temporary folder for the duration of a test
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path = env::temp_dir().join(format!("model_format_{}_{}", process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Builds a GLB file from its chunks, padding each to 4 bytes unless `pad` is off
+    fn glb(chunks: &[(&[u8; 4], &[u8])], pad: bool) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, data) in chunks {
+            let mut data = data.to_vec();
+            while pad && data.len() % 4 != 0 {
+                data.push(if *kind == b"JSON" { b' ' } else { 0 });
+            }
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(*kind);
+            body.extend(data);
+        }
+        let mut file = b"glTF".to_vec();
+        file.extend(2u32.to_le_bytes());
+        file.extend((12 + body.len() as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    const JSON: &[u8] = br#"{"asset":{"version":"2.0"}}"#;
+
+    fn detect_bytes(name: &str, contents: &[u8], extension: Option<&str>) -> Result<ModelFormat, String> {
+        let file = TempFile::new(name, contents);
+        detect(&file.0, extension).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn accepts_well_formed_glb() {
+        assert_eq!(detect_bytes("plain.glb", &glb(&[(b"JSON", JSON)], true), None), Ok(ModelFormat::Glb));
+        let with_bin = glb(&[(b"JSON", JSON), (b"BIN\0", &[0; 8]), (b"XTRA", &[1; 4])], true);
+        assert_eq!(detect_bytes("bin.glb", &with_bin, Some("bin")), Ok(ModelFormat::Glb));
+    }
+
+    #[test]
+    fn rejects_malformed_glb_chunks() {
+        let cases: Vec<(&str, Vec<u8>, &str)> = vec![
+            ("unaligned", glb(&[(b"JSON", JSON)], false), "not 4-byte aligned"),
+            ("bin_first", glb(&[(b"BIN\0", &[0; 4]), (b"JSON", JSON)], true), "first chunk must be the JSON chunk"),
+            ("two_json", glb(&[(b"JSON", JSON), (b"JSON", JSON)], true), "repeats a JSON or BIN chunk"),
+            ("no_json", glb(&[], true), "file has no JSON chunk"),
+            ("bad_json", glb(&[(b"JSON", b"{nope")], true), "JSON chunk is not valid JSON"),
+        ];
+        for (name, contents, expected) in cases {
+            let error = detect_bytes(name, &contents, None).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn rejects_glb_with_wrong_lengths() {
+        let mut file = glb(&[(b"JSON", JSON)], true);
+        file.extend([0; 4]);
+        let error = detect_bytes("long.glb", &file, None).unwrap_err();
+        assert!(error.contains("GLB header declares"), "{}", error);
+
+        let mut file = glb(&[(b"JSON", JSON)], true);
+        file[12] += 4;
+        let error = detect_bytes("chunk.glb", &file, None).unwrap_err();
+        assert!(error.contains("runs past the end of the file"), "{}", error);
+
+        let mut file = glb(&[(b"JSON", JSON)], true);
+        file[4] = 1;
+        let error = detect_bytes("v1.glb", &file, None).unwrap_err();
+        assert!(error.contains("GLB version 1 is not supported"), "{}", error);
+    }
+
+    #[test]
+    fn detects_formats_from_contents_before_extensions() {
+        assert_eq!(detect_bytes("json.obj", JSON, Some("obj")), Ok(ModelFormat::Gltf));
+        assert_eq!(detect_bytes("cube.obj", b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -1\n", Some("OBJ")), Ok(ModelFormat::Obj));
+        assert!(detect_bytes("fake.glb", b"not a model", Some("glb")).unwrap_err().contains("not a valid .glb file"));
+        assert!(detect_bytes("fake.txt", b"not a model", Some("txt")).unwrap_err().contains("unrecognised model format"));
+    }
+
+    #[test]
+    fn validates_obj_indices() {
+        assert!(detect_bytes("ahead.obj", b"v 0 0 0\nf 1 2 3\n", Some("obj")).unwrap_err().contains("index 2"));
+        assert!(detect_bytes("zero.obj", b"v 0 0 0\nf 0 1 1\n", Some("obj")).unwrap_err().contains("index 0"));
+        assert!(detect_bytes("nan.obj", b"v 0 nan 0\n", Some("obj")).unwrap_err().contains("line 1"));
+    }
+
+    #[test]
+    fn bounds_accessors_by_their_buffer_views() {
+        let gltf = |stride: &str, view_len: u64, accessor: &str| format!(
+            r#"{{"asset":{{"version":"2.0"}},"buffers":[{{"uri":"data:application/octet-stream;base64,","byteLength":64}}],
+            "bufferViews":[{{"buffer":0,"byteLength":{}{}}}],"accessors":[{}]}}"#,
+            view_len, stride, accessor
+        );
+        let vec3 = r#"{"bufferView":0,"componentType":5126,"type":"VEC3","count":2}"#;
+        assert_eq!(detect_bytes("fits.gltf", gltf("", 24, vec3).as_bytes(), None), Ok(ModelFormat::Gltf));
+        assert_eq!(detect_bytes("stride.gltf", gltf(r#","byteStride":16"#, 28, vec3).as_bytes(), None), Ok(ModelFormat::Gltf));
+
+        let cases = [
+            ("short", gltf("", 23, vec3), "runs past the end of bufferViews[0]"),
+            ("strided", gltf(r#","byteStride":16"#, 27, vec3), "runs past the end of bufferViews[0]"),
+            ("offset", gltf("", 24, r#"{"bufferView":0,"byteOffset":4,"componentType":5126,"type":"VEC3","count":2}"#), "runs past"),
+            ("overflow", gltf("", 24, r#"{"bufferView":0,"componentType":5126,"type":"MAT4","count":18446744073709551615}"#), "runs past"),
+            ("unbacked", gltf("", 24, r#"{"componentType":5126,"type":"MAT4","count":4294967296}"#), "has no bufferView"),
+            ("sparse", gltf("", 24, r#"{"componentType":5126,"type":"VEC3","count":2,"sparse":{"count":3}}"#), "sparse.count"),
+        ];
+        for (name, json, expected) in cases {
+            let error = detect_bytes(&format!("{}.gltf", name), json.as_bytes(), None).unwrap_err();
+            assert!(error.contains(expected), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn rejects_ply_counts_that_overflow() {
+        let header = |format: &str| format!(
+            "ply\nformat {} 1.0\nelement vertex 18446744073709551615\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty uchar i\nend_header\n",
+            format
+        );
+        for format in ["ascii", "binary_little_endian"] {
+            let error = detect_bytes(&format!("{}.ply", format), header(format).as_bytes(), Some("ply")).unwrap_err();
+            assert!(error.contains("more data than a file can hold"), "{}: {}", format, error);
+        }
+    }
+
+    #[test]
+    fn validates_splat_records() {
+        assert_eq!(detect_bytes("one.splat", &[0; 32], Some("splat")), Ok(ModelFormat::Splat));
+        assert!(detect_bytes("short.splat", &[0; 31], Some("splat")).unwrap_err().contains("whole number"));
+        let mut record = [0; 32];
+        record[..4].copy_from_slice(&f32::INFINITY.to_le_bytes());
+        assert!(detect_bytes("inf.splat", &record, Some("splat")).unwrap_err().contains("non-finite"));
+    }
+}
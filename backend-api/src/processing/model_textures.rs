@@ -0,0 +1,301 @@
+//! glTF texture downscaling and re-encoding
+//!
+//! Uploaded GLB files often embed 4K PNG textures that make up most of
+//! their size. This pass shrinks embedded textures to a maximum size and
+//! re-encodes them: opaque colour textures as JPEG and textures with
+//! transparency and normal maps losslessly as PNG, or everything as
+//! lossless WebP through the `EXT_texture_webp` extension. KTX2 is not
+//! offered, as there is no pure-Rust Basis Universal encoder.
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::{self, PngEncoder}, webp::WebPEncoder},
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageEncoder, ImageFormat,
+};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use std::{collections::{HashMap, HashSet}, env, fs, path::Path};
+
+use super::{gltf, ProcessingResult};
+
+/// Longest texture side when `MODEL_TEXTURE_MAX_SIZE` is not set
+const DEFAULT_MAX_TEXTURE_SIZE: u32 = 2048;
+
+/// JPEG quality of re-encoded colour textures
+const JPEG_QUALITY: u8 = 85;
+
+/// Extension that lets textures use WebP images
+const WEBP_EXTENSION: &str = "EXT_texture_webp";
+
+/// Encoding of re-encoded textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// JPEG for opaque colour textures, PNG for the others; readable by every viewer
+    Jpeg,
+    /// Lossless WebP, which needs viewers supporting `EXT_texture_webp`
+    Webp,
+}
+
+/// Result of compressing the textures of a model
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TextureStats {
+    /// Images that were downscaled or re-encoded
+    pub image_count: u64,
+    /// Size of the uploaded file in bytes
+    pub original_size: u64,
+    /// Size of the stored file in bytes
+    pub size: u64,
+}
+
+/// Longest side, in pixels, textures are downscaled to
+pub fn max_texture_size() -> u32 {
+    env::var("MODEL_TEXTURE_MAX_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_MAX_TEXTURE_SIZE)
+}
+
+/// Encoding of re-encoded textures, set with `MODEL_TEXTURE_FORMAT` to
+/// `jpeg` (the default) or `webp`
+pub fn texture_format() -> TextureFormat {
+    match env::var("MODEL_TEXTURE_FORMAT").as_deref() {
+        Ok("webp") => TextureFormat::Webp,
+        _ => TextureFormat::Jpeg,
+    }
+}
+
+/// Downscales and re-encodes the textures embedded in a GLB file
+///
+/// PNG and JPEG images larger than `max_size` are downscaled; smaller PNG
+/// images are only replaced when re-encoding makes them smaller, and smaller
+/// JPEG images are kept to avoid compressing them twice. Normal maps are never
+/// encoded lossily, as JPEG artifacts show up as bumps in the lighting.
+/// Images that cannot be decoded, and WebP or KTX2 images, are left as they are.
+///
+/// # Arguments
+/// * `source` - Validated GLB file
+/// * `destination` - Path of the rewritten GLB file
+/// * `max_size` - Longest side of the textures, in pixels
+/// * `format` - Encoding of re-encoded textures
+///
+/// # Returns
+/// The image count and sizes, or `None` when no texture had to change and
+/// nothing was written
+pub fn compress(source: &Path, destination: &Path, max_size: u32, format: TextureFormat) -> ProcessingResult<Option<TextureStats>> {
+    let document = gltf::read_glb(source)?;
+    let buffers = gltf::read_buffers(source, &document)?;
+    let mut views = gltf::view_bytes(&document.json, &buffers)?;
+    let mut json = document.json;
+
+    let image_view = |json: &Value, i: usize| json["images"][i].get("bufferView").and_then(Value::as_u64).map(|v| v as usize);
+    let image_count = json.get("images").and_then(Value::as_array).map_or(0, Vec::len);
+    // Images may share a buffer view; it is encoded once, losslessly if any of them is a normal map
+    let lossless_views: HashSet<usize> = normal_map_images(&json).into_iter().filter_map(|i| image_view(&json, i)).collect();
+    let mut rewritten: HashMap<usize, &'static str> = HashMap::new();
+    let mut webp_images = HashSet::new();
+    let mut changed = 0;
+    for i in 0..image_count {
+        let Some(view) = image_view(&json, i) else { continue };
+        if let Some(mime_type) = rewritten.get(&view).copied() {
+            json["images"][i]["mimeType"] = json!(mime_type);
+            if mime_type == "image/webp" {
+                webp_images.insert(i);
+            }
+            continue;
+        }
+        let Some(data) = views.get(view) else { continue };
+        let input_format = match gltf::image_mime_type(data) {
+            Some("image/png") => ImageFormat::Png,
+            Some("image/jpeg") => ImageFormat::Jpeg,
+            _ => continue,
+        };
+        let Ok(mut image) = image::load_from_memory_with_format(data, input_format) else { continue };
+
+        let downscaled = image.width() > max_size || image.height() > max_size;
+        if !downscaled && input_format == ImageFormat::Jpeg {
+            continue;
+        }
+        if downscaled {
+            image = image.resize(max_size, max_size, FilterType::Lanczos3);
+        }
+        let (encoded, mime_type) = encode(&image, format, lossless_views.contains(&view))?;
+        if !downscaled && encoded.len() >= data.len() {
+            continue;
+        }
+
+        views[view] = encoded;
+        rewritten.insert(view, mime_type);
+        json["images"][i]["mimeType"] = json!(mime_type);
+        if mime_type == "image/webp" {
+            webp_images.insert(i);
+        }
+        changed += 1;
+    }
+    if changed == 0 {
+        return Ok(None);
+    }
+
+    if !webp_images.is_empty() {
+        use_webp_extension(&mut json, &webp_images);
+    }
+    let glb = gltf::write_glb(json, &views)?;
+    fs::write(destination, &glb)?;
+
+    Ok(Some(TextureStats {
+        image_count: changed,
+        original_size: fs::metadata(source)?.len(),
+        size: glb.len() as u64,
+    }))
+}
+
+/// Encodes a texture, losslessly when it has transparency or `lossless` is set
+///
+/// # Returns
+/// The encoded image and its MIME type
+fn encode(image: &DynamicImage, format: TextureFormat, lossless: bool) -> ProcessingResult<(Vec<u8>, &'static str)> {
+    let transparent = image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255);
+    let (pixels, color_type) = match transparent {
+        true => (image.to_rgba8().into_raw(), ColorType::Rgba8),
+        false => (image.to_rgb8().into_raw(), ColorType::Rgb8),
+    };
+    let (width, height) = (image.width(), image.height());
+
+    let mut bytes = Vec::new();
+    let mime_type = match format {
+        TextureFormat::Webp => {
+            WebPEncoder::new_lossless(&mut bytes).write_image(&pixels, width, height, color_type)?;
+            "image/webp"
+        },
+        TextureFormat::Jpeg if !transparent && !lossless => {
+            JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY).write_image(&pixels, width, height, color_type)?;
+            "image/jpeg"
+        },
+        TextureFormat::Jpeg => {
+            PngEncoder::new_with_quality(&mut bytes, png::CompressionType::Best, png::FilterType::Adaptive)
+                .write_image(&pixels, width, height, color_type)?;
+            "image/png"
+        },
+    };
+    Ok((bytes, mime_type))
+}
+
+/// Lists the images used as normal maps
+fn normal_map_images(json: &Value) -> HashSet<usize> {
+    let textures = json.get("textures").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    json.get("materials").and_then(Value::as_array).into_iter().flatten()
+        .filter_map(|material| material.pointer("/normalTexture/index").and_then(Value::as_u64))
+        .filter_map(|texture| textures.get(texture as usize)?.get("source")?.as_u64())
+        .map(|image| image as usize)
+        .collect()
+}
+
+/// Points the textures of WebP images at them through `EXT_texture_webp`,
+/// which core glTF viewers cannot skip, so the extension is required
+fn use_webp_extension(json: &mut Value, webp_images: &HashSet<usize>) {
+    if let Some(textures) = json.get_mut("textures").and_then(Value::as_array_mut) {
+        for texture in textures.iter_mut().filter_map(Value::as_object_mut) {
+            let Some(source) = texture.get("source").and_then(Value::as_u64) else { continue };
+            if !webp_images.contains(&(source as usize)) {
+                continue;
+            }
+            texture.remove("source");
+            if let Some(extensions) = texture.entry("extensions").or_insert_with(|| json!({})).as_object_mut() {
+                extensions.insert(WEBP_EXTENSION.to_string(), json!({ "source": source }));
+            }
+        }
+    }
+
+    for list in ["extensionsUsed", "extensionsRequired"] {
+        let Some(object) = json.as_object_mut() else { return };
+        let names = object.entry(list).or_insert_with(|| json!([]));
+        if let Some(names) = names.as_array_mut() {
+            if !names.iter().any(|name| name == WEBP_EXTENSION) {
+                names.push(json!(WEBP_EXTENSION));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::process;
+
+    /// A PNG of one colour
+    fn png(width: u32, height: u32, pixel: [u8; 4]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba(pixel)))
+            .write_to(&mut std::io::Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    /// Compresses a GLB whose images are `views`, with `images[i]` in
+    /// `views[images[i]]` and the first image used as a normal map when `normal` is set
+    fn compress_images(name: &str, views: Vec<Vec<u8>>, images: &[usize], normal: bool, format: TextureFormat) -> (Value, Vec<Vec<u8>>) {
+        let material = match normal {
+            true => json!({ "normalTexture": { "index": 0 } }),
+            false => json!({ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }),
+        };
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "bufferViews": views.iter().map(|_| json!({})).collect::<Vec<_>>(),
+            "images": images.iter().map(|&view| json!({ "bufferView": view, "mimeType": "image/png" })).collect::<Vec<_>>(),
+            "textures": (0..images.len()).map(|i| json!({ "source": i })).collect::<Vec<_>>(),
+            "materials": [material],
+        });
+        let path = |suffix: &str| std::env::temp_dir().join(format!("model_textures_{}_{}_{}.glb", process::id(), name, suffix));
+        fs::write(path("in"), gltf::write_glb(json, &views).unwrap()).unwrap();
+        let result = compress(&path("in"), &path("out"), 16, format).map(|stats| {
+            assert!(stats.is_some());
+            let document = gltf::read_glb(&path("out")).unwrap();
+            let buffers = gltf::read_buffers(&path("out"), &document).unwrap();
+            let views = gltf::view_bytes(&document.json, &buffers).unwrap();
+            (document.json, views)
+        });
+        let _ = fs::remove_file(path("in"));
+        let _ = fs::remove_file(path("out"));
+        result.unwrap()
+    }
+
+    /// The MIME type and size of each image after compression
+    fn images(json: &Value, views: &[Vec<u8>]) -> Vec<(String, (u32, u32))> {
+        json["images"].as_array().unwrap().iter().map(|image| {
+            let data = &views[image["bufferView"].as_u64().unwrap() as usize];
+            let decoded = image::load_from_memory(data).unwrap();
+            (image["mimeType"].as_str().unwrap().to_string(), (decoded.width(), decoded.height()))
+        }).collect()
+    }
+
+    #[test]
+    fn downscales_oversized_textures() {
+        let (json, views) = compress_images("large", vec![png(64, 32, [200, 100, 50, 255])], &[0], false, TextureFormat::Jpeg);
+        assert_eq!(images(&json, &views), [("image/jpeg".to_string(), (16, 8))]);
+    }
+
+    #[test]
+    fn keeps_transparency_and_normal_maps_lossless() {
+        let (json, views) = compress_images("alpha", vec![png(32, 32, [200, 100, 50, 128])], &[0], false, TextureFormat::Jpeg);
+        assert_eq!(images(&json, &views)[0].0, "image/png");
+        let (json, views) = compress_images("normal", vec![png(32, 32, [128, 128, 255, 255])], &[0], true, TextureFormat::Jpeg);
+        assert_eq!(images(&json, &views)[0].0, "image/png");
+    }
+
+    #[test]
+    fn requires_the_webp_extension() {
+        let (json, views) = compress_images("webp", vec![png(32, 32, [200, 100, 50, 255])], &[0], false, TextureFormat::Webp);
+        assert_eq!(images(&json, &views), [("image/webp".to_string(), (16, 16))]);
+        assert_eq!(json["textures"][0], json!({ "extensions": { WEBP_EXTENSION: { "source": 0 } } }));
+        assert_eq!(json["extensionsUsed"], json!([WEBP_EXTENSION]));
+        assert_eq!(json["extensionsRequired"], json!([WEBP_EXTENSION]));
+    }
+
+    #[test]
+    fn encodes_shared_views_once() {
+        let (json, views) = compress_images("shared", vec![png(32, 32, [128, 128, 255, 255])], &[0, 0], true, TextureFormat::Jpeg);
+        assert_eq!(images(&json, &views), vec![("image/png".to_string(), (16, 16)); 2]);
+        assert_eq!(views.len(), 1);
+    }
+}
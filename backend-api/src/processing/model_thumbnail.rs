@@ -0,0 +1,402 @@
+//! Software-rendered 3D model thumbnails
+//!
+//! Renders glTF and GLB meshes with a small CPU rasterizer, and `.splat`
+//! files as blended Gaussian points, so the models grid can show a preview
+//! without loading the model itself. The thumbnail is a still PNG with a
+//! transparent background; optionally a turntable GIF is rendered too.
+
+use image::{codecs::gif::{GifEncoder, Repeat}, Delay, Frame, Rgba, RgbaImage};
+use serde_json::Value;
+use std::{env, f64::consts::PI, fs::{self, File}, io::BufWriter, path::Path};
+
+use super::{gltf, model_format::{ModelFormat, SPLAT_RECORD_SIZE}, ProcessingResult};
+
+/// Width and height of the still thumbnail
+pub const THUMBNAIL_SIZE: u32 = 512;
+
+/// Width and height of the turntable frames
+const TURNTABLE_SIZE: u32 = 256;
+
+/// Turntable frames when `MODEL_TURNTABLE_FRAMES` is not set; 0 disables it
+const DEFAULT_TURNTABLE_FRAMES: u32 = 0;
+
+/// Upper bound on turntable frames
+const MAX_TURNTABLE_FRAMES: u32 = 120;
+
+/// Milliseconds per turntable frame
+const TURNTABLE_FRAME_MS: u32 = 80;
+
+/// GIF quantization speed, 1 (best) to 30 (fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Renders are supersampled by this factor in each direction for antialiasing
+const SUPERSAMPLE: u32 = 2;
+
+/// Vertical field of view of the camera
+const FIELD_OF_VIEW: f64 = 35.0 * PI / 180.0;
+
+/// Space left around the framed sphere, as a factor of its radius
+const FRAMING_MARGIN: f64 = 1.1;
+
+/// Angle of the camera above the horizon
+const ELEVATION: f64 = 20.0 * PI / 180.0;
+
+/// Angle around the model of the still thumbnail, a three-quarter view
+const THUMBNAIL_AZIMUTH: f64 = 30.0 * PI / 180.0;
+
+/// Most splats drawn; `.splat` files are sorted with the most visible first
+const MAX_PREVIEW_SPLATS: usize = 200_000;
+
+/// Background of turntable frames, as GIF has no partial transparency
+const TURNTABLE_BACKGROUND: [u8; 3] = [245, 245, 245];
+
+/// Number of turntable frames, or `None` when turntables are disabled
+///
+/// Configured with `MODEL_TURNTABLE_FRAMES`.
+pub fn turntable_frames() -> Option<u32> {
+    let frames = env::var("MODEL_TURNTABLE_FRAMES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TURNTABLE_FRAMES)
+        .min(MAX_TURNTABLE_FRAMES);
+    (frames > 0).then_some(frames)
+}
+
+/// Whether thumbnails can be rendered for a format
+pub fn supports(format: ModelFormat) -> bool {
+    matches!(format, ModelFormat::Glb | ModelFormat::Gltf | ModelFormat::Splat)
+}
+
+/// A flat-coloured triangle in world space
+struct Triangle {
+    vertices: [[f64; 3]; 3],
+    colour: [f64; 3],
+}
+
+/// A Gaussian splat, drawn as a round blob
+struct Splat {
+    position: [f64; 3],
+    /// Standard deviation in world units
+    radius: f64,
+    /// sRGB colour and opacity, from 0 to 1
+    colour: [f64; 4],
+}
+
+/// What a model renders as, and the sphere the camera frames
+struct Scene {
+    triangles: Vec<Triangle>,
+    splats: Vec<Splat>,
+    center: [f64; 3],
+    radius: f64,
+}
+
+/// Renders the thumbnail, and optionally the turntable, of a model
+///
+/// # Arguments
+/// * `model` - Model file
+/// * `format` - Its format, one for which `supports` is true
+/// * `thumbnail` - PNG file to write
+/// * `turntable` - GIF file to write and its number of frames, if any
+pub fn render(model: &Path, format: ModelFormat, thumbnail: &Path, turntable: Option<(&Path, u32)>) -> ProcessingResult<()> {
+    let scene = match format {
+        ModelFormat::Glb | ModelFormat::Gltf => load_gltf(model)?,
+        ModelFormat::Splat => load_splats(model)?,
+        _ => return Err(format!("thumbnails are not supported for {:?} models", format).into()),
+    };
+    if scene.triangles.is_empty() && scene.splats.is_empty() {
+        return Err("the model has nothing to render".into());
+    }
+
+    render_frame(&scene, THUMBNAIL_SIZE, THUMBNAIL_AZIMUTH).save(thumbnail)?;
+
+    if let Some((destination, frames)) = turntable {
+        let mut writer = BufWriter::new(File::create(destination)?);
+        let mut encoder = GifEncoder::new_with_speed(&mut writer, GIF_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for frame in 0..frames {
+            let azimuth = THUMBNAIL_AZIMUTH + 2.0 * PI * frame as f64 / frames as f64;
+            let mut image = render_frame(&scene, TURNTABLE_SIZE, azimuth);
+            for pixel in image.pixels_mut() {
+                let alpha = pixel[3] as u32;
+                for (c, background) in TURNTABLE_BACKGROUND.iter().enumerate() {
+                    pixel[c] = ((pixel[c] as u32 * alpha + *background as u32 * (255 - alpha)) / 255) as u8;
+                }
+                pixel[3] = 255;
+            }
+            encoder.encode_frame(Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(TURNTABLE_FRAME_MS, 1)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Collects the triangles of the default scene of a glTF or GLB model, in
+/// world space, coloured with their material's base colour
+fn load_gltf(path: &Path) -> ProcessingResult<Scene> {
+    let document = match path.extension().and_then(|e| e.to_str()) {
+        Some(e) if e.eq_ignore_ascii_case("gltf") => gltf::read_gltf(path)?,
+        _ => gltf::read_glb(path)?,
+    };
+    let json = &document.json;
+    let buffers = gltf::read_buffers(path, &document)?;
+    let meshes = json.get("meshes").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let materials = json.get("materials").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+
+    let mut triangles = Vec::new();
+    for (mesh, world) in gltf::mesh_instances(json) {
+        let primitives = meshes.get(mesh).and_then(|m| m.get("primitives")).and_then(Value::as_array);
+        for primitive in primitives.into_iter().flatten() {
+            let mode = primitive.get("mode").and_then(Value::as_u64).unwrap_or(4);
+            let Some(position) = primitive.get("attributes").and_then(|a| a.get("POSITION")).and_then(Value::as_u64) else { continue };
+            if !matches!(mode, 4..=6) {
+                continue;
+            }
+            let positions = gltf::read_accessor(json, &buffers, position as usize)?;
+            let indices = match primitive.get("indices").and_then(Value::as_u64) {
+                Some(indices) => gltf::read_indices(json, &buffers, indices as usize)?,
+                None => (0..positions.len() as u32).collect(),
+            };
+            let colour = primitive.get("material").and_then(Value::as_u64)
+                .and_then(|m| materials.get(m as usize))
+                .and_then(|m| m.pointer("/pbrMetallicRoughness/baseColorFactor"))
+                .and_then(Value::as_array)
+                .map(|c| std::array::from_fn(|i| c.get(i).and_then(Value::as_f64).unwrap_or(1.0)))
+                .unwrap_or([0.8; 3]);
+
+            if positions.components != 3 {
+                continue;
+            }
+            let vertex = |i: u32| -> Option<[f64; 3]> {
+                let p = ((i as usize) < positions.len()).then(|| positions.get(i as usize))?;
+                Some(gltf::transform_point(&world, [p[0] as f64, p[1] as f64, p[2] as f64]))
+            };
+            let corners: Vec<[u32; 3]> = match mode {
+                4 => indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect(),
+                5 => indices.windows(3).enumerate()
+                    .map(|(i, t)| if i % 2 == 0 { [t[0], t[1], t[2]] } else { [t[1], t[0], t[2]] })
+                    .collect(),
+                _ => indices.windows(2).skip(1).map(|t| [indices[0], t[0], t[1]]).collect(),
+            };
+            for [a, b, c] in corners {
+                if let (Some(a), Some(b), Some(c)) = (vertex(a), vertex(b), vertex(c)) {
+                    triangles.push(Triangle { vertices: [a, b, c], colour });
+                }
+            }
+        }
+    }
+
+    let points: Vec<[f64; 3]> = triangles.iter().flat_map(|t| t.vertices).collect();
+    let (center, radius) = bounding_sphere(&points, 1.0);
+    Ok(Scene { triangles, splats: Vec::new(), center, radius })
+}
+
+/// Reads the most visible splats of a `.splat` file
+fn load_splats(path: &Path) -> ProcessingResult<Scene> {
+    let data = fs::read(path)?;
+    let splats: Vec<Splat> = data.chunks_exact(SPLAT_RECORD_SIZE as usize)
+        .take(MAX_PREVIEW_SPLATS)
+        .map(|record| {
+            let float = |i: usize| f32::from_le_bytes([record[i * 4], record[i * 4 + 1], record[i * 4 + 2], record[i * 4 + 3]]) as f64;
+            Splat {
+                position: [float(0), float(1), float(2)],
+                radius: (float(3) + float(4) + float(5)) / 3.0,
+                colour: std::array::from_fn(|c| record[24 + c] as f64 / 255.0),
+            }
+        })
+        .filter(|splat| splat.position.iter().all(|v| v.is_finite()) && splat.radius.is_finite())
+        .collect();
+
+    // Scans are surrounded by sparse floaters, so only the bulk is framed
+    let points: Vec<[f64; 3]> = splats.iter().map(|s| s.position).collect();
+    let (center, radius) = bounding_sphere(&points, 0.95);
+    Ok(Scene { triangles: Vec::new(), splats, center, radius })
+}
+
+/// Center and radius of a sphere holding `fraction` of the points, centred
+/// on their bounding box, or on their median when framing only part of them
+fn bounding_sphere(points: &[[f64; 3]], fraction: f64) -> ([f64; 3], f64) {
+    if points.is_empty() {
+        return ([0.0; 3], 1.0);
+    }
+    let center: [f64; 3] = std::array::from_fn(|axis| {
+        let mut values: Vec<f64> = points.iter().map(|p| p[axis]).collect();
+        if fraction < 1.0 {
+            let middle = values.len() / 2;
+            *values.select_nth_unstable_by(middle, f64::total_cmp).1
+        } else {
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (min + max) / 2.0
+        }
+    });
+    let mut distances: Vec<f64> = points.iter().map(|p| distance(p, &center)).collect();
+    let index = ((distances.len() as f64 * fraction).ceil() as usize).clamp(1, distances.len()) - 1;
+    let radius = *distances.select_nth_unstable_by(index, f64::total_cmp).1;
+    (center, if radius > 0.0 { radius } else { 1.0 })
+}
+
+/// A camera looking at the center of the scene from a point on a sphere
+/// that fits it into the field of view
+struct Camera {
+    eye: [f64; 3],
+    right: [f64; 3],
+    up: [f64; 3],
+    forward: [f64; 3],
+    /// Pixels per unit of `x / z` at the image plane
+    focal: f64,
+    size: f64,
+}
+
+impl Camera {
+    fn new(scene: &Scene, size: u32, azimuth: f64) -> Self {
+        let distance = scene.radius * FRAMING_MARGIN / (FIELD_OF_VIEW / 2.0).sin();
+        let direction = [azimuth.sin() * ELEVATION.cos(), ELEVATION.sin(), azimuth.cos() * ELEVATION.cos()];
+        let eye = std::array::from_fn(|i| scene.center[i] + direction[i] * distance);
+        let forward = normalize(std::array::from_fn(|i| -direction[i]));
+        let right = normalize(cross(&forward, &[0.0, 1.0, 0.0]));
+        let up = cross(&right, &forward);
+        let size = size as f64;
+        Camera { eye, right, up, forward, focal: size / 2.0 / (FIELD_OF_VIEW / 2.0).tan(), size }
+    }
+
+    /// Screen position and depth of a world-space point
+    fn project(&self, point: &[f64; 3]) -> Option<[f64; 3]> {
+        let relative = std::array::from_fn(|i| point[i] - self.eye[i]);
+        let depth = dot(&relative, &self.forward);
+        (depth > 1e-9).then(|| [
+            self.size / 2.0 + dot(&relative, &self.right) / depth * self.focal,
+            self.size / 2.0 - dot(&relative, &self.up) / depth * self.focal,
+            depth,
+        ])
+    }
+}
+
+/// Renders one view of the scene with a transparent background
+fn render_frame(scene: &Scene, size: u32, azimuth: f64) -> RgbaImage {
+    let full = size * SUPERSAMPLE;
+    let camera = Camera::new(scene, full, azimuth);
+    // Premultiplied RGBA
+    let mut pixels = vec![[0.0f64; 4]; (full * full) as usize];
+
+    if !scene.triangles.is_empty() {
+        rasterize(&scene.triangles, &camera, full, &mut pixels);
+    }
+    if !scene.splats.is_empty() {
+        splat(&scene.splats, &camera, full, &mut pixels);
+    }
+
+    // Box-filter down to the output size
+    let samples = (SUPERSAMPLE * SUPERSAMPLE) as f64;
+    RgbaImage::from_fn(size, size, |x, y| {
+        let mut sum = [0.0; 4];
+        for dy in 0..SUPERSAMPLE {
+            for dx in 0..SUPERSAMPLE {
+                let pixel = pixels[((y * SUPERSAMPLE + dy) * full + x * SUPERSAMPLE + dx) as usize];
+                (0..4).for_each(|c| sum[c] += pixel[c] / samples);
+            }
+        }
+        let alpha = sum[3];
+        let colour = |c: usize| if alpha > 0.0 { (sum[c] / alpha * 255.0).round().clamp(0.0, 255.0) as u8 } else { 0 };
+        Rgba([colour(0), colour(1), colour(2), (alpha * 255.0).round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// Draws triangles with a depth buffer and two-sided Lambert shading from a
+/// light over the camera's shoulder
+fn rasterize(triangles: &[Triangle], camera: &Camera, size: u32, pixels: &mut [[f64; 4]]) {
+    let light = normalize(std::array::from_fn(|i| -0.5 * camera.forward[i] + 0.8 * camera.up[i] - 0.5 * camera.right[i]));
+    // Inverse depth, so larger is nearer and it interpolates linearly on screen
+    let mut depth = vec![0.0f64; pixels.len()];
+
+    for triangle in triangles {
+        let [a, b, c] = &triangle.vertices;
+        let (Some(pa), Some(pb), Some(pc)) = (camera.project(a), camera.project(b), camera.project(c)) else { continue };
+        let area = edge(&pa, &pb, &pc);
+        if area.abs() < 1e-12 {
+            continue;
+        }
+
+        let normal = normalize(cross(&sub(b, a), &sub(c, a)));
+        let facing = dot(&normal, &sub(&camera.eye, a)).signum();
+        let diffuse = (dot(&normal, &light) * facing).max(0.0);
+        let shade = 0.3 + 0.7 * diffuse;
+        let colour: [f64; 3] = std::array::from_fn(|i| (triangle.colour[i].clamp(0.0, 1.0) * shade).powf(1.0 / 2.2));
+
+        let min_x = pa[0].min(pb[0]).min(pc[0]).floor().max(0.0) as u32;
+        let max_x = pa[0].max(pb[0]).max(pc[0]).ceil().min(size as f64 - 1.0) as i64;
+        let min_y = pa[1].min(pb[1]).min(pc[1]).floor().max(0.0) as u32;
+        let max_y = pa[1].max(pb[1]).max(pc[1]).ceil().min(size as f64 - 1.0) as i64;
+        for y in min_y as i64..=max_y {
+            for x in min_x as i64..=max_x {
+                let p = [x as f64 + 0.5, y as f64 + 0.5, 0.0];
+                let (wa, wb, wc) = (edge(&pb, &pc, &p) / area, edge(&pc, &pa, &p) / area, edge(&pa, &pb, &p) / area);
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let inverse_depth = wa / pa[2] + wb / pb[2] + wc / pc[2];
+                let index = (y as u32 * size + x as u32) as usize;
+                if inverse_depth > depth[index] {
+                    depth[index] = inverse_depth;
+                    pixels[index] = [colour[0], colour[1], colour[2], 1.0];
+                }
+            }
+        }
+    }
+}
+
+/// Blends splats back to front as circular Gaussians
+fn splat(splats: &[Splat], camera: &Camera, size: u32, pixels: &mut [[f64; 4]]) {
+    let mut projected: Vec<([f64; 3], &Splat)> = splats.iter()
+        .filter_map(|splat| camera.project(&splat.position).map(|p| (p, splat)))
+        .collect();
+    projected.sort_by(|a, b| b.0[2].total_cmp(&a.0[2]));
+
+    // Tiny splats still cover a pixel; huge ones are capped to bound the cost
+    let max_sigma = size as f64 / 96.0;
+    for ([x, y, depth], splat) in projected {
+        let sigma = (splat.radius / depth * camera.focal).clamp(0.5, max_sigma);
+        let reach = (sigma * 2.5).ceil();
+        let (x0, x1) = ((x - reach).max(0.0) as i64, (x + reach).min(size as f64 - 1.0) as i64);
+        let (y0, y1) = ((y - reach).max(0.0) as i64, (y + reach).min(size as f64 - 1.0) as i64);
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let (dx, dy) = (px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                let alpha = splat.colour[3] * (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+                if alpha < 1.0 / 255.0 {
+                    continue;
+                }
+                let pixel = &mut pixels[(py as u32 * size + px as u32) as usize];
+                for (value, colour) in pixel.iter_mut().zip(&splat.colour[..3]) {
+                    *value = colour * alpha + *value * (1.0 - alpha);
+                }
+                pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the screen triangle `a`, `b`, `p`
+fn edge(a: &[f64; 3], b: &[f64; 3], p: &[f64; 3]) -> f64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|i| a[i] - b[i])
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = dot(&v, &v).sqrt();
+    if length > 0.0 { v.map(|c| c / length) } else { v }
+}
+
+fn distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let d = sub(a, b);
+    dot(&d, &d).sqrt()
+}
@@ -0,0 +1,360 @@
+//! ISO base media file (MP4/MOV) parsing
+//!
+//! Walks the box structure of MP4 and QuickTime files to read the duration
+//! and tracks described in the `moov` box. Only the boxes needed for probing
+//! are read; media data is skipped without being loaded.
+
+use std::{fs::File, io::{BufReader, Read, Seek, SeekFrom}, path::Path};
+
+use super::{video::{MediaInfo, Track, TrackKind}, ProcessingResult};
+
+/// Largest `moov` box that will be loaded into memory
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Iterates over the boxes contained in `data`, yielding their type and body
+pub(super) fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let kind: [u8; 4] = header[4..8].try_into().ok()?;
+        let (header_len, size) = match u32::from_be_bytes(header[0..4].try_into().ok()?) {
+            0 => (8, data.len() - pos),
+            1 => (16, u64::from_be_bytes(data.get(pos + 8..pos + 16)?.try_into().ok()?) as usize),
+            size => (8, size as usize),
+        };
+        if size < header_len {
+            return None;
+        }
+        let body = data.get(pos + header_len..pos.checked_add(size)?)?;
+        pos += size;
+        Some((kind, body))
+    })
+}
+
+/// Finds the first child box of the given type
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// Follows a path of nested boxes, e.g. `[b"mdia", b"minf", b"stbl"]`
+fn descend<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| child(data, kind))
+}
+
+/// Position of a box at the top level of a file
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TopLevelBox {
+    pub kind: [u8; 4],
+    /// Offset of the box header in the file
+    pub offset: u64,
+    /// Size of the box including its header
+    pub size: u64,
+    /// Size of the header, 16 when a 64-bit size is used
+    pub header_len: u64,
+}
+
+/// Lists the top-level boxes of a file without reading their bodies
+///
+/// Stops at the first box that does not fit in the file.
+pub(super) fn top_level_boxes(file: &mut (impl Read + Seek), file_len: u64) -> ProcessingResult<Vec<TopLevelBox>> {
+    let mut found = Vec::new();
+    let mut offset = 0u64;
+
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let (header_len, size) = match u32::from_be_bytes(header[0..4].try_into()?) {
+            0 => (8, file_len - offset),
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                (16, u64::from_be_bytes(large))
+            },
+            size => (8, size as u64),
+        };
+        if size < header_len || offset.checked_add(size).is_none_or(|end| end > file_len) {
+            // Truncated or corrupt; callers that rewrite the file check coverage
+            break;
+        }
+
+        found.push(TopLevelBox { kind: header[4..8].try_into()?, offset, size, header_len });
+        offset += size;
+    }
+
+    Ok(found)
+}
+
+/// Reads the body of a top-level box
+pub(super) fn read_body(file: &mut (impl Read + Seek), top: &TopLevelBox) -> ProcessingResult<Vec<u8>> {
+    if top.size > MAX_MOOV_SIZE {
+        return Err("moov box is too large".into());
+    }
+    file.seek(SeekFrom::Start(top.offset + top.header_len))?;
+    let mut body = vec![0u8; (top.size - top.header_len) as usize];
+    file.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Reads the `moov` box of a file
+///
+/// # Returns
+/// The body of the box, or `None` if the file has no `moov` box
+fn read_moov(path: &Path) -> ProcessingResult<Option<Vec<u8>>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let file_len = file.get_ref().metadata()?.len();
+
+    match top_level_boxes(&mut file, file_len)?.iter().find(|top| &top.kind == b"moov") {
+        Some(moov) => Ok(Some(read_body(&mut file, moov)?)),
+        None => Ok(None),
+    }
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+pub(super) fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+pub(super) fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Reads the timescale and raw duration of an `mvhd` or `mdhd` box
+fn timing(data: &[u8]) -> Option<(u32, u64)> {
+    match data.first()? {
+        1 => Some((be_u32(data, 20)?, be_u64(data, 24)?)),
+        _ => Some((be_u32(data, 12)?, be_u32(data, 16)? as u64)),
+    }
+}
+
+/// Reads the duration of an `mvhd` or `mdhd` box
+///
+/// # Returns
+/// The duration in milliseconds, or `None` when it is unknown
+fn header_duration(data: &[u8]) -> Option<u64> {
+    let (timescale, duration) = timing(data)?;
+    // All ones marks an unknown duration
+    if timescale == 0 || duration == u64::MAX || duration == u32::MAX as u64 {
+        return None;
+    }
+    Some((duration as u128 * 1000 / timescale as u128) as u64)
+}
+
+/// Reads the display size from a `tkhd` box, accounting for rotation
+fn display_size(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let matrix = if tkhd.first()? == &1 { 52 } else { 40 };
+    let width = be_u32(tkhd, matrix + 36)? >> 16;
+    let height = be_u32(tkhd, matrix + 40)? >> 16;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    // A matrix of the form [0 b; c 0] rotates by 90 or 270 degrees
+    let (a, b) = (be_u32(tkhd, matrix)?, be_u32(tkhd, matrix + 4)?);
+    let (c, d) = (be_u32(tkhd, matrix + 12)?, be_u32(tkhd, matrix + 16)?);
+    if a == 0 && d == 0 && b != 0 && c != 0 {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+/// Computes the average frame rate from an `stts` box and the media timescale
+///
+/// # Returns
+/// The frame rate, or `None` if the table is empty, truncated or overflows
+fn frame_rate(stts: &[u8], timescale: u32) -> Option<f64> {
+    let entries = be_u32(stts, 4)? as usize;
+    let (mut samples, mut ticks) = (0u64, 0u64);
+    for i in 0..entries {
+        let entry = 8 + i * 8;
+        let count = be_u32(stts, entry)? as u64;
+        samples = samples.checked_add(count)?;
+        ticks = ticks.checked_add(count.checked_mul(be_u32(stts, entry + 4)? as u64)?)?;
+    }
+    (samples > 0 && ticks > 0).then(|| samples as f64 * timescale as f64 / ticks as f64)
+}
+
+/// Reads the duration and tracks of an MP4 or QuickTime file
+pub fn read(path: &Path) -> ProcessingResult<MediaInfo> {
+    let moov = read_moov(path)?.ok_or("file has no moov box")?;
+
+    let tracks = boxes(&moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .filter_map(|(_, trak)| {
+            let mdia = child(trak, b"mdia")?;
+            // hdlr: version/flags (4), pre_defined (4), handler_type (4)
+            let handler = child(mdia, b"hdlr")?.get(8..12)?;
+            let kind = match handler {
+                b"vide" => TrackKind::Video,
+                b"soun" => TrackKind::Audio,
+                _ => TrackKind::Other,
+            };
+
+            // stsd: version/flags (4), entry_count (4), then sample entry boxes
+            let stbl = descend(mdia, &[b"minf", b"stbl"])?;
+            let (fourcc, entry) = boxes(child(stbl, b"stsd")?.get(8..)?).next()?;
+
+            let mdhd = child(mdia, b"mdhd");
+            let mut track = Track {
+                kind,
+                codec: codec_name(&fourcc),
+                duration_ms: mdhd.and_then(header_duration),
+                ..Track::default()
+            };
+
+            if kind == TrackKind::Video {
+                // Visual sample entries store the coded size at offset 24
+                let coded_size = be_u16(entry, 24).zip(be_u16(entry, 26))
+                    .map(|(w, h)| (w as u32, h as u32))
+                    .filter(|&(w, h)| w > 0 && h > 0);
+                let size = child(trak, b"tkhd").and_then(display_size).or(coded_size);
+                track.width = size.map(|(w, _)| w);
+                track.height = size.map(|(_, h)| h);
+
+                let timescale = mdhd.and_then(timing).map(|(timescale, _)| timescale);
+                track.frame_rate = child(stbl, b"stts")
+                    .zip(timescale)
+                    .and_then(|(stts, timescale)| frame_rate(stts, timescale));
+            }
+
+            Some(track)
+        })
+        .collect();
+
+    Ok(MediaInfo {
+        duration_ms: child(&moov, b"mvhd").and_then(header_duration),
+        tracks,
+    })
+}
+
+/// Maps a sample entry type to a codec name
+fn codec_name(fourcc: &[u8; 4]) -> String {
+    match fourcc {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"av01" => "av1",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"mp4v" => "mpeg4",
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores",
+        b"mp4a" => "aac",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b".mp3" => "mp3",
+        _ => return String::from_utf8_lossy(fourcc).trim().to_lowercase(),
+    }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = (body.len() as u32 + 8).to_be_bytes().to_vec();
+        out.extend(kind);
+        out.extend(body);
+        out
+    }
+
+    fn be(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    /// An `mvhd` or `mdhd` box, version 0
+    fn header(kind: &[u8; 4], timescale: u32, duration: u32) -> Vec<u8> {
+        mp4_box(kind, &be(&[0, 0, 0, timescale, duration, 0]))
+    }
+
+    /// A version 0 `tkhd` box with a transformation matrix and a 16.16 display size
+    fn tkhd(matrix: [u32; 9], width: u32, height: u32) -> Vec<u8> {
+        let mut body = be(&[0; 10]);
+        body.extend(be(&matrix));
+        body.extend(be(&[width << 16, height << 16]));
+        mp4_box(b"tkhd", &body)
+    }
+
+    const IDENTITY: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000];
+
+    /// A track with one sample entry, its coded size, and a time-to-sample table
+    fn trak(handler: &[u8; 4], entry: &[u8; 4], size: (u16, u16), timescale: u32, duration: u32, stts: &[(u32, u32)], tkhd: Vec<u8>) -> Vec<u8> {
+        let mut sample_entry = vec![0; 78];
+        sample_entry[24..26].copy_from_slice(&size.0.to_be_bytes());
+        sample_entry[26..28].copy_from_slice(&size.1.to_be_bytes());
+        let mut stsd = be(&[0, 1]);
+        stsd.extend(mp4_box(entry, &sample_entry));
+        let mut table = be(&[0, stts.len() as u32]);
+        stts.iter().for_each(|(count, delta)| table.extend(be(&[*count, *delta])));
+        let stbl = [mp4_box(b"stsd", &stsd), mp4_box(b"stts", &table)].concat();
+        let mut hdlr = be(&[0, 0]);
+        hdlr.extend(handler);
+        hdlr.extend([0; 13]);
+        let mdia = [header(b"mdhd", timescale, duration), mp4_box(b"hdlr", &hdlr), mp4_box(b"minf", &mp4_box(b"stbl", &stbl))].concat();
+        mp4_box(b"trak", &[tkhd, mp4_box(b"mdia", &mdia)].concat())
+    }
+
+    #[test]
+    fn reads_duration_and_tracks() {
+        let video = trak(b"vide", b"avc1", (1920, 1088), 30000, 300300, &[(300, 1001)], tkhd(IDENTITY, 1920, 1080));
+        let audio = trak(b"soun", b"mp4a", (0, 0), 48000, 480000, &[(469, 1024)], tkhd(IDENTITY, 0, 0));
+        let moov = mp4_box(b"moov", &[header(b"mvhd", 1000, 10010), video, audio].concat());
+        let path = env::temp_dir().join(format!("mp4_{}.mp4", process::id()));
+        fs::write(&path, [mp4_box(b"ftyp", b"isom"), mp4_box(b"mdat", &[0; 16]), moov].concat()).unwrap();
+        let info = read(&path);
+        let _ = fs::remove_file(&path);
+        let info = info.unwrap();
+
+        assert_eq!(info.duration_ms, Some(10010));
+        let video = &info.tracks[0];
+        assert_eq!((video.kind, video.codec.as_str(), video.duration_ms), (TrackKind::Video, "h264", Some(10010)));
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+        let audio = &info.tracks[1];
+        assert_eq!((audio.kind, audio.codec.as_str(), audio.duration_ms), (TrackKind::Audio, "aac", Some(10000)));
+        assert_eq!((audio.width, audio.frame_rate), (None, None));
+    }
+
+    #[test]
+    fn applies_rotation_to_display_size() {
+        let rotated = [0, 0x10000, 0, 0xFFFF_0000, 0, 0, 0, 0, 0x4000_0000];
+        let tkhd = tkhd(rotated, 1920, 1080);
+        assert_eq!(display_size(&tkhd[8..]), Some((1080, 1920)));
+        assert_eq!(display_size(&self::tkhd(IDENTITY, 0, 1080)[8..]), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_frame_rates() {
+        let stts = |entries: &[(u32, u32)]| {
+            let mut table = be(&[0, entries.len() as u32]);
+            entries.iter().for_each(|(count, delta)| table.extend(be(&[*count, *delta])));
+            table
+        };
+        assert_eq!(frame_rate(&stts(&[(25, 40)]), 1000), Some(25.0));
+        assert_eq!(frame_rate(&stts(&[(u32::MAX, u32::MAX), (u32::MAX, u32::MAX)]), 1000), None);
+        assert_eq!(frame_rate(&stts(&[]), 1000), None);
+        // Truncated tables are rejected rather than read short
+        assert_eq!(frame_rate(&stts(&[(25, 40)])[..12], 1000), None);
+    }
+
+    #[test]
+    fn walks_boxes() {
+        let mut large = 1u32.to_be_bytes().to_vec();
+        large.extend(b"larg");
+        large.extend(18u64.to_be_bytes());
+        large.extend(b"ab");
+        let data = [mp4_box(b"free", b"1234"), large, 0u32.to_be_bytes().to_vec(), b"rest".to_vec(), b"tail".to_vec()].concat();
+        let found: Vec<([u8; 4], &[u8])> = boxes(&data).collect();
+        assert_eq!(found, [(*b"free", &b"1234"[..]), (*b"larg", b"ab"), (*b"rest", b"tail")]);
+
+        // A size smaller than the header, or past the end, stops the walk
+        let data = [mp4_box(b"free", b""), 4u32.to_be_bytes().to_vec(), b"bad!".to_vec()].concat();
+        assert_eq!(boxes(&data).count(), 1);
+        assert_eq!(boxes(&mp4_box(b"free", b"1234")[..10]).count(), 0);
+    }
+}
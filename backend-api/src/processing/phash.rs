@@ -0,0 +1,174 @@
+//! Perceptual hashing
+//!
+//! Computes a 64-bit difference hash (dHash) of a photo. Two photos whose
+//! hashes differ in only a few bits look alike, even after resizing,
+//! recompression or small colour changes.
+
+use image::{imageops::FilterType, DynamicImage};
+use std::{collections::HashMap, env, path::Path};
+
+use super::ProcessingResult;
+
+/// Maximum Hamming distance between two near-duplicates when
+/// `PHOTO_DUPLICATE_THRESHOLD` is not set
+const DEFAULT_THRESHOLD: u32 = 10;
+
+/// Maximum Hamming distance for two photos to count as near-duplicates
+pub fn duplicate_threshold() -> u32 {
+    env::var("PHOTO_DUPLICATE_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_THRESHOLD)
+}
+
+/// Computes the difference hash of an image
+///
+/// The image is reduced to a 9x8 greyscale thumbnail and each bit records
+/// whether a pixel is brighter than its right-hand neighbour.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// Decodes an image file and computes its difference hash
+pub fn dhash_file(path: &Path) -> ProcessingResult<u64> {
+    Ok(dhash(&image::open(path)?))
+}
+
+/// Number of differing bits between two hashes
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Finds every pair of hashes at most `threshold` bits apart
+///
+/// The hashes are split into `threshold + 1` bands of bits. Two hashes that
+/// differ in at most `threshold` bits agree on at least one whole band, so
+/// only hashes sharing a band value are compared, instead of every pair.
+///
+/// # Returns
+/// The indices of both hashes, the first one lower, and their distance
+pub fn near_pairs(hashes: &[u64], threshold: u32) -> Vec<(usize, usize, u32)> {
+    if threshold >= 64 {
+        return (0..hashes.len())
+            .flat_map(|i| (i + 1..hashes.len()).map(move |j| (i, j, distance(hashes[i], hashes[j]))))
+            .collect();
+    }
+
+    let bands = band_masks(threshold as usize + 1);
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (i, &hash) in hashes.iter().enumerate() {
+        for (band, &mask) in bands.iter().enumerate() {
+            buckets.entry((band, hash & mask)).or_default().push(i);
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for ((band, _), members) in &buckets {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                let (a, b) = (hashes[i], hashes[j]);
+                // Pairs sharing several bands are only reported from the first one
+                if bands[..*band].iter().any(|&mask| a & mask == b & mask) {
+                    continue;
+                }
+                let distance = distance(a, b);
+                if distance <= threshold {
+                    pairs.push((i, j, distance));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Splits the 64 bits of a hash into `count` contiguous bands of near-equal width
+fn band_masks(count: usize) -> Vec<u64> {
+    (0..count)
+        .map(|band| {
+            let (start, end) = (band * 64 / count, (band + 1) * 64 / count);
+            match end - start {
+                64 => u64::MAX,
+                width => ((1u64 << width) - 1) << start,
+            }
+        })
+        .collect()
+}
+
+/// Formats a hash the way it is stored on `Photo`
+pub fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Parses a hash stored on `Photo`
+pub fn from_hex(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Brute-force reference for `near_pairs`
+    fn all_pairs(hashes: &[u64], threshold: u32) -> Vec<(usize, usize, u32)> {
+        let mut pairs = Vec::new();
+        for i in 0..hashes.len() {
+            for j in i + 1..hashes.len() {
+                let distance = distance(hashes[i], hashes[j]);
+                if distance <= threshold {
+                    pairs.push((i, j, distance));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn band_masks_cover_every_bit_once() {
+        for count in 1..=64 {
+            let masks = band_masks(count);
+            assert_eq!(masks.iter().fold(0, |all, mask| all | mask), u64::MAX);
+            assert_eq!(masks.iter().map(|mask| mask.count_ones()).sum::<u32>(), 64);
+        }
+    }
+
+    #[test]
+    fn near_pairs_matches_brute_force() {
+        // Hashes derived from a few bases with bits flipped, plus some random ones
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut hashes = Vec::new();
+        for _ in 0..8 {
+            let base = next();
+            for flips in 0..12 {
+                let mut hash = base;
+                for _ in 0..flips {
+                    hash ^= 1 << (next() % 64);
+                }
+                hashes.push(hash);
+            }
+        }
+        hashes.extend((0..30).map(|_| next()));
+        hashes.push(hashes[0]);
+
+        for threshold in [0, 1, 5, 10, 20, 63, 64] {
+            let mut pairs = near_pairs(&hashes, threshold);
+            pairs.sort_unstable();
+            assert_eq!(pairs, all_pairs(&hashes, threshold), "threshold {}", threshold);
+        }
+    }
+}
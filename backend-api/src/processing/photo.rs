@@ -0,0 +1,200 @@
+//! Photo derivative rendering
+//!
+//! Uploaded photos are kept untouched in the private originals folder.
+//! What visitors download from `/static/photos` is a derivative rendered
+//! from that original: turned upright according to its EXIF orientation,
+//! edited according to its recipe, downscaled to a sane size and
+//! watermarked when the photo's category asks for it. Animated originals
+//! keep their animation and get a still poster as well.
+
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, GenericImageView, ImageFormat, imageops::FilterType};
+use std::{env, fs, io::BufWriter, path::{Path, PathBuf}};
+
+use crate::handlers::photos::{PHOTO_FOLDER, PHOTO_ORIGINALS_FOLDER};
+use crate::models::PhotoEdits;
+use super::{
+    animation::{self, AnimationInfo},
+    edits::apply_edits,
+    image_info::{apply_orientation, read_orientation},
+    preview,
+    watermark::Watermark,
+    ProcessingResult,
+};
+
+/// Longest edge of a public derivative when `PHOTO_MAX_DIMENSION` is not set
+const DEFAULT_MAX_DIMENSION: u32 = 2560;
+
+/// Subfolder of the public folder holding posters and animated copies
+pub const DERIVED_FOLDER: &str = "derived";
+
+/// JPEG quality used for public derivatives
+const JPEG_QUALITY: u8 = 90;
+
+/// Path of the private original for a stored photo filename
+pub fn original_path(filename: &str) -> PathBuf {
+    Path::new(PHOTO_ORIGINALS_FOLDER).join(filename)
+}
+
+/// Path of the public derivative for a stored photo filename
+pub fn public_path(filename: &str) -> PathBuf {
+    Path::new(PHOTO_FOLDER).join(filename)
+}
+
+fn max_dimension() -> u32 {
+    env::var("PHOTO_MAX_DIMENSION")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_DIMENSION)
+}
+
+/// Whether animated WebP photos that have to be re-encoded get an animated
+/// GIF copy, set with `PHOTO_WEBP_GIF_FALLBACK=true`
+fn webp_gif_fallback() -> bool {
+    env::var("PHOTO_WEBP_GIF_FALLBACK").is_ok_and(|v| v == "true" || v == "1")
+}
+
+/// Whether an image of the given size is within the size limit of public derivatives
+fn fits((width, height): (u32, u32)) -> bool {
+    width <= max_dimension() && height <= max_dimension()
+}
+
+/// Makes sure a private original exists for the photo
+///
+/// Photos uploaded before derivatives existed only have their public file;
+/// that file is adopted as the original the first time it is re-rendered.
+pub fn ensure_original(filename: &str) -> ProcessingResult<PathBuf> {
+    let original = original_path(filename);
+    if !original.exists() {
+        fs::create_dir_all(PHOTO_ORIGINALS_FOLDER)?;
+        fs::copy(public_path(filename), &original)?;
+    }
+    Ok(original)
+}
+
+/// Public files produced for an animated photo
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedRendition {
+    pub info: AnimationInfo,
+    /// Still first frame, always a PNG
+    pub poster_filename: String,
+    /// Animated copy: the photo's own filename, an animated GIF for animated
+    /// WebP originals that had to be re-encoded, or `None` if the
+    /// animation could not be published
+    pub animation_filename: Option<String>,
+}
+
+/// Builds the name of a file published alongside a photo, e.g.
+/// `derived/photo_x_poster.png`
+///
+/// Derived files live in `DERIVED_FOLDER` so listings of the public folder
+/// only show the photos themselves.
+fn derived_filename(filename: &str, suffix: &str, extension: &str) -> String {
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or(filename);
+    format!("{}/{}{}.{}", DERIVED_FOLDER, stem, suffix, extension)
+}
+
+/// Renders the public derivative of a photo from its private original
+///
+/// Animated originals additionally get a still poster, and every frame of
+/// the animation goes through the same edits, resizing and watermark.
+///
+/// # Arguments
+/// * `filename` - Stored filename of the photo
+/// * `edits` - Edit recipe to replay on the original, if any
+/// * `watermark` - Watermark to stamp on the derivative, if any
+///
+/// # Returns
+/// The animated renditions when the original is animated, `None` otherwise
+pub fn render_derivative(
+    filename: &str,
+    edits: Option<&PhotoEdits>,
+    watermark: Option<&Watermark>,
+) -> ProcessingResult<Option<AnimatedRendition>> {
+    let original = ensure_original(filename)?;
+    let format = ImageFormat::from_path(&original)?;
+    let process = |image: DynamicImage| process_frame(image, edits, watermark);
+
+    if let Some(info) = animation::probe(&original)? {
+        let poster = process(animation::first_frame(&original)?);
+        let poster_filename = derived_filename(filename, "_poster", "png");
+        write_image(&public_path(&poster_filename), &poster, ImageFormat::Png)?;
+
+        let animation_filename = match format {
+            ImageFormat::Gif | ImageFormat::Png => {
+                preview::replace_atomically(&public_path(filename), |tmp_path| {
+                    animation::render(&original, tmp_path, format, info, process)
+                })?;
+                Some(filename.to_string())
+            },
+            // Animated WebP cannot be re-encoded, so it is published as is
+            // when nothing would change it
+            _ if edits.is_none() && watermark.is_none() && fits(image::image_dimensions(&original)?) => {
+                preview::replace_atomically(&public_path(filename), |tmp_path| Ok(fs::copy(&original, tmp_path).map(|_| ())?))?;
+                let _ = fs::remove_file(public_path(&derived_filename(filename, "_anim", "gif")));
+                Some(filename.to_string())
+            },
+            _ => {
+                write_image(&public_path(filename), &poster, format)?;
+                let gif = derived_filename(filename, "_anim", "gif");
+                if webp_gif_fallback() {
+                    preview::replace_atomically(&public_path(&gif), |tmp_path| {
+                        animation::render(&original, tmp_path, ImageFormat::Gif, info, process)
+                    })?;
+                    Some(gif)
+                } else {
+                    let _ = fs::remove_file(public_path(&gif));
+                    None
+                }
+            },
+        };
+
+        return Ok(Some(AnimatedRendition { info, poster_filename, animation_filename }));
+    }
+
+    let mut image = image::open(&original)?;
+    if let Some(orientation) = read_orientation(&original) {
+        image = apply_orientation(image, orientation);
+    }
+
+    write_image(&public_path(filename), &process(image), format)?;
+    Ok(None)
+}
+
+/// Applies the edits, size limit and watermark to a still image or animation frame
+fn process_frame(
+    mut image: DynamicImage,
+    edits: Option<&PhotoEdits>,
+    watermark: Option<&Watermark>,
+) -> DynamicImage {
+    if let Some(edits) = edits {
+        image = apply_edits(image, edits);
+    }
+
+    if !fits(image.dimensions()) {
+        image = image.resize(max_dimension(), max_dimension(), FilterType::Lanczos3);
+    }
+
+    if let Some(watermark) = watermark {
+        let mut rgba = image.to_rgba8();
+        watermark.apply(&mut rgba);
+        image = DynamicImage::ImageRgba8(rgba);
+    }
+
+    image
+}
+
+/// Encodes a still image to the given path
+fn write_image(path: &Path, image: &DynamicImage, format: ImageFormat) -> ProcessingResult<()> {
+    preview::replace_atomically(path, |tmp_path| {
+        let mut writer = BufWriter::new(fs::File::create(tmp_path)?);
+        match format {
+            ImageFormat::Jpeg => {
+                let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+                JpegEncoder::new_with_quality(&mut writer, JPEG_QUALITY).encode_image(&rgb)?;
+            },
+            _ => image.write_to(&mut writer, format)?,
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(())
+    })
+}
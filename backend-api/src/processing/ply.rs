@@ -0,0 +1,295 @@
+//! PLY point cloud and mesh parsing
+//!
+//! Reads the header of PLY files, which declares the elements (vertices,
+//! faces, ...) and their properties, and checks that the body matches it.
+
+use std::{fs::File, io::{BufRead, BufReader, Read}, path::Path};
+
+/// Longest header accepted; real headers are a few hundred bytes
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Encoding of the body of a PLY file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Scalar types a property can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    /// Size of a binary value in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Decodes a binary value
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! number {
+            ($t:ty) => {{
+                let bytes = bytes[..self.size()].try_into().unwrap_or_default();
+                (if big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) }) as f64
+            }};
+        }
+        match self {
+            Scalar::I8 => number!(i8),
+            Scalar::U8 => number!(u8),
+            Scalar::I16 => number!(i16),
+            Scalar::U16 => number!(u16),
+            Scalar::I32 => number!(i32),
+            Scalar::U32 => number!(u32),
+            Scalar::F32 => number!(f32),
+            Scalar::F64 => number!(f64),
+        }
+    }
+}
+
+/// A property of an element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    pub name: String,
+    pub kind: Scalar,
+    /// Type of the length prefix, for list properties such as face indices
+    pub list_count: Option<Scalar>,
+}
+
+/// An element declared in the header, such as `vertex` or `face`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub count: u64,
+    pub properties: Vec<Property>,
+}
+
+impl Element {
+    /// Size of one binary record, or `None` if it contains lists
+    pub fn record_size(&self) -> Option<usize> {
+        self.properties.iter()
+            .map(|p| p.list_count.is_none().then_some(p.kind.size()))
+            .sum()
+    }
+
+    /// Position of a property in each record
+    pub fn property(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name == name)
+    }
+}
+
+/// The parsed header of a PLY file
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub encoding: Encoding,
+    pub elements: Vec<Element>,
+    /// Length of the header in bytes, i.e. the offset of the body
+    pub len: u64,
+}
+
+impl Header {
+    /// Looks up an element by name
+    pub fn element(&self, name: &str) -> Option<&Element> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+}
+
+/// Reads the header of a PLY file
+pub fn read_header(reader: &mut impl BufRead) -> Result<Header, String> {
+    let mut len = 0;
+    if next_line(reader, &mut len)?.trim_end() != "ply" {
+        return Err("file does not start with 'ply'".to_string());
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        let line = next_line(reader, &mut len)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {},
+            ["format", format, "1.0"] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(format!("unknown PLY format '{}'", format)),
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| format!("element {} has an invalid count '{}'", name, count))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            },
+            ["property", "list", count, kind, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("property {} comes before any element", name))?;
+                let list_count = Scalar::parse(count)
+                    .filter(|t| !matches!(t, Scalar::F32 | Scalar::F64))
+                    .ok_or_else(|| format!("property {} has an invalid list length type '{}'", name, count))?;
+                let kind = Scalar::parse(kind).ok_or_else(|| format!("property {} has an unknown type '{}'", name, kind))?;
+                element.properties.push(Property { name: name.to_string(), kind, list_count: Some(list_count) });
+            },
+            ["property", kind, name] => {
+                let element = elements.last_mut().ok_or_else(|| format!("property {} comes before any element", name))?;
+                let kind = Scalar::parse(kind).ok_or_else(|| format!("property {} has an unknown type '{}'", name, kind))?;
+                element.properties.push(Property { name: name.to_string(), kind, list_count: None });
+            },
+            _ => return Err(format!("invalid header line '{}'", line.trim_end())),
+        }
+    }
+
+    let encoding = encoding.ok_or("header has no format line")?;
+    Ok(Header { encoding, elements, len: len as u64 })
+}
+
+/// Reads one header line, counting its bytes into `len`
+fn next_line(reader: &mut impl BufRead, len: &mut usize) -> Result<String, String> {
+    let mut line = Vec::new();
+    let read = reader.take(MAX_HEADER_SIZE.saturating_sub(*len) as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| e.to_string())?;
+    if read == 0 || line.last() != Some(&b'\n') {
+        return Err("header is truncated or has no end_header line".to_string());
+    }
+    *len += read;
+    String::from_utf8(line).map_err(|_| "header is not ASCII text".to_string())
+}
+
+/// Reads the header of a PLY file and checks that the body matches it
+///
+/// Binary bodies must hold every declared record; when elements have list
+/// properties, only the vertex records can be checked. ASCII bodies must
+/// have a line for every declared record.
+pub fn validate(path: &Path) -> Result<Header, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let file_len = reader.get_ref().metadata().map_err(|e| e.to_string())?.len();
+    let header = read_header(&mut reader)?;
+
+    let vertex = header.element("vertex").ok_or("file has no vertex element")?;
+    for axis in ["x", "y", "z"] {
+        if vertex.property(axis).is_none() {
+            return Err(format!("vertex element has no {} property", axis));
+        }
+    }
+
+    let body_len = file_len - header.len;
+    let too_large = || "header declares more data than a file can hold".to_string();
+    match header.encoding {
+        Encoding::Ascii => {
+            let records = header.elements.iter().try_fold(0u64, |sum, e| sum.checked_add(e.count)).ok_or_else(too_large)?;
+            let mut lines = 0;
+            for line in reader.lines() {
+                let line = line.map_err(|_| "body is not ASCII text".to_string())?;
+                if !line.trim().is_empty() {
+                    lines += 1;
+                }
+            }
+            if lines < records {
+                return Err(format!("header declares {} records, but the body only has {} lines", records, lines));
+            }
+        },
+        Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => {
+            let sizes = header.elements.iter()
+                .map(|e| e.record_size().map(|size| (size as u64).checked_mul(e.count).ok_or_else(too_large)).transpose())
+                .collect::<Result<Vec<_>, _>>()?;
+            let expected = match sizes.into_iter().collect::<Option<Vec<u64>>>() {
+                Some(sizes) => Some(sizes.into_iter().try_fold(0u64, u64::checked_add).ok_or_else(too_large)?),
+                None => None,
+            };
+            match expected {
+                Some(expected) if expected > body_len => {
+                    return Err(format!(
+                        "file is truncated: the header declares {} bytes of data, but there are only {}",
+                        expected, body_len
+                    ));
+                },
+                // Lists make the body size unknowable without reading it; at
+                // least the fixed-size vertex records must fit
+                None if vertex.record_size().is_some_and(|size| (size as u64).checked_mul(vertex.count).is_none_or(|len| len > body_len)) => {
+                    return Err("file is truncated: the vertex data does not fit in the body".to_string());
+                },
+                _ => {},
+            }
+        },
+    }
+
+    Ok(header)
+}
+
+/// Reads every vertex of a PLY file whose first element is `vertex` and
+/// has no list properties, as is the case for point clouds and Gaussian
+/// splats
+///
+/// # Arguments
+/// * `path` - PLY file
+/// * `visit` - Called with the values of each vertex, in property order
+///
+/// # Returns
+/// The header of the file
+pub fn read_vertices(path: &Path, mut visit: impl FnMut(&[f64])) -> Result<Header, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let header = read_header(&mut reader)?;
+    let vertex = header.elements.first().filter(|e| e.name == "vertex")
+        .ok_or("the first element is not vertex")?;
+    let record_size = vertex.record_size().ok_or("vertex element has list properties")?;
+
+    let mut values = vec![0.0; vertex.properties.len()];
+    match header.encoding {
+        Encoding::Ascii => {
+            let mut lines = reader.lines();
+            for index in 0..vertex.count {
+                let line = lines.next()
+                    .ok_or_else(|| format!("vertex {} is missing", index))?
+                    .map_err(|_| "body is not ASCII text".to_string())?;
+                let mut words = line.split_whitespace();
+                for value in values.iter_mut() {
+                    *value = words.next().and_then(|w| w.parse().ok())
+                        .ok_or_else(|| format!("vertex {} has a missing or invalid value", index))?;
+                }
+                visit(&values);
+            }
+        },
+        Encoding::BinaryLittleEndian | Encoding::BinaryBigEndian => {
+            let big_endian = header.encoding == Encoding::BinaryBigEndian;
+            let mut record = vec![0u8; record_size];
+            for index in 0..vertex.count {
+                reader.read_exact(&mut record).map_err(|_| format!("vertex {} is truncated", index))?;
+                let mut offset = 0;
+                for (value, property) in values.iter_mut().zip(&vertex.properties) {
+                    *value = property.kind.decode(&record[offset..], big_endian);
+                    offset += property.kind.size();
+                }
+                visit(&values);
+            }
+        },
+    }
+    Ok(header)
+}
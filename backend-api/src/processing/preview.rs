@@ -0,0 +1,249 @@
+//! Video poster frames and thumbnail strips
+//!
+//! Extracts a poster image for each video and, optionally, a sprite sheet
+//! of evenly spaced thumbnails with a WebVTT index. Players use the index
+//! to show a preview of the frame under the cursor while hover-scrubbing.
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType};
+use std::{env, ffi::OsStr, fmt::Write as _, fs, io::{BufWriter, Write}, path::Path};
+use uuid::Uuid;
+
+use super::{ffmpeg, vtt, ProcessingResult};
+
+/// Filename of the generated poster inside a video's artifact folder
+pub const POSTER_FILENAME: &str = "poster.jpg";
+
+/// Filename of the thumbnail sprite sheet inside a video's artifact folder
+pub const SPRITE_FILENAME: &str = "thumbnails.jpg";
+
+/// Filename of the WebVTT thumbnail index inside a video's artifact folder
+pub const THUMBNAILS_FILENAME: &str = "thumbnails.vtt";
+
+/// Seconds between thumbnails when `VIDEO_THUMBNAIL_INTERVAL` is not set
+const DEFAULT_THUMBNAIL_INTERVAL: u64 = 5;
+
+/// Widest poster image
+const POSTER_MAX_WIDTH: u32 = 1280;
+
+/// JPEG quality of uploaded posters
+const POSTER_QUALITY: u8 = 90;
+
+/// Widest chapter thumbnail
+pub const CHAPTER_THUMBNAIL_WIDTH: u32 = 320;
+
+/// Width of one thumbnail in the sprite sheet
+const THUMBNAIL_WIDTH: u32 = 160;
+
+/// Thumbnails per sprite sheet row
+const SPRITE_COLUMNS: u64 = 10;
+
+/// Upper bound on thumbnails per video; longer videos get a wider interval
+const MAX_THUMBNAILS: u64 = 100;
+
+/// Milliseconds between thumbnails, or `None` when thumbnail strips are disabled
+///
+/// Configured in seconds with `VIDEO_THUMBNAIL_INTERVAL`; `0` disables them.
+pub fn thumbnail_interval() -> Option<u64> {
+    let seconds = env::var("VIDEO_THUMBNAIL_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_THUMBNAIL_INTERVAL);
+    (seconds > 0).then_some(seconds * 1000)
+}
+
+/// Picks the default poster time: a little way in, to skip fade-ins
+pub fn default_poster_time(duration_ms: Option<u64>) -> u64 {
+    duration_ms.map_or(0, |ms| (ms / 10).min(3000))
+}
+
+/// Extracts a single frame as a JPEG poster
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `destination` - JPEG to write; replaced atomically
+/// * `at_ms` - Position of the frame in milliseconds
+pub fn extract_poster(source: &Path, destination: &Path, at_ms: u64) -> ProcessingResult<()> {
+    extract_frame(source, destination, at_ms, POSTER_MAX_WIDTH)
+}
+
+/// Extracts a single frame as a JPEG no wider than `max_width`
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `destination` - JPEG to write; replaced atomically
+/// * `at_ms` - Position of the frame in milliseconds
+/// * `max_width` - Width the frame is scaled down to if it is wider
+pub fn extract_frame(source: &Path, destination: &Path, at_ms: u64, max_width: u32) -> ProcessingResult<()> {
+    let scale = format!("scale='min({},iw)':-2", max_width);
+    replace_atomically(destination, |tmp| {
+        ffmpeg::run([
+            OsStr::new("-ss"), OsStr::new(&ffmpeg::seconds(at_ms)),
+            OsStr::new("-i"), source.as_os_str(),
+            OsStr::new("-frames:v"), OsStr::new("1"),
+            OsStr::new("-update"), OsStr::new("1"),
+            OsStr::new("-vf"), OsStr::new(&scale),
+            OsStr::new("-q:v"), OsStr::new("3"),
+            tmp.as_os_str(),
+        ])
+    })
+}
+
+/// Stores an uploaded image as a JPEG poster
+///
+/// # Arguments
+/// * `data` - Encoded image in any format the `image` crate can decode
+/// * `destination` - JPEG to write; replaced atomically
+pub fn write_poster(data: &[u8], destination: &Path) -> ProcessingResult<()> {
+    let mut image = image::load_from_memory(data)?;
+    if image.width() > POSTER_MAX_WIDTH {
+        image = image.resize(POSTER_MAX_WIDTH, u32::MAX, FilterType::Lanczos3);
+    }
+
+    replace_atomically(destination, |tmp| {
+        let mut writer = BufWriter::new(fs::File::create(tmp)?);
+        JpegEncoder::new_with_quality(&mut writer, POSTER_QUALITY).encode_image(&image.to_rgb8())?;
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Renders a thumbnail sprite sheet and its WebVTT index
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `folder` - Artifact folder receiving `SPRITE_FILENAME` and `THUMBNAILS_FILENAME`
+/// * `duration_ms` - Duration of the video
+/// * `size` - Display size of the video, used for the thumbnail aspect ratio
+/// * `interval_ms` - Requested time between thumbnails
+pub fn render_thumbnails(
+    source: &Path,
+    folder: &Path,
+    duration_ms: u64,
+    size: Option<(u32, u32)>,
+    interval_ms: u64,
+) -> ProcessingResult<()> {
+    if duration_ms == 0 {
+        return Err("video has no duration".into());
+    }
+    let interval_ms = interval_ms.max(duration_ms.div_ceil(MAX_THUMBNAILS)).max(1);
+    let count = duration_ms.div_ceil(interval_ms);
+    let columns = count.min(SPRITE_COLUMNS);
+    let rows = count.div_ceil(columns);
+
+    // Thumbnail heights must be even for the JPEG encoder's chroma subsampling
+    let height = size
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .map(|(w, h)| ((THUMBNAIL_WIDTH as f64 * h as f64 / w as f64 / 2.0).round() as u32 * 2).max(2))
+        .unwrap_or(THUMBNAIL_WIDTH * 9 / 16);
+
+    let filter = format!(
+        "fps=1000/{},scale={}:{},tile={}x{}",
+        interval_ms, THUMBNAIL_WIDTH, height, columns, rows
+    );
+    replace_atomically(&folder.join(SPRITE_FILENAME), |tmp| {
+        ffmpeg::run([
+            OsStr::new("-i"), source.as_os_str(),
+            OsStr::new("-vf"), OsStr::new(&filter),
+            OsStr::new("-frames:v"), OsStr::new("1"),
+            OsStr::new("-update"), OsStr::new("1"),
+            OsStr::new("-q:v"), OsStr::new("5"),
+            tmp.as_os_str(),
+        ])
+    })?;
+
+    let mut vtt = String::from("WEBVTT\n");
+    for i in 0..count {
+        let start = i * interval_ms;
+        let end = ((i + 1) * interval_ms).min(duration_ms);
+        let (x, y) = ((i % columns) as u32 * THUMBNAIL_WIDTH, (i / columns) as u32 * height);
+        let _ = write!(
+            vtt,
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            vtt::timestamp(start), vtt::timestamp(end), SPRITE_FILENAME, x, y, THUMBNAIL_WIDTH, height
+        );
+    }
+    replace_atomically(&folder.join(THUMBNAILS_FILENAME), |tmp| Ok(fs::write(tmp, vtt)?))
+}
+
+/// Writes `path` through a temporary file or folder, so readers never see
+/// partial output
+///
+/// `write` receives `.tmp_<uuid>_<name>` next to `path`, which keeps the
+/// extension of `path` since ffmpeg picks the output format from it, and is
+/// renamed into place once it succeeds. The random part keeps concurrent
+/// jobs writing the same output apart. The parent folder is created first. Output
+/// without a single non-empty file is treated as a failure: ffmpeg exits
+/// successfully without writing anything when seeking past the last frame.
+/// The temporary file or folder is removed when anything fails.
+pub(super) fn replace_atomically(path: &Path, write: impl FnOnce(&Path) -> ProcessingResult<()>) -> ProcessingResult<()> {
+    let filename = path.file_name().ok_or("invalid output path")?.to_string_lossy();
+    let tmp = path.with_file_name(format!(".tmp_{}_{}", Uuid::new_v4().simple(), filename));
+    fs::create_dir_all(path.parent().ok_or("invalid output path")?)?;
+
+    let result = write(&tmp).and_then(|_| match has_output(&tmp) {
+        true => Ok(fs::rename(&tmp, path)?),
+        false => Err("no output was written".into()),
+    });
+    if result.is_err() {
+        remove_output(&tmp);
+    }
+    result
+}
+
+/// Whether a file is non-empty, or a folder holds a non-empty file
+fn has_output(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .is_ok_and(|entries| entries.flatten().any(|entry| has_output(&entry.path()))),
+        Ok(metadata) => metadata.len() > 0,
+        Err(_) => false,
+    }
+}
+
+/// Removes a file or folder, if it exists
+fn remove_output(path: &Path) {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => { let _ = fs::remove_dir_all(path); },
+        Ok(_) => { let _ = fs::remove_file(path); },
+        Err(_) => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn replaces_files_and_folders_atomically() {
+        let root = env::temp_dir().join(format!("replace_atomically_{}", process::id()));
+        let file = root.join("nested").join("out.txt");
+        let leftovers = |folder: &Path| fs::read_dir(folder).unwrap().flatten()
+            .any(|entry| entry.file_name().to_string_lossy().starts_with(".tmp_"));
+
+        replace_atomically(&file, |tmp| Ok(fs::write(tmp, "first")?)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+
+        // Failed and empty writes keep the previous file and leave nothing behind
+        let error = replace_atomically(&file, |tmp| {
+            fs::write(tmp, "partial")?;
+            Err("failed".into())
+        });
+        assert!(error.is_err());
+        assert!(replace_atomically(&file, |tmp| Ok(fs::write(tmp, "")?)).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first");
+        assert!(!leftovers(file.parent().unwrap()));
+
+        // Folders need at least one non-empty file somewhere inside
+        let folder = root.join("hls");
+        assert!(replace_atomically(&folder, |tmp| Ok(fs::create_dir_all(tmp.join("720p"))?)).is_err());
+        assert!(!folder.exists() && !leftovers(&root));
+        replace_atomically(&folder, |tmp| {
+            fs::create_dir_all(tmp.join("720p"))?;
+            Ok(fs::write(tmp.join("720p").join("index.m3u8"), "#EXTM3U")?)
+        }).unwrap();
+        assert!(folder.join("720p").join("index.m3u8").is_file());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,247 @@
+//! Gaussian splat conversion
+//!
+//! Scans exported by 3D Gaussian Splatting training tools are large PLY
+//! files with spherical harmonics and raw activation values. The viewer
+//! renders the compact 32-byte `.splat` layout instead: position and scale
+//! as floats, colour and opacity as bytes and the rotation quantized to
+//! bytes, sorted so the most visible splats come first.
+
+use serde::{Serialize, Deserialize};
+use std::{env, fs::{self, File}, io::{BufReader, BufWriter, Write}, path::Path};
+
+use super::{model_format::{InvalidModel, SPLAT_RECORD_SIZE}, ply, preview, ProcessingResult};
+
+/// Zeroth-order spherical harmonic coefficient, mapping `f_dc_*` to colour
+const SH_C0: f64 = 0.282_094_791_773_878_14;
+
+/// Opacity below which splats are pruned when `SPLAT_MIN_OPACITY` is not set
+const DEFAULT_MIN_OPACITY: f64 = 0.01;
+
+/// Properties that identify a Gaussian splat PLY
+const GAUSSIAN_PROPERTIES: &[&str] = &["opacity", "scale_0", "scale_1", "scale_2", "rot_0", "rot_1", "rot_2", "rot_3"];
+
+/// Result of compacting a set of splats
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct SplatStats {
+    /// Splats in the stored `.splat` file
+    pub splat_count: u64,
+    /// Splats dropped for being nearly transparent or malformed
+    pub pruned_count: u64,
+    /// Size of the uploaded file in bytes
+    pub original_size: u64,
+    /// Size of the stored `.splat` file in bytes
+    pub size: u64,
+}
+
+/// Opacity, between 0 and 1, below which splats are pruned
+pub fn min_opacity() -> f64 {
+    env::var("SPLAT_MIN_OPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|v: &f64| (0.0..=1.0).contains(v))
+        .unwrap_or(DEFAULT_MIN_OPACITY)
+}
+
+/// Checks whether a PLY file holds 3D Gaussian splats rather than a point
+/// cloud or mesh
+pub fn is_gaussian_ply(path: &Path) -> ProcessingResult<bool> {
+    let header = ply::read_header(&mut BufReader::new(File::open(path)?)).map_err(InvalidModel)?;
+    Ok(has_gaussian_properties(&header))
+}
+
+/// Checks whether a PLY header declares the properties of Gaussian splats
+fn has_gaussian_properties(header: &ply::Header) -> bool {
+    header.element("vertex")
+        .is_some_and(|vertex| GAUSSIAN_PROPERTIES.iter().all(|name| vertex.property(name).is_some()))
+}
+
+/// Converts a Gaussian splat PLY file to the `.splat` layout
+///
+/// Splats are sorted by importance, their volume times their opacity, and
+/// those with an opacity below `min_opacity` or non-finite values are
+/// pruned. Higher-order harmonics are dropped. The conversion is written
+/// atomically.
+///
+/// # Arguments
+/// * `source` - Validated PLY file
+/// * `destination` - Path of the `.splat` file
+/// * `min_opacity` - Opacity below which splats are pruned
+///
+/// # Returns
+/// The splat counts and sizes, or an `InvalidModel` error if the PLY file
+/// cannot be read as splats
+pub fn convert_ply(source: &Path, destination: &Path, min_opacity: f64) -> ProcessingResult<SplatStats> {
+    let invalid = |e: String| InvalidModel(format!("invalid PLY file: {}", e));
+    let header = ply::read_header(&mut BufReader::new(File::open(source)?)).map_err(invalid)?;
+    if !has_gaussian_properties(&header) {
+        return Err(invalid("file does not hold Gaussian splats".to_string()).into());
+    }
+    let vertex = header.element("vertex").ok_or_else(|| invalid("file has no vertex element".to_string()))?;
+    let lookup = |names: [&str; 3]| names.map(|name| vertex.property(name));
+    let position = lookup(["x", "y", "z"]);
+    let scale = lookup(["scale_0", "scale_1", "scale_2"]);
+    let rotation = ["rot_0", "rot_1", "rot_2", "rot_3"].map(|name| vertex.property(name));
+    let opacity = vertex.property("opacity");
+    // Colour comes from the zeroth-order harmonics, or plain RGB bytes
+    let harmonics = lookup(["f_dc_0", "f_dc_1", "f_dc_2"]).iter().all(Option::is_some);
+    let colour = lookup(if harmonics { ["f_dc_0", "f_dc_1", "f_dc_2"] } else { ["red", "green", "blue"] });
+
+    let mut splats: Vec<(f64, [u8; SPLAT_RECORD_SIZE as usize])> = Vec::new();
+    let mut pruned_count = 0;
+    ply::read_vertices(source, |values| {
+        let value = |i: Option<usize>| i.and_then(|i| values.get(i)).copied().unwrap_or(f64::NAN);
+
+        let alpha = 1.0 / (1.0 + (-value(opacity)).exp());
+        let position = position.map(value);
+        let scale = scale.map(|i| value(i).exp());
+        let rotation = rotation.map(value);
+        let norm = rotation.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let finite = position.iter().chain(&scale).all(|v| v.is_finite()) && alpha.is_finite() && norm.is_normal();
+        if !finite || alpha < min_opacity {
+            pruned_count += 1;
+            return;
+        }
+
+        let mut record = [0u8; SPLAT_RECORD_SIZE as usize];
+        for (slot, v) in position.iter().chain(&scale).enumerate() {
+            record[slot * 4..slot * 4 + 4].copy_from_slice(&(*v as f32).to_le_bytes());
+        }
+        for (slot, i) in colour.into_iter().enumerate() {
+            record[24 + slot] = match i {
+                Some(_) if harmonics => to_byte(0.5 + SH_C0 * value(i)),
+                Some(_) => to_byte(value(i) / 255.0),
+                None => 255,
+            };
+        }
+        record[27] = to_byte(alpha);
+        for (slot, v) in rotation.iter().enumerate() {
+            record[28 + slot] = (v / norm * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+        splats.push((scale.iter().product::<f64>() * alpha, record));
+    }).map_err(invalid)?;
+
+    if splats.is_empty() {
+        return Err(InvalidModel(format!("every splat is below the minimum opacity of {}", min_opacity)).into());
+    }
+    splats.sort_by(|a, b| b.0.total_cmp(&a.0));
+    write_splats(destination, splats.iter().map(|(_, record)| record))?;
+
+    Ok(SplatStats {
+        splat_count: splats.len() as u64,
+        pruned_count,
+        original_size: fs::metadata(source)?.len(),
+        size: fs::metadata(destination)?.len(),
+    })
+}
+
+/// Summarises a `.splat` file that was uploaded as is
+pub fn stats(path: &Path) -> ProcessingResult<SplatStats> {
+    let size = fs::metadata(path)?.len();
+    Ok(SplatStats { splat_count: size / SPLAT_RECORD_SIZE, pruned_count: 0, original_size: size, size })
+}
+
+/// Writes splat records to a temporary file and renames it into place
+fn write_splats<'a>(destination: &Path, records: impl Iterator<Item = &'a [u8; SPLAT_RECORD_SIZE as usize]>) -> ProcessingResult<()> {
+    preview::replace_atomically(destination, |tmp| {
+        let mut writer = BufWriter::new(File::create(tmp)?);
+        for record in records {
+            writer.write_all(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+/// Quantizes a value between 0 and 1 to a byte
+fn to_byte(value: f64) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    const PROPERTIES: &[&str] = &[
+        "x", "y", "z", "f_dc_0", "f_dc_1", "f_dc_2", "opacity",
+        "scale_0", "scale_1", "scale_2", "rot_0", "rot_1", "rot_2", "rot_3",
+    ];
+
+    /// A binary Gaussian splat PLY with one vertex per row of `PROPERTIES` values
+    fn gaussian_ply(vertices: &[[f32; 14]]) -> Vec<u8> {
+        let mut ply = format!("ply\nformat binary_little_endian 1.0\nelement vertex {}\n", vertices.len());
+        PROPERTIES.iter().for_each(|name| ply.push_str(&format!("property float {}\n", name)));
+        ply.push_str("end_header\n");
+        let mut bytes = ply.into_bytes();
+        vertices.iter().flatten().for_each(|v| bytes.extend(v.to_le_bytes()));
+        bytes
+    }
+
+    /// Converts a PLY held in memory, returning the stats and the `.splat` bytes
+    fn convert(name: &str, vertices: &[[f32; 14]], min_opacity: f64) -> ProcessingResult<(SplatStats, Vec<u8>)> {
+        let path = |extension: &str| env::temp_dir().join(format!("splat_{}_{}.{}", process::id(), name, extension));
+        fs::write(path("ply"), gaussian_ply(vertices))?;
+        let result = convert_ply(&path("ply"), &path("splat"), min_opacity)
+            .and_then(|stats| Ok((stats, fs::read(path("splat"))?)));
+        let _ = fs::remove_file(path("ply"));
+        let _ = fs::remove_file(path("splat"));
+        result
+    }
+
+    /// Harmonic coefficient that maps to a colour channel of `value`
+    fn harmonic(value: f64) -> f32 {
+        ((value - 0.5) / SH_C0) as f32
+    }
+
+    #[test]
+    fn writes_the_32_byte_layout() {
+        let ln2 = std::f32::consts::LN_2;
+        let splat = [1.0, 2.0, 3.0, harmonic(1.0), harmonic(0.0), harmonic(0.5), 0.0, ln2, 0.0, ln2, 0.0, 0.0, 0.0, 2.0];
+        let (stats, bytes) = convert("layout", &[splat], 0.01).unwrap();
+        assert_eq!(stats.splat_count, 1);
+        assert_eq!(bytes.len(), 32);
+
+        let floats: Vec<f32> = bytes[..24].chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(floats[..3], [1.0, 2.0, 3.0]);
+        for (scale, expected) in floats[3..].iter().zip([2.0, 1.0, 2.0]) {
+            assert!((scale - expected).abs() < 1e-6, "{:?}", floats);
+        }
+        // Colour from SH_C0, opacity through a sigmoid, and the unit quaternion around 128
+        assert_eq!(bytes[24..28], [255, 0, 128, 128]);
+        assert_eq!(bytes[28..], [128, 128, 128, 255]);
+    }
+
+    #[test]
+    fn sorts_by_volume_times_opacity() {
+        let splat = |scale: f32, opacity: f32| [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, opacity, scale, scale, scale, 1.0, 0.0, 0.0, 0.0];
+        // Largest first; the big faint splat outranks the small opaque one
+        let (_, bytes) = convert("sorted", &[splat(0.0, 4.0), splat(2.0, -2.0), splat(1.0, 0.0)], 0.01).unwrap();
+        let scales: Vec<f32> = bytes.chunks_exact(32).map(|r| f32::from_le_bytes(r[12..16].try_into().unwrap())).collect();
+        assert_eq!(scales.len(), 3);
+        assert!(scales[0] > scales[1] && scales[1] > scales[2], "{:?}", scales);
+    }
+
+    #[test]
+    fn prunes_faint_and_malformed_splats() {
+        let splat = |x: f32, opacity: f32, rotation: f32| [x, 0.0, 0.0, 0.0, 0.0, 0.0, opacity, 0.0, 0.0, 0.0, rotation, 0.0, 0.0, 0.0];
+        let vertices = [
+            splat(0.0, 0.0, 1.0),
+            splat(0.0, -10.0, 1.0),
+            splat(f32::NAN, 0.0, 1.0),
+            splat(f32::INFINITY, 0.0, 1.0),
+            splat(0.0, 0.0, 0.0),
+        ];
+        let (stats, bytes) = convert("pruned", &vertices, 0.01).unwrap();
+        assert_eq!((stats.splat_count, stats.pruned_count), (1, 4));
+        assert_eq!(stats.size, 32);
+        assert_eq!(bytes.len(), 32);
+    }
+
+    #[test]
+    fn fails_when_every_splat_is_pruned() {
+        let faint = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -10.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+        let error = convert("empty", &[faint, faint], 0.01).unwrap_err();
+        assert!(error.downcast_ref::<InvalidModel>().is_some());
+        assert!(error.to_string().contains("every splat is below the minimum opacity"), "{}", error);
+    }
+}
@@ -0,0 +1,56 @@
+//! Transcoding to a web-safe format
+//!
+//! Browsers only decode a handful of codecs. Videos using anything else,
+//! such as HEVC or ProRes, get an H.264/AAC MP4 playback copy that every
+//! browser can play, while the uploaded file is kept as the master.
+
+use std::{ffi::OsString, path::Path};
+
+use super::{ffmpeg, preview, ProcessingResult};
+
+/// Video codecs all current browsers can decode
+const WEB_VIDEO_CODECS: &[&str] = &["h264", "vp8", "vp9", "av1"];
+
+/// Audio codecs all current browsers can decode
+const WEB_AUDIO_CODECS: &[&str] = &["aac", "mp3", "opus", "vorbis", "flac"];
+
+/// Whether a video needs a playback copy for browsers to play it
+///
+/// # Arguments
+/// * `codec` - Codec of the video track; an unknown codec is assumed unplayable
+/// * `audio_codec` - Codec of the audio track, or `None` if there is none
+pub fn needs_transcode(codec: Option<&str>, audio_codec: Option<&str>) -> bool {
+    !codec.is_some_and(|codec| WEB_VIDEO_CODECS.contains(&codec))
+        || audio_codec.is_some_and(|codec| !WEB_AUDIO_CODECS.contains(&codec))
+}
+
+/// Transcodes a video to H.264 Main profile and stereo AAC in a faststart MP4
+///
+/// The output is written through a temporary file and never left half-written.
+///
+/// # Arguments
+/// * `source` - Video file
+/// * `destination` - MP4 file to create or replace
+pub fn transcode(source: &Path, destination: &Path) -> ProcessingResult<()> {
+    let mut args: Vec<OsString> = vec![
+        "-i".into(), source.into(),
+        "-map".into(), "0:v:0".into(),
+        "-map".into(), "0:a:0?".into(),
+        // 4:2:0 chroma requires even dimensions
+        "-vf".into(), "scale=trunc(iw/2)*2:trunc(ih/2)*2".into(),
+        "-c:v".into(), "libx264".into(),
+        "-preset".into(), "medium".into(),
+        "-crf".into(), "20".into(),
+        "-profile:v".into(), "main".into(),
+        "-pix_fmt".into(), "yuv420p".into(),
+        "-c:a".into(), "aac".into(),
+        "-b:a".into(), "192k".into(),
+        "-ac".into(), "2".into(),
+        "-movflags".into(), "+faststart".into(),
+    ];
+
+    preview::replace_atomically(destination, |tmp| {
+        args.push(tmp.into());
+        ffmpeg::run(args)
+    })
+}
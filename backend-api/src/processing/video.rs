@@ -0,0 +1,129 @@
+//! Video probing
+//!
+//! Identifies the container of an uploaded video and reads its duration,
+//! dimensions, frame rate and codecs with the container-specific parsers in
+//! `mp4` and `matroska`.
+
+use serde::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+
+use crate::handlers::videos::VIDEO_FOLDER;
+use super::{container::Container, matroska, mp4, ProcessingResult};
+
+/// Path of a stored video
+pub fn video_path(filename: &str) -> PathBuf {
+    Path::new(VIDEO_FOLDER).join(filename)
+}
+
+/// Folder holding the files derived from a video, such as its poster
+///
+/// Named after the video without its extension, next to the video itself.
+pub fn artifact_dir(filename: &str) -> PathBuf {
+    Path::new(VIDEO_FOLDER).join(artifact_stem(filename))
+}
+
+/// Path of a derived file relative to the video folder, as stored on `Video`
+pub fn artifact_filename(filename: &str, artifact: &str) -> String {
+    format!("{}/{}", artifact_stem(filename), artifact)
+}
+
+fn artifact_stem(filename: &str) -> String {
+    Path::new(filename).file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// What a track carries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    #[default]
+    Other,
+}
+
+/// A track of a video file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    pub kind: TrackKind,
+    /// Codec name, e.g. `h264`, `vp9` or `aac`
+    pub codec: String,
+    /// Duration of the track in milliseconds
+    pub duration_ms: Option<u64>,
+    /// Display width of video tracks in pixels
+    pub width: Option<u32>,
+    /// Display height of video tracks in pixels
+    pub height: Option<u32>,
+    /// Average frames per second of video tracks
+    pub frame_rate: Option<f64>,
+}
+
+/// Duration and tracks read from a container
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Duration of the whole presentation in milliseconds
+    pub duration_ms: Option<u64>,
+    pub tracks: Vec<Track>,
+}
+
+/// Container, codec and playback information recorded on `Video`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VideoProbe {
+    /// Container detected from the file contents
+    pub container: Container,
+    /// Codec of the first video track
+    pub codec: Option<String>,
+    /// Codec of the first audio track
+    pub audio_codec: Option<String>,
+    /// Playback duration in milliseconds
+    pub duration_ms: Option<u64>,
+    /// Display width in pixels, with rotation applied
+    pub width: Option<u32>,
+    /// Display height in pixels, with rotation applied
+    pub height: Option<u32>,
+    /// Average frames per second
+    pub frame_rate: Option<f64>,
+    /// Average bitrate of the whole file in bits per second
+    pub bitrate: Option<u64>,
+}
+
+/// Probes a video file
+///
+/// # Returns
+/// `None` if the container is not recognised. An error is only returned when
+/// the file cannot be read; a container whose tracks cannot be parsed is
+/// reported without codec and playback information.
+pub fn probe(path: &Path) -> ProcessingResult<Option<VideoProbe>> {
+    let Some(container) = Container::sniff_file(path)? else {
+        return Ok(None);
+    };
+
+    let info = match container {
+        Container::Mp4 | Container::QuickTime => mp4::read(path),
+        Container::WebM | Container::Matroska => matroska::read(path),
+        _ => Ok(MediaInfo::default()),
+    };
+    let info = info.unwrap_or_else(|e| {
+        eprintln!("⚠️ Could not read tracks of {}: {}", path.display(), e);
+        MediaInfo::default()
+    });
+
+    let video = info.tracks.iter().find(|track| track.kind == TrackKind::Video);
+    let audio = info.tracks.iter().find(|track| track.kind == TrackKind::Audio);
+
+    let duration_ms = info.duration_ms
+        .or_else(|| info.tracks.iter().filter_map(|track| track.duration_ms).max())
+        .filter(|&ms| ms > 0);
+    let file_len = std::fs::metadata(path)?.len();
+
+    Ok(Some(VideoProbe {
+        container,
+        codec: video.map(|track| track.codec.clone()),
+        audio_codec: audio.map(|track| track.codec.clone()),
+        duration_ms,
+        width: video.and_then(|track| track.width),
+        height: video.and_then(|track| track.height),
+        frame_rate: video
+            .and_then(|track| track.frame_rate)
+            .map(|fps| (fps * 1000.0).round() / 1000.0),
+        bitrate: duration_ms.map(|ms| (file_len as u128 * 8 * 1000 / ms as u128) as u64),
+    }))
+}
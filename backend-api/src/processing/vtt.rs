@@ -0,0 +1,281 @@
+//! WebVTT and SRT handling
+//!
+//! Parses SRT and WebVTT caption files into cues, validates their timing
+//! and writes them back out as WebVTT, the only caption format browsers
+//! understand natively.
+
+use std::{fmt::Write as _, fs, path::Path};
+
+use super::{preview, ProcessingResult};
+
+/// A single timed text block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    /// Optional cue identifier
+    pub id: Option<String>,
+    /// Start time in milliseconds
+    pub start_ms: u64,
+    /// End time in milliseconds
+    pub end_ms: u64,
+    /// WebVTT cue settings such as `line:0 align:start`, if any
+    pub settings: Option<String>,
+    /// Cue payload; may span several lines
+    pub text: String,
+}
+
+/// Decodes a caption file as UTF-8, falling back to Latin-1, which older
+/// SRT files often use
+pub fn decode(data: &[u8]) -> String {
+    String::from_utf8(data.to_vec()).unwrap_or_else(|_| data.iter().map(|&b| b as char).collect())
+}
+
+/// Parses an SRT or WebVTT file
+///
+/// The format is detected from the `WEBVTT` signature. SRT-only markup such
+/// as `<font>` tags and `{\an8}` position codes is removed.
+///
+/// # Returns
+/// The cues in file order, or a message describing the first problem found
+pub fn parse(text: &str) -> Result<Vec<Cue>, String> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let is_vtt = text.starts_with("WEBVTT");
+
+    let mut cues = Vec::new();
+    let blocks = text.split("\n\n").map(|b| b.trim_matches('\n')).filter(|b| !b.trim().is_empty());
+    for (index, block) in blocks.enumerate() {
+        if is_vtt && (index == 0 || ["NOTE", "STYLE", "REGION"].iter().any(|k| block.starts_with(k))) {
+            continue;
+        }
+
+        let number = cues.len() + 1;
+        let mut lines = block.lines();
+        let first = lines.next().unwrap_or_default();
+        let (id, timing) = if first.contains("-->") {
+            (None, first)
+        } else {
+            let timing = lines.next().filter(|line| line.contains("-->"))
+                .ok_or_else(|| format!("cue {} has no timing line", number))?;
+            (Some(first.trim().to_string()).filter(|id| !id.is_empty()), timing)
+        };
+
+        let (start, rest) = timing.split_once("-->").unwrap_or_default();
+        let mut rest = rest.split_whitespace();
+        let start_ms = parse_timestamp(start.trim())
+            .ok_or_else(|| format!("cue {} has an invalid start time '{}'", number, start.trim()))?;
+        let end = rest.next().unwrap_or_default();
+        let end_ms = parse_timestamp(end)
+            .ok_or_else(|| format!("cue {} has an invalid end time '{}'", number, end))?;
+        let settings = if is_vtt {
+            Some(rest.collect::<Vec<_>>().join(" ")).filter(|s| !s.is_empty())
+        } else {
+            None
+        };
+
+        let text = lines
+            .map(|line| if is_vtt { line.to_string() } else { strip_srt_markup(line) })
+            .collect::<Vec<_>>()
+            .join("\n");
+        cues.push(Cue { id, start_ms, end_ms, settings, text });
+    }
+
+    Ok(cues)
+}
+
+/// Checks that cues are well-ordered and fit in a video
+///
+/// # Arguments
+/// * `cues` - Parsed cues
+/// * `duration_ms` - Duration of the video, if known
+pub fn validate(cues: &[Cue], duration_ms: Option<u64>) -> Result<(), String> {
+    if cues.is_empty() {
+        return Err("file contains no cues".to_string());
+    }
+
+    let mut previous_start = 0;
+    for (i, cue) in cues.iter().enumerate() {
+        let number = i + 1;
+        if cue.end_ms <= cue.start_ms {
+            return Err(format!(
+                "cue {} ends at {} but starts at {}",
+                number, timestamp(cue.end_ms), timestamp(cue.start_ms)
+            ));
+        }
+        if cue.start_ms < previous_start {
+            return Err(format!("cue {} starts before the cue preceding it", number));
+        }
+        if let Some(duration) = duration_ms.filter(|&duration| cue.start_ms >= duration) {
+            return Err(format!(
+                "cue {} starts at {}, after the video ends at {}",
+                number, timestamp(cue.start_ms), timestamp(duration)
+            ));
+        }
+        previous_start = cue.start_ms;
+    }
+
+    Ok(())
+}
+
+/// Writes cues as a WebVTT file
+pub fn write(cues: &[Cue]) -> String {
+    let mut vtt = String::from("WEBVTT\n");
+    for cue in cues {
+        vtt.push('\n');
+        if let Some(id) = cue.id.as_deref().filter(|id| !id.contains("-->")) {
+            vtt.push_str(id);
+            vtt.push('\n');
+        }
+        let _ = write!(vtt, "{} --> {}", timestamp(cue.start_ms), timestamp(cue.end_ms));
+        if let Some(settings) = &cue.settings {
+            let _ = write!(vtt, " {}", settings);
+        }
+        vtt.push('\n');
+        for line in cue.text.lines().filter(|line| !line.trim().is_empty()) {
+            vtt.push_str(&line.replace("-->", "->"));
+            vtt.push('\n');
+        }
+    }
+    vtt
+}
+
+/// Writes cues to a WebVTT file through a temporary file, creating its folder
+pub fn write_file(path: &Path, cues: &[Cue]) -> ProcessingResult<()> {
+    let vtt = write(cues);
+    preview::replace_atomically(path, |tmp| Ok(fs::write(tmp, vtt)?))
+}
+
+/// Formats milliseconds as a WebVTT timestamp
+pub fn timestamp(ms: u64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Parses `hh:mm:ss.mmm` or `mm:ss.mmm`, accepting the `,` SRT uses as decimal mark
+fn parse_timestamp(value: &str) -> Option<u64> {
+    let (clock, fraction) = value.split_once(['.', ',']).unwrap_or((value, "0"));
+    if fraction.is_empty() || fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis: u64 = format!("{:0<3}", fraction).parse().ok()?;
+
+    let parts: Vec<u64> = clock.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    // Checked, so absurd hour counts are rejected rather than wrapping around
+    hours.checked_mul(3_600_000)?.checked_add((minutes * 60 + seconds) * 1000 + millis)
+}
+
+/// Removes `<font>` tags and `{\...}` override codes, which WebVTT does not support
+fn strip_srt_markup(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find(['<', '{']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        let lower = tail.to_ascii_lowercase();
+        let end = if lower.starts_with("<font") || lower.starts_with("</font") {
+            tail.find('>')
+        } else if tail.starts_with("{\\") {
+            tail.find('}')
+        } else {
+            None
+        };
+        match end {
+            Some(end) => rest = &tail[end + 1..],
+            None => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<font color=\"#ffff00\">Hello</font>\r\n\r\n\
+        2\r\n00:00:03,000 --> 00:00:04,000\r\n{\\an8}Top line\r\nsecond line\r\n";
+
+    #[test]
+    fn converts_srt_to_vtt() {
+        let cues = parse(SRT).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0], Cue { id: Some("1".into()), start_ms: 1000, end_ms: 2500, settings: None, text: "Hello".into() });
+        assert_eq!(cues[1].text, "Top line\nsecond line");
+        assert_eq!(
+            write(&cues),
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello\n\n2\n00:00:03.000 --> 00:00:04.000\nTop line\nsecond line\n"
+        );
+    }
+
+    #[test]
+    fn parses_vtt_settings_and_skips_metadata_blocks() {
+        let text = "\u{feff}WEBVTT - captions\n\nNOTE written by hand\n\nSTYLE\n::cue { color: red }\n\n\
+            00:01.000 --> 00:02.000 line:0 align:start\n<b>Bold</b> stays\n";
+        let cues = parse(text).unwrap();
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].id, None);
+        assert_eq!(cues[0].settings.as_deref(), Some("line:0 align:start"));
+        assert_eq!(cues[0].text, "<b>Bold</b> stays");
+    }
+
+    #[test]
+    fn falls_back_to_latin1() {
+        let latin1 = b"1\n00:00:01,000 --> 00:00:02,000\nCaf\xe9 cr\xe8me\n";
+        assert_eq!(parse(&decode(latin1)).unwrap()[0].text, "Café crème");
+        assert_eq!(decode("Café".as_bytes()), "Café");
+    }
+
+    #[test]
+    fn reports_invalid_timings() {
+        assert_eq!(parse("1\nno timing here\n").unwrap_err(), "cue 1 has no timing line");
+        assert_eq!(parse("00:00:01,000 --> 00:61:00,000\nx\n").unwrap_err(), "cue 1 has an invalid end time '00:61:00,000'");
+        assert_eq!(parse("1.5 --> 2\nx\n").unwrap_err(), "cue 1 has an invalid start time '1.5'");
+    }
+
+    #[test]
+    fn writes_chapter_tracks_that_parse_back() {
+        let chapters = vec![
+            Cue { id: None, start_ms: 0, end_ms: 65_000, settings: None, text: "Intro --> setup".into() },
+            Cue { id: Some("a --> b".into()), start_ms: 65_000, end_ms: 3_725_010, settings: None, text: "Part 2\n\n  \nend".into() },
+        ];
+        let vtt = write(&chapters);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n00:00:00.000 --> 00:01:05.000\nIntro -> setup\n\n00:01:05.000 --> 01:02:05.010\nPart 2\nend\n"
+        );
+
+        let parsed = parse(&vtt).unwrap();
+        assert_eq!(parsed.iter().map(|cue| (cue.start_ms, cue.end_ms)).collect::<Vec<_>>(), [(0, 65_000), (65_000, 3_725_010)]);
+        assert_eq!(parsed[1].text, "Part 2\nend");
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("01:02:03.456"), Some(3_723_456));
+        assert_eq!(parse_timestamp("02:03,4"), Some(123_400));
+        assert_eq!(parse_timestamp("00:00:01."), None);
+        assert_eq!(parse_timestamp("00:00:01.1234"), None);
+        assert_eq!(parse_timestamp("00:60.000"), None);
+        assert_eq!(parse_timestamp("5124095576030:00:00.000"), Some(5_124_095_576_030 * 3_600_000));
+        assert_eq!(parse_timestamp("5124095576031:00:00.000"), None);
+        assert_eq!(parse_timestamp("18446744073709551615:00:00.000"), None);
+    }
+
+    #[test]
+    fn validates_cue_order_and_duration() {
+        let cue = |start_ms, end_ms| Cue { id: None, start_ms, end_ms, settings: None, text: String::new() };
+        assert!(validate(&[cue(0, 1000), cue(1000, 2000)], Some(5000)).is_ok());
+        assert!(validate(&[], None).is_err());
+        assert!(validate(&[cue(1000, 1000)], None).is_err());
+        assert!(validate(&[cue(2000, 3000), cue(1000, 4000)], None).is_err());
+        assert!(validate(&[cue(6000, 7000)], Some(5000)).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Watermark rendering
+//!
+//! Turns the stored `WatermarkSettings` into a ready-to-apply mark and
+//! composites it onto photo derivatives.
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use image::{imageops, imageops::FilterType, Rgba, RgbaImage};
+use std::{env, fs, path::Path};
+
+use crate::handlers::watermark::WATERMARK_FOLDER;
+use crate::models::watermark::{WatermarkKind, WatermarkPosition, WatermarkSettings};
+use super::ProcessingResult;
+
+/// Font used for text watermarks when `WATERMARK_FONT` is not set
+const DEFAULT_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans-Bold.ttf";
+
+/// Reference size the text is laid out at before being scaled to the photo
+const TEXT_REFERENCE_PX: f32 = 128.0;
+
+enum Mark {
+    Text { font: FontVec, text: String },
+    Logo(RgbaImage),
+}
+
+/// A loaded watermark that can be stamped onto any number of photos
+pub struct Watermark {
+    mark: Mark,
+    position: WatermarkPosition,
+    opacity: f32,
+    scale: f32,
+    margin: f32,
+}
+
+impl Watermark {
+    /// Loads the font or logo referenced by the settings
+    ///
+    /// # Returns
+    /// * `Ok(None)` - Watermarking is disabled
+    /// * `Ok(Some(Watermark))` - Watermark ready to be applied
+    /// * `Err` - Font or logo could not be read
+    pub fn load(settings: &WatermarkSettings) -> ProcessingResult<Option<Self>> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+
+        let mark = match settings.kind {
+            WatermarkKind::Text => {
+                let font_path = env::var("WATERMARK_FONT").unwrap_or_else(|_| DEFAULT_FONT.to_string());
+                let font = FontVec::try_from_vec(fs::read(&font_path)?)
+                    .map_err(|e| format!("invalid font {}: {}", font_path, e))?;
+                Mark::Text { font, text: settings.text.clone() }
+            },
+            WatermarkKind::Logo => {
+                let logo_filename = settings.logo_filename.as_deref()
+                    .ok_or("logo watermark selected but no logo uploaded")?;
+                let logo = image::open(Path::new(WATERMARK_FOLDER).join(logo_filename))?;
+                Mark::Logo(logo.to_rgba8())
+            },
+        };
+
+        Ok(Some(Self {
+            mark,
+            position: settings.position,
+            opacity: settings.opacity,
+            scale: settings.scale,
+            margin: settings.margin,
+        }))
+    }
+
+    /// Composites the watermark onto the image in place
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let (width, height) = image.dimensions();
+        let target_width = ((width as f32 * self.scale).round() as u32).max(1);
+
+        let mut mark = match &self.mark {
+            Mark::Text { font, text } => render_text(font, text, target_width),
+            Mark::Logo(logo) => {
+                let target_height = ((logo.height() as f32 * target_width as f32
+                    / logo.width().max(1) as f32).round() as u32).max(1);
+                imageops::resize(logo, target_width, target_height, FilterType::Lanczos3)
+            },
+        };
+
+        if mark.width() > width || mark.height() > height {
+            let ratio = (width as f32 / mark.width() as f32).min(height as f32 / mark.height() as f32);
+            let (w, h) = (
+                ((mark.width() as f32 * ratio) as u32).max(1),
+                ((mark.height() as f32 * ratio) as u32).max(1),
+            );
+            mark = imageops::resize(&mark, w, h, FilterType::Lanczos3);
+        }
+
+        for pixel in mark.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * self.opacity).round() as u8;
+        }
+
+        let margin = (width as f32 * self.margin).round() as i64;
+        let (x, y) = match self.position {
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (width as i64 - mark.width() as i64 - margin, margin),
+            WatermarkPosition::BottomLeft => (margin, height as i64 - mark.height() as i64 - margin),
+            WatermarkPosition::BottomRight => (
+                width as i64 - mark.width() as i64 - margin,
+                height as i64 - mark.height() as i64 - margin,
+            ),
+            WatermarkPosition::Center => (
+                (width as i64 - mark.width() as i64) / 2,
+                (height as i64 - mark.height() as i64) / 2,
+            ),
+        };
+
+        imageops::overlay(image, &mark, x.max(0), y.max(0));
+    }
+}
+
+/// Rasterizes white text so that it is `target_width` pixels wide
+fn render_text(font: &FontVec, text: &str, target_width: u32) -> RgbaImage {
+    let reference_width = layout_width(font, text, TEXT_REFERENCE_PX).max(1.0);
+    let px = TEXT_REFERENCE_PX * target_width as f32 / reference_width;
+    let scaled = font.as_scaled(PxScale::from(px));
+
+    let width = layout_width(font, text, px).ceil().max(1.0) as u32;
+    let height = scaled.height().ceil().max(1.0) as u32;
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 0]));
+
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(px, point(caret, scaled.ascent()));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let x = bounds.min.x as i32 + gx as i32;
+                let y = bounds.min.y as i32 + gy as i32;
+                if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                    let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+                    pixel[3] = pixel[3].max((coverage * 255.0).round() as u8);
+                }
+            });
+        }
+    }
+
+    canvas
+}
+
+/// Horizontal advance of the whole string at the given pixel size
+fn layout_width(font: &FontVec, text: &str, px: f32) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(px));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
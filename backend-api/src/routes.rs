@@ -9,7 +9,7 @@
 
 use axum::{
     Router,
-    routing::{get, post, delete},
+    routing::{get, post, put, delete},
     extract::DefaultBodyLimit,
     http::header,
 };
@@ -18,7 +18,7 @@ use tower_http::{
     cors::CorsLayer,
 };
 use http::{HeaderValue, Method};
-use crate::handlers::{photos, models, videos, categories, stats};
+use crate::handlers::{photos, models, videos, categories, stats, watermark};
 use std::sync::Arc;
 use mongodb::Database;
 use crate::handlers::auth::login_handler;
@@ -42,7 +42,7 @@ pub fn create_routes(db: Arc<Database>) -> Router {
 
     let cors = CorsLayer::new()
         .allow_origin("*".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
         .allow_headers([
             header::CONTENT_TYPE,
             header::ACCEPT,
@@ -61,13 +61,33 @@ pub fn create_routes(db: Arc<Database>) -> Router {
         .route("/api/login", post(login_handler))
         .route("/api/categories", post(categories::create_category))
         .route("/api/categories", get(categories::list_categories))
+        .route("/api/categories/:id", get(categories::get_category).put(categories::update_category).delete(categories::delete_category))
+        .route("/api/categories/:id/watermark", put(categories::set_category_watermark))
+        .route("/api/watermark", get(watermark::get_watermark).put(watermark::update_watermark))
+        .route("/api/watermark/logo", post(watermark::upload_logo))
+        .route("/api/watermark/rerender", post(watermark::rerender_watermarks))
         .route("/api/photos/details", get(photos::get_photos))
+        .route("/api/photos/duplicates", get(photos::get_duplicate_report))
         .route("/api/models/details", get(models::get_models))
         .route("/api/videos/details", get(videos::get_videos))
         .route("/api/stats", get(stats::get_stats))
         .route("/api/models/:id", delete(models::delete_model))
+        .route("/api/models/:id/thumbnail", post(models::regenerate_thumbnail))
+        .route("/api/models/:id/optimize", post(models::regenerate_lods))
+        .route("/api/models/:id/viewer", put(models::update_model_viewer))
         .route("/api/photos/:id", delete(photos::delete_photo))
+        .route("/api/photos/:id/edits", put(photos::update_photo_edits).delete(photos::revert_photo_edits))
         .route("/api/videos/:id", delete(videos::delete_video))
+        .route("/api/videos/:id/stream", get(videos::stream_video))
+        .route("/api/videos/:id/poster", put(videos::set_poster_frame).post(videos::upload_poster))
+        .route("/api/videos/:id/captions", post(videos::upload_caption))
+        .route("/api/videos/:id/captions/:track_id", delete(videos::delete_caption))
+        .route("/api/videos/:id/clips", post(videos::create_clip))
+        .route("/api/videos/:id/chapters", put(videos::set_chapters))
+        .route("/api/videos/:id/preview", post(videos::regenerate_preview))
+        .route("/api/videos/:id/hls", post(videos::regenerate_hls))
+        .route("/api/videos/:id/transcode", post(videos::regenerate_transcode))
+        .route("/api/videos/hls/*path", get(videos::serve_hls))
         .nest_service("/static", ServeDir::new("static"))
         .nest_service("/public", ServeDir::new("static"))
         .layer(cors)